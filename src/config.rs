@@ -0,0 +1,228 @@
+use crate::domain::event::{EventType, Priority};
+use crate::queue::DEFAULT_LANE_WEIGHTS;
+use crate::service::{BreakerPolicy, OverloadPolicy, Priorities};
+use crate::store::RetentionPolicy;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Which `EventStore` backend the binary runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreBackend {
+    Memory,
+    File,
+    /// Requires the `sqlite` cargo feature (on by default).
+    Sqlite,
+}
+
+/// Runtime configuration, read from `EVENTS_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `EVENTS_STORE`: `memory` (default), `file` or `sqlite`.
+    pub store: StoreBackend,
+    /// `EVENTS_DATA_DIR`: directory for durable backends (default `./data`).
+    /// The SQLite backend keeps its database at `<data_dir>/events.db`.
+    pub data_dir: PathBuf,
+    /// `EVENTS_SNAPSHOT_INTERVAL_SECS`: how often the file store snapshots and
+    /// compacts its log (default 300; `0` disables).
+    pub snapshot_interval: Option<Duration>,
+    /// `EVENTS_REAPER_INTERVAL_SECS`: how often expired claim leases are
+    /// returned to the queue (default 30).
+    pub reaper_interval: Duration,
+    /// Which finished events to evict. `EVENTS_COMPLETED_TTL_SECS` (default
+    /// 86400) and `EVENTS_FAILED_TTL_SECS` (default 604800) set the per-status
+    /// TTLs, `EVENTS_MAX_RECORDS` caps the record count (default unlimited)
+    /// and `EVENTS_IDEMPOTENCY_WINDOW_SECS` (default 604800) how long ids of
+    /// evicted events are still deduplicated. `0` disables any of them.
+    pub retention: RetentionPolicy,
+    /// `EVENTS_RETENTION_INTERVAL_SECS`: how often the retention policy is
+    /// applied (default 60).
+    pub retention_interval: Duration,
+    /// `EVENTS_HANDLER_TIMEOUT_SECS`: how long a handler may run before the
+    /// attempt is abandoned as a retryable timeout, unless its registration
+    /// sets its own (default 30; `0` disables).
+    pub handler_timeout: Option<Duration>,
+    /// `EVENTS_SHUTDOWN_TIMEOUT_SECS`: how long shutdown waits for running
+    /// handlers before cancelling them (default 30).
+    pub shutdown_timeout: Duration,
+    /// `EVENTS_PARTITION_FIELD`: payload field whose value becomes the
+    /// partition key of events ingested without one (default `user_id`;
+    /// empty disables).
+    pub partition_field: Option<String>,
+    /// `EVENTS_PRIORITIES`: queue lane of events that arrive without a
+    /// priority, as `type=high|normal|low` pairs separated by commas, `*`
+    /// setting the default (default `user.login_failed=high`, everything
+    /// else normal).
+    pub priorities: Priorities,
+    /// `EVENTS_LANE_WEIGHTS`: relative share of the high, normal and low
+    /// lanes while all are busy, as three comma-separated numbers (default
+    /// `4,2,1`).
+    pub lane_weights: [u32; 3],
+    /// `EVENTS_QUEUE_CAPACITY`: how many ready events may wait in the queue
+    /// before ingest pushes back (default 10000; `0` is unbounded).
+    pub queue_capacity: Option<usize>,
+    /// What ingest does while the queue is full. `EVENTS_OVERLOAD_WAIT_MS`
+    /// sets how long it waits for room (default 1000; `0` fails fast) and
+    /// `EVENTS_RETRY_AFTER_SECS` the `Retry-After` sent with the 429
+    /// (default 1).
+    pub overload: OverloadPolicy,
+    /// `EVENTS_CONCURRENCY_LIMITS`: bulkheads, as `pattern=limit` pairs
+    /// separated by commas, e.g. `billing.*=2,user.login_failed=8`. Events
+    /// whose type matches a pattern are processed at most `limit` at a time,
+    /// all types matching one pattern sharing it (default none).
+    pub concurrency_limits: Vec<(String, usize)>,
+    /// Per-handler circuit breakers. One opens when at least
+    /// `EVENTS_BREAKER_MIN_CALLS` (default 10) of the last
+    /// `EVENTS_BREAKER_WINDOW` calls (default 20; `0` disables breakers)
+    /// were made and `EVENTS_BREAKER_FAILURE_RATE` of them (default 0.5)
+    /// failed, and probes again after `EVENTS_BREAKER_OPEN_SECS` (default
+    /// 30).
+    pub breaker: Option<BreakerPolicy>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            store: StoreBackend::Memory,
+            data_dir: PathBuf::from("data"),
+            snapshot_interval: Some(Duration::from_secs(300)),
+            reaper_interval: Duration::from_secs(30),
+            retention: RetentionPolicy {
+                completed_ttl: Some(Duration::from_secs(24 * 3600)),
+                failed_ttl: Some(Duration::from_secs(7 * 24 * 3600)),
+                max_records: None,
+                idempotency_window: Some(Duration::from_secs(7 * 24 * 3600)),
+            },
+            retention_interval: Duration::from_secs(60),
+            handler_timeout: Some(Duration::from_secs(30)),
+            shutdown_timeout: Duration::from_secs(30),
+            partition_field: Some("user_id".to_string()),
+            priorities: Priorities::new(Priority::Normal).with(EventType::UserLoginFailed, Priority::High),
+            lane_weights: DEFAULT_LANE_WEIGHTS,
+            queue_capacity: Some(10_000),
+            overload: OverloadPolicy::default(),
+            concurrency_limits: Vec::new(),
+            breaker: Some(BreakerPolicy::default()),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut cfg = Config::default();
+        if let Ok(v) = std::env::var("EVENTS_STORE") {
+            cfg.store = match v.to_ascii_lowercase().as_str() {
+                "memory" => StoreBackend::Memory,
+                "file" => StoreBackend::File,
+                "sqlite" => StoreBackend::Sqlite,
+                other => anyhow::bail!("unknown EVENTS_STORE backend: {other}"),
+            };
+        }
+        if let Ok(v) = std::env::var("EVENTS_DATA_DIR") {
+            cfg.data_dir = PathBuf::from(v);
+        }
+        if let Ok(v) = std::env::var("EVENTS_SNAPSHOT_INTERVAL_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_SNAPSHOT_INTERVAL_SECS: {e}"))?;
+            cfg.snapshot_interval = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Ok(v) = std::env::var("EVENTS_REAPER_INTERVAL_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_REAPER_INTERVAL_SECS: {e}"))?;
+            cfg.reaper_interval = Duration::from_secs(secs.max(1));
+        }
+        if let Some(ttl) = optional_secs("EVENTS_COMPLETED_TTL_SECS")? {
+            cfg.retention.completed_ttl = ttl;
+        }
+        if let Some(ttl) = optional_secs("EVENTS_FAILED_TTL_SECS")? {
+            cfg.retention.failed_ttl = ttl;
+        }
+        if let Ok(v) = std::env::var("EVENTS_MAX_RECORDS") {
+            let max: usize = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_MAX_RECORDS: {e}"))?;
+            cfg.retention.max_records = (max > 0).then_some(max);
+        }
+        if let Some(window) = optional_secs("EVENTS_IDEMPOTENCY_WINDOW_SECS")? {
+            cfg.retention.idempotency_window = window;
+        }
+        if let Ok(v) = std::env::var("EVENTS_RETENTION_INTERVAL_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_RETENTION_INTERVAL_SECS: {e}"))?;
+            cfg.retention_interval = Duration::from_secs(secs.max(1));
+        }
+        if let Some(timeout) = optional_secs("EVENTS_HANDLER_TIMEOUT_SECS")? {
+            cfg.handler_timeout = timeout;
+        }
+        if let Ok(v) = std::env::var("EVENTS_SHUTDOWN_TIMEOUT_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_SHUTDOWN_TIMEOUT_SECS: {e}"))?;
+            cfg.shutdown_timeout = Duration::from_secs(secs);
+        }
+        if let Ok(v) = std::env::var("EVENTS_PARTITION_FIELD") {
+            cfg.partition_field = (!v.is_empty()).then_some(v);
+        }
+        if let Ok(v) = std::env::var("EVENTS_QUEUE_CAPACITY") {
+            let capacity: usize = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_QUEUE_CAPACITY: {e}"))?;
+            cfg.queue_capacity = (capacity > 0).then_some(capacity);
+        }
+        if let Ok(v) = std::env::var("EVENTS_OVERLOAD_WAIT_MS") {
+            let ms: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_OVERLOAD_WAIT_MS: {e}"))?;
+            cfg.overload.max_wait = Duration::from_millis(ms);
+        }
+        if let Ok(v) = std::env::var("EVENTS_RETRY_AFTER_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_RETRY_AFTER_SECS: {e}"))?;
+            cfg.overload.retry_after = Duration::from_secs(secs.max(1));
+        }
+        if let Ok(v) = std::env::var("EVENTS_CONCURRENCY_LIMITS") {
+            cfg.concurrency_limits = parse_limits(&v).map_err(|e| anyhow::anyhow!("invalid EVENTS_CONCURRENCY_LIMITS: {e}"))?;
+        }
+        if let Ok(v) = std::env::var("EVENTS_PRIORITIES") {
+            cfg.priorities = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_PRIORITIES: {e}"))?;
+        }
+        if let Ok(v) = std::env::var("EVENTS_LANE_WEIGHTS") {
+            let weights: Vec<u32> = v.split(',').map(|w| w.trim().parse()).collect::<Result<_, _>>().map_err(|e| anyhow::anyhow!("invalid EVENTS_LANE_WEIGHTS: {e}"))?;
+            cfg.lane_weights = weights.try_into().map_err(|_| anyhow::anyhow!("invalid EVENTS_LANE_WEIGHTS: expected three weights"))?;
+        }
+        let mut breaker_enabled = cfg.breaker.is_some();
+        let mut breaker = cfg.breaker.take().unwrap_or_default();
+        if let Ok(v) = std::env::var("EVENTS_BREAKER_WINDOW") {
+            breaker.window = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_BREAKER_WINDOW: {e}"))?;
+            breaker_enabled = breaker.window > 0;
+        }
+        if let Ok(v) = std::env::var("EVENTS_BREAKER_MIN_CALLS") {
+            breaker.min_calls = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_BREAKER_MIN_CALLS: {e}"))?;
+        }
+        if let Ok(v) = std::env::var("EVENTS_BREAKER_FAILURE_RATE") {
+            let rate: f64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_BREAKER_FAILURE_RATE: {e}"))?;
+            if !(rate > 0.0 && rate <= 1.0) {
+                anyhow::bail!("invalid EVENTS_BREAKER_FAILURE_RATE: expected a rate above 0 and at most 1, got {rate}");
+            }
+            breaker.failure_rate = rate;
+        }
+        if let Ok(v) = std::env::var("EVENTS_BREAKER_OPEN_SECS") {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid EVENTS_BREAKER_OPEN_SECS: {e}"))?;
+            breaker.open_for = Duration::from_secs(secs.max(1));
+        }
+        cfg.breaker = breaker_enabled.then_some(breaker);
+        Ok(cfg)
+    }
+}
+
+/// Parse `pattern=limit` pairs separated by commas.
+fn parse_limits(s: &str) -> Result<Vec<(String, usize)>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (pattern, limit) = pair.split_once('=').ok_or_else(|| format!("expected pattern=limit, got {pair:?}"))?;
+            let limit = limit.trim().parse().map_err(|e| format!("{pair:?}: {e}"))?;
+            Ok((pattern.trim().to_string(), limit))
+        })
+        .collect()
+}
+
+/// Read a duration in seconds from `var`, where `0` means "disabled".
+/// Returns `None` if the variable is unset.
+fn optional_secs(var: &str) -> anyhow::Result<Option<Option<Duration>>> {
+    match std::env::var(var) {
+        Ok(v) => {
+            let secs: u64 = v.parse().map_err(|e| anyhow::anyhow!("invalid {var}: {e}"))?;
+            Ok(Some((secs > 0).then(|| Duration::from_secs(secs))))
+        }
+        Err(_) => Ok(None),
+    }
+}
@@ -1,10 +1,157 @@
+use crate::domain::state::EventStatus;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum DomainError {
     #[error("event not found")]
     NotFound,
 
-    #[error("invalid transition")]
-    InvalidTransition,
+    #[error("invalid transition from {from:?} to {to:?}")]
+    InvalidTransition { from: EventStatus, to: EventStatus },
+
+    #[error("version conflict: expected {expected}, found {actual}")]
+    VersionConflict { expected: u64, actual: u64 },
+}
+
+/// What an event handler reports when it cannot process an event. The
+/// variant decides whether and when the processor tries again.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HandlerError {
+    /// Worth retrying under the event type's `RetryPolicy`, e.g. a
+    /// connection reset.
+    #[error("{code}: {details}")]
+    Retryable { code: String, details: String },
+
+    /// Retrying cannot help, e.g. a malformed payload; the event goes
+    /// straight to the dead-letter queue.
+    #[error("{code}: {details}")]
+    Permanent { code: String, details: String },
+
+    /// Retry, but not before `after`, e.g. when a downstream service is
+    /// rate limiting.
+    #[error("{code}: {details} (retry after {after:?})")]
+    RetryAfter { after: Duration, code: String, details: String },
+
+    /// The handler ran longer than its timeout allows and was abandoned.
+    /// Retried like `Retryable`.
+    #[error("timeout: handler did not finish within {after:?}")]
+    Timeout { after: Duration },
+}
+
+impl HandlerError {
+    pub fn retryable(code: impl Into<String>, details: impl Into<String>) -> Self {
+        HandlerError::Retryable { code: code.into(), details: details.into() }
+    }
+
+    pub fn permanent(code: impl Into<String>, details: impl Into<String>) -> Self {
+        HandlerError::Permanent { code: code.into(), details: details.into() }
+    }
+
+    pub fn retry_after(after: Duration, code: impl Into<String>, details: impl Into<String>) -> Self {
+        HandlerError::RetryAfter { after, code: code.into(), details: details.into() }
+    }
+}
+
+/// How an `EventError` bears on retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Retryable,
+    Permanent,
+    RetryAfter,
+    Timeout,
+}
+
+/// The error stored on a record: a handler's `HandlerError`, or one raised
+/// by the service itself (an expired lease, an operator action).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventError {
+    pub kind: ErrorKind,
+    /// Short machine-readable identifier, e.g. `invalid_payload`.
+    pub code: String,
+    pub details: String,
+    /// The delay a `RetryAfter` error asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl EventError {
+    pub fn new(kind: ErrorKind, code: impl Into<String>, details: impl Into<String>) -> Self {
+        Self { kind, code: code.into(), details: details.into(), retry_after_ms: None }
+    }
+}
+
+impl std::fmt::Display for EventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.details)
+    }
+}
+
+impl From<HandlerError> for EventError {
+    fn from(e: HandlerError) -> Self {
+        match e {
+            HandlerError::Retryable { code, details } => EventError::new(ErrorKind::Retryable, code, details),
+            HandlerError::Permanent { code, details } => EventError::new(ErrorKind::Permanent, code, details),
+            HandlerError::RetryAfter { after, code, details } => {
+                EventError { retry_after_ms: Some(after.as_millis().try_into().unwrap_or(u64::MAX)), ..EventError::new(ErrorKind::RetryAfter, code, details) }
+            }
+            HandlerError::Timeout { after } => EventError::new(ErrorKind::Timeout, "timeout", format!("handler did not finish within {after:?}")),
+        }
+    }
+}
+
+/// A bare message, as stored before errors were structured, reads back as a
+/// retryable error with code `error`.
+impl From<String> for EventError {
+    fn from(details: String) -> Self {
+        EventError::new(ErrorKind::Retryable, "error", details)
+    }
+}
+
+impl From<&str> for EventError {
+    fn from(details: &str) -> Self {
+        details.to_string().into()
+    }
+}
+
+impl<'de> Deserialize<'de> for EventError {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Structured {
+            kind: ErrorKind,
+            code: String,
+            details: String,
+            #[serde(default)]
+            retry_after_ms: Option<u64>,
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Structured(Structured),
+            Legacy(String),
+        }
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Structured(s) => EventError { kind: s.kind, code: s.code, details: s.details, retry_after_ms: s.retry_after_ms },
+            Stored::Legacy(text) => text.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_errors_read_structured_and_legacy_forms() {
+        let err = EventError::from(HandlerError::retry_after(Duration::from_secs(2), "rate_limited", "try later"));
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json, serde_json::json!({"kind": "retry_after", "code": "rate_limited", "details": "try later", "retry_after_ms": 2000}));
+        assert_eq!(serde_json::from_value::<EventError>(json).unwrap(), err);
+
+        // records written before errors were structured hold a bare message
+        let legacy: EventError = serde_json::from_str(r#""connection reset""#).unwrap();
+        assert_eq!(legacy, EventError::new(ErrorKind::Retryable, "error", "connection reset"));
+    }
 }
@@ -1,12 +1,14 @@
+use crate::domain::error::{DomainError, ErrorKind, EventError};
 use crate::domain::state::EventStatus;
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::convert::TryFrom;
 
 /// Typed event type. Known variants can be listed here; unknown types are
 /// preserved in `Other` so the system remains forward-compatible.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum EventType {
     #[serde(rename = "user.login_failed")]
     UserLoginFailed,
@@ -37,12 +39,115 @@ impl TryFrom<String> for EventType {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EventPayload(pub Value);
 
+impl EventPayload {
+    /// Deserialize the payload into the struct for its event type.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.0)
+    }
+}
+
+/// Payload of a `user.login_failed` event.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserLoginFailed {
+    pub user_id: String,
+    /// Address the attempt came from, if known.
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// Why the login was refused, e.g. `bad_password`.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Consecutive failures for this user, as counted by the sender.
+    #[serde(default)]
+    pub failure_count: Option<u32>,
+}
+
+/// Which lane of the work queue an event waits in. Lanes are served with
+/// weighted fairness, so a busy `High` lane slows `Low` traffic down but
+/// never stops it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// Highest first, matching the lane order.
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    /// Stable name, matching the serde representation.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "high" => Ok(Priority::High),
+            "normal" => Ok(Priority::Normal),
+            "low" => Ok(Priority::Low),
+            other => Err(format!("unknown priority: {other}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub event_id: String,
     pub event_type: EventType,
     pub occurred_at: DateTime<Utc>,
     pub payload: EventPayload,
+    /// Events sharing a key are processed one at a time, in ingest order.
+    /// `None` puts no constraint on the event.
+    #[serde(default)]
+    pub partition_key: Option<String>,
+    /// Queue lane; `None` is `Priority::Normal`.
+    #[serde(default)]
+    pub priority: Option<Priority>,
+}
+
+impl Event {
+    /// Take the partition key from the payload field `field` unless one was
+    /// given. Only string and number values are used.
+    pub fn default_partition_key(&mut self, field: &str) {
+        if self.partition_key.is_some() {
+            return;
+        }
+        self.partition_key = match self.payload.0.get(field) {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        };
+    }
+}
+
+/// History detail of the change made by `EventRecord::replay`.
+pub const REPLAYED: &str = "replayed from dead-letter queue";
+
+/// Longest error or result text kept in a history entry, in characters.
+const SUMMARY_LIMIT: usize = 200;
+
+/// One entry in a record's status history.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatusChange {
+    /// `None` for the entry written when the record is created.
+    pub from: Option<EventStatus>,
+    pub to: EventStatus,
+    pub at: DateTime<Utc>,
+    /// The attempt this change belongs to (0 before the first claim).
+    pub attempt: u32,
+    /// Worker that made the change, if it came from a claim holder.
+    pub worker: Option<String>,
+    /// Error message or result summary, truncated to a few hundred bytes.
+    pub detail: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,10 +155,31 @@ pub struct EventRecord {
     pub event: Event,
     pub status: EventStatus,
     pub attempts: u32,
-    pub last_error: Option<String>,
+    /// The error of the latest failed attempt (or operator action).
+    pub last_error: Option<EventError>,
     pub result: Option<Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Incremented on every change, starting at 1. Callers pass the version
+    /// they last saw to mutations to detect concurrent edits.
+    #[serde(default)]
+    pub version: u64,
+    /// Worker holding the current claim, while `Processing`.
+    #[serde(default)]
+    pub lease_owner: Option<String>,
+    /// When the current claim lapses and the record may be reclaimed.
+    #[serde(default)]
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    /// Every status change, oldest first. Only ever appended to.
+    #[serde(default)]
+    pub history: Vec<StatusChange>,
+    /// Backoff chosen before the most recent retry, in milliseconds.
+    #[serde(default)]
+    pub retry_delay_ms: Option<u64>,
+    /// Earliest time the record may be claimed again, while a retry waits
+    /// out its delay. Cleared by every status change.
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
 }
 
 impl EventRecord {
@@ -67,6 +193,177 @@ impl EventRecord {
             result: None,
             created_at: now,
             updated_at: now,
+            version: 1,
+            lease_owner: None,
+            lease_expires_at: None,
+            history: vec![StatusChange { from: None, to: EventStatus::Received, at: now, attempt: 0, worker: None, detail: None }],
+            retry_delay_ms: None,
+            not_before: None,
+        }
+    }
+
+    /// A record for an event that should not be processed before `at`. An
+    /// `at` already past gives a plain `Received` record.
+    pub fn scheduled(event: Event, at: DateTime<Utc>) -> Self {
+        let mut rec = Self::new(event);
+        if at > rec.created_at {
+            rec.status = EventStatus::Scheduled;
+            rec.not_before = Some(at);
+            rec.history[0].to = EventStatus::Scheduled;
+            rec.history[0].detail = Some(format!("due at {}", at.to_rfc3339()));
+        }
+        rec
+    }
+
+    /// Move Received (or a due Scheduled) -> Processing, count the attempt and take a lease for
+    /// `owner` lasting `ttl`. Returns false if the record was not claimable,
+    /// including while it is not yet due (`not_before`).
+    pub fn claim(&mut self, owner: &str, ttl: std::time::Duration) -> bool {
+        let now = Utc::now();
+        if !self.status.can_transition(EventStatus::Processing) || self.not_before.is_some_and(|t| t > now) {
+            return false;
         }
+        self.history.push(StatusChange {
+            from: Some(self.status),
+            to: EventStatus::Processing,
+            at: now,
+            attempt: self.attempts.saturating_add(1),
+            worker: Some(owner.to_string()),
+            detail: None,
+        });
+        self.status = EventStatus::Processing;
+        self.attempts = self.attempts.saturating_add(1);
+        self.lease_owner = Some(owner.to_string());
+        let expires = chrono::Duration::from_std(ttl).ok().and_then(|d| now.checked_add_signed(d));
+        self.lease_expires_at = Some(expires.unwrap_or(now + chrono::Duration::days(36_500)));
+        self.not_before = None;
+        self.updated_at = now;
+        self.version += 1;
+        true
+    }
+
+    pub fn complete(&mut self, result: Value) -> Result<(), DomainError> {
+        self.transition(EventStatus::Completed, Some(result.to_string()))?;
+        self.result = Some(result);
+        Ok(())
+    }
+
+    pub fn fail(&mut self, err: EventError) -> Result<(), DomainError> {
+        self.transition(EventStatus::Failed, Some(err.to_string()))?;
+        self.last_error = Some(err);
+        Ok(())
+    }
+
+    /// Record an error and move back to `Received` for retry.
+    pub fn requeue(&mut self, err: EventError) -> Result<(), DomainError> {
+        self.transition(EventStatus::Received, Some(err.to_string()))?;
+        self.last_error = Some(err);
+        Ok(())
+    }
+
+    /// Like `requeue`, noting that the next attempt should wait `delay` and
+    /// holding off claims until then.
+    pub fn retry_later(&mut self, err: EventError, delay: std::time::Duration) -> Result<(), DomainError> {
+        self.requeue(err)?;
+        self.retry_delay_ms = Some(delay.as_millis().try_into().unwrap_or(u64::MAX));
+        self.not_before = Some(chrono::Duration::from_std(delay).ok().and_then(|d| self.updated_at.checked_add_signed(d)).unwrap_or(self.updated_at + chrono::Duration::days(36_500)));
+        Ok(())
+    }
+
+    /// Call off a `Scheduled` event before it is claimed.
+    pub fn cancel(&mut self) -> Result<(), DomainError> {
+        self.transition(EventStatus::Cancelled, Some("cancelled before it came due".to_string()))
+    }
+
+    /// When the current round of attempts began: at creation, at the first
+    /// claim of an event held `Scheduled` until it came due, or at the last
+    /// replay out of the dead-letter queue. Time spent waiting for
+    /// `process_after` does not count.
+    pub fn attempts_started_at(&self) -> DateTime<Utc> {
+        let began = |c: &&StatusChange| (c.attempt == 0 && c.to == EventStatus::Received) || (c.from == Some(EventStatus::Scheduled) && c.to == EventStatus::Processing);
+        self.history.iter().rev().find(began).map(|c| c.at).unwrap_or(self.created_at)
+    }
+
+    /// Take a dead-lettered record out of `Failed` and reset it for a fresh
+    /// round of attempts. Its earlier attempts stay in the history.
+    pub fn replay(&mut self) -> Result<(), DomainError> {
+        if self.status != EventStatus::Failed {
+            return Err(DomainError::InvalidTransition { from: self.status, to: EventStatus::Received });
+        }
+        self.attempts = 0;
+        self.apply(EventStatus::Received, Some(REPLAYED.to_string()));
+        self.last_error = None;
+        self.result = None;
+        self.retry_delay_ms = None;
+        Ok(())
+    }
+
+    /// Move to `next` if `EventStatus::can_transition` allows it, appending
+    /// to the history. Leaving `Processing` always gives up the lease.
+    fn transition(&mut self, next: EventStatus, detail: Option<String>) -> Result<(), DomainError> {
+        if !self.status.can_transition(next) {
+            return Err(DomainError::InvalidTransition { from: self.status, to: next });
+        }
+        self.apply(next, detail);
+        Ok(())
+    }
+
+    /// Record the move to `next` without checking that it is allowed.
+    fn apply(&mut self, next: EventStatus, detail: Option<String>) {
+        let now = Utc::now();
+        self.history.push(StatusChange {
+            from: Some(self.status),
+            to: next,
+            at: now,
+            attempt: self.attempts,
+            worker: self.lease_owner.take(),
+            detail: detail.map(summarize),
+        });
+        self.status = next;
+        self.lease_owner = None;
+        self.lease_expires_at = None;
+        self.not_before = None;
+        self.updated_at = now;
+        self.version += 1;
+    }
+
+    /// Compare-and-set guard: fail unless the record is still at `expected`
+    /// (`None` accepts any version).
+    pub fn expect_version(&self, expected: Option<u64>) -> Result<(), DomainError> {
+        match expected {
+            Some(expected) if expected != self.version => Err(DomainError::VersionConflict { expected, actual: self.version }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether this record is `Processing` under a lease that lapsed at or
+    /// before `cutoff` (any lease if `cutoff` is `None`). Claims without a
+    /// lease count as expired.
+    pub fn lease_expired(&self, cutoff: Option<DateTime<Utc>>) -> bool {
+        self.status == EventStatus::Processing
+            && match (cutoff, self.lease_expires_at) {
+                (Some(cutoff), Some(expires)) => expires <= cutoff,
+                _ => true,
+            }
+    }
+
+    /// Give an abandoned claim back: Processing -> Received, noting who lost it.
+    pub fn release_lease(&mut self) -> Result<(), DomainError> {
+        let err = lease_expired_error(self.lease_owner.as_deref());
+        self.requeue(err)
+    }
+}
+
+/// The `last_error` left on a record whose claim by `owner` lapsed.
+pub fn lease_expired_error(owner: Option<&str>) -> EventError {
+    EventError::new(ErrorKind::Retryable, "lease_expired", format!("lease held by {} expired", owner.unwrap_or("unknown worker")))
+}
+
+/// Cut `text` down to `SUMMARY_LIMIT` characters for a history entry.
+pub fn summarize(mut text: String) -> String {
+    if let Some((cut, _)) = text.char_indices().nth(SUMMARY_LIMIT) {
+        text.truncate(cut);
+        text.push('…');
     }
+    text
 }
@@ -1,14 +1,21 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EventStatus {
     Received,
     Processing,
     Completed,
     Failed,
+    /// Ingested with a `process_after` still in the future; claimable once
+    /// its `not_before` has passed.
+    Scheduled,
+    /// A `Scheduled` event called off before it came due.
+    Cancelled,
 }
 
 impl EventStatus {
+    pub const ALL: [EventStatus; 6] = [EventStatus::Received, EventStatus::Processing, EventStatus::Completed, EventStatus::Failed, EventStatus::Scheduled, EventStatus::Cancelled];
+
     pub fn can_transition(self, next: EventStatus) -> bool {
         match (self, next) {
             (EventStatus::Received, EventStatus::Processing) => true,
@@ -16,7 +23,43 @@ impl EventStatus {
             (EventStatus::Processing, EventStatus::Failed) => true,
             // allow requeue to Received if worker wants
             (EventStatus::Processing, EventStatus::Received) => true,
+            (EventStatus::Scheduled, EventStatus::Processing) => true,
+            (EventStatus::Scheduled, EventStatus::Cancelled) => true,
             _ => false,
         }
     }
+
+    /// `Completed`, `Failed` and `Cancelled` are final for workers. `Failed`
+    /// records form the dead-letter queue; only an operator replay moves them
+    /// back out.
+    pub fn is_terminal(self) -> bool {
+        matches!(self, EventStatus::Completed | EventStatus::Failed | EventStatus::Cancelled)
+    }
+
+    /// Stable name, matching the serde representation.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventStatus::Received => "Received",
+            EventStatus::Processing => "Processing",
+            EventStatus::Completed => "Completed",
+            EventStatus::Failed => "Failed",
+            EventStatus::Scheduled => "Scheduled",
+            EventStatus::Cancelled => "Cancelled",
+        }
+    }
+}
+
+impl std::str::FromStr for EventStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Received" => Ok(EventStatus::Received),
+            "Processing" => Ok(EventStatus::Processing),
+            "Completed" => Ok(EventStatus::Completed),
+            "Failed" => Ok(EventStatus::Failed),
+            "Scheduled" => Ok(EventStatus::Scheduled),
+            "Cancelled" => Ok(EventStatus::Cancelled),
+            other => Err(format!("unknown event status: {other}")),
+        }
+    }
 }
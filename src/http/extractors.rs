@@ -1,5 +1,6 @@
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -21,3 +22,40 @@ where
         Ok(RequestId(Uuid::new_v4().to_string()))
     }
 }
+
+/// Entity tag for a record version, as sent in `ETag` headers.
+pub fn etag(version: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{version}\"")).expect("a quoted integer is a valid header value")
+}
+
+/// The record version required by an `If-Match` header: `None` when the
+/// header is absent or `*`. Tags are compared strongly, so a weak or
+/// malformed tag can never match and is rejected with 412 up front.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IfMatch(pub Option<u64>);
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(v) = parts.headers.get(header::IF_MATCH) else {
+            return Ok(IfMatch(None));
+        };
+        let v = v.to_str().map_err(|_| (StatusCode::BAD_REQUEST, "invalid If-Match header"))?.trim();
+        if v == "*" {
+            return Ok(IfMatch(None));
+        }
+        if v.contains(',') {
+            return Err((StatusCode::BAD_REQUEST, "If-Match with several entity tags is not supported"));
+        }
+        v.strip_prefix('"')
+            .and_then(|t| t.strip_suffix('"'))
+            .and_then(|t| t.parse::<u64>().ok())
+            .map(|version| IfMatch(Some(version)))
+            .ok_or((StatusCode::PRECONDITION_FAILED, "If-Match does not match any version"))
+    }
+}
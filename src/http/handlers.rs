@@ -1,38 +1,179 @@
-use crate::http::types::{EventIn, EventStatusOut};
-use crate::service::IngestService;
-use crate::store::MemoryStore;
+use crate::domain::event::EventRecord;
+use crate::http::extractors::{etag, IfMatch};
+use crate::http::types::{BreakerOut, DeadLetterPageOut, EventHistoryOut, EventIn, EventPageOut, EventStatusOut, ListEventsIn, ReplayIn, ReplayOut};
+use crate::service::{Breakers, DeadLetterQueue, IngestError, IngestService};
+use crate::store::{EventStore, MemoryStore, StoreError};
 use crate::Telemetry;
-use axum::{extract::Path, extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::Path, extract::Query, extract::State, http::header, http::StatusCode, response::IntoResponse, response::Response, Json};
 
-pub struct HttpState {
-    pub ingest: IngestService,
-    pub store: MemoryStore,
+pub struct HttpState<S = MemoryStore> {
+    pub ingest: IngestService<S>,
+    pub dlq: DeadLetterQueue<S>,
+    pub store: S,
     pub telemetry: Telemetry,
+    /// The processor's handler circuit breakers, for `GET /admin/breakers`.
+    pub breakers: Breakers,
 }
 
-pub async fn post_events(State(state): State<std::sync::Arc<HttpState>>, Json(payload): Json<EventIn>) -> impl IntoResponse {
+pub async fn post_events<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Json(payload): Json<EventIn>) -> impl IntoResponse {
+    let process_after = match payload.process_after(chrono::Utc::now()) {
+        Ok(at) => at,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
     let ev = payload.into_domain();
-    let (rec, inserted) = state.ingest.ingest(ev).await;
-    if inserted {
-        (StatusCode::ACCEPTED, Json(EventStatusOut::from(rec))).into_response()
-    } else {
-        (StatusCode::OK, Json(EventStatusOut::from(rec))).into_response()
+    let id = ev.event_id.clone();
+    match state.ingest.ingest_at(ev, process_after).await {
+        Ok((rec, true)) => record_response(StatusCode::ACCEPTED, rec),
+        Ok((rec, false)) => record_response(StatusCode::OK, rec),
+        Err(IngestError::Closed) => (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response(),
+        Err(e @ IngestError::Overloaded { retry_after }) => (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after_secs(retry_after))], e.to_string()).into_response(),
+        Err(e @ IngestError::Queue(_)) => {
+            tracing::error!(%e, "ingest failed");
+            (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, retry_after_secs(state.ingest.overload.retry_after))], e.to_string()).into_response()
+        }
+        // a duplicate of an event whose record has already been evicted
+        Err(IngestError::Store(StoreError::Evicted { status })) => (StatusCode::OK, Json(serde_json::json!({"event_id": id, "status": status.as_str(), "evicted": true}))).into_response(),
+        Err(e) => {
+            tracing::error!(%e, "ingest failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "store error").into_response()
+        }
     }
 }
 
-pub async fn get_event(State(state): State<std::sync::Arc<HttpState>>, Path(id): Path<String>) -> impl IntoResponse {
+pub async fn get_event<S: EventStore>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>) -> impl IntoResponse {
     match state.store.get(&id).await {
-        Ok(rec) => (StatusCode::OK, Json(EventStatusOut::from(rec))).into_response(),
-        Err(_) => (StatusCode::NOT_FOUND, "not found").into_response(),
+        Ok(rec) => record_response(StatusCode::OK, rec),
+        Err(e) => store_error_response(e),
     }
 }
 
-pub async fn healthz(State(state): State<std::sync::Arc<HttpState>>) -> impl IntoResponse {
+/// `POST /events/{id}/cancel`: call off a `Scheduled` event before it comes
+/// due. Honours `If-Match`.
+pub async fn cancel_event<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>, IfMatch(expected): IfMatch) -> impl IntoResponse {
+    match state.ingest.cancel(&id, expected).await {
+        Ok(rec) => record_response(StatusCode::OK, rec),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `GET /events`: filtered, cursor-paginated listing.
+pub async fn list_events<S: EventStore>(State(state): State<std::sync::Arc<HttpState<S>>>, Query(params): Query<ListEventsIn>) -> impl IntoResponse {
+    let query = match params.into_query() {
+        Ok(q) => q,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match state.store.list(&query).await {
+        Ok(page) => (StatusCode::OK, Json(EventPageOut::from(page))).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `GET /events/{id}/history`: every status change of an event, oldest first.
+pub async fn get_event_history<S: EventStore>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>) -> impl IntoResponse {
+    match state.store.get(&id).await {
+        Ok(rec) => {
+            let tag = etag(rec.version);
+            (StatusCode::OK, [(header::ETAG, tag)], Json(EventHistoryOut::from(rec))).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `POST /admin/events/{id}/requeue`: take an event from its worker and queue
+/// it again. Honours `If-Match`.
+pub async fn admin_requeue<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>, IfMatch(expected): IfMatch) -> impl IntoResponse {
+    match state.ingest.requeue(&id, expected).await {
+        Ok(rec) => record_response(StatusCode::OK, rec),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `POST /admin/events/{id}/fail`: mark an in-flight event failed. Honours
+/// `If-Match`.
+pub async fn admin_fail<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>, IfMatch(expected): IfMatch) -> impl IntoResponse {
+    match state.ingest.fail(&id, expected).await {
+        Ok(rec) => record_response(StatusCode::OK, rec),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `GET /admin/breakers`: each handler's circuit breaker state.
+pub async fn list_breakers<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>) -> impl IntoResponse {
+    Json(state.breakers.snapshot().into_iter().map(BreakerOut::from).collect::<Vec<_>>())
+}
+
+/// `GET /dlq`: dead-lettered events with payload, last error and history.
+/// Takes the `GET /events` filters; `status` is always `Failed`.
+pub async fn list_dlq<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Query(params): Query<ListEventsIn>) -> impl IntoResponse {
+    let query = match params.into_query() {
+        Ok(q) => q,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match state.dlq.list(&query).await {
+        Ok(page) => (StatusCode::OK, Json(DeadLetterPageOut::from(page))).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `POST /dlq/{id}/replay`: reset a dead-lettered event and queue it again.
+/// Honours `If-Match`.
+pub async fn replay_dlq<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>, IfMatch(expected): IfMatch) -> impl IntoResponse {
+    match state.dlq.replay(&id, expected).await {
+        Ok(rec) => record_response(StatusCode::OK, rec),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `POST /dlq/replay`: replay every dead-lettered event matching a filter.
+pub async fn replay_dlq_matching<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Json(filter): Json<ReplayIn>) -> impl IntoResponse {
+    let (query, max) = match filter.into_query() {
+        Ok(q) => q,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match state.dlq.replay_matching(&query, max).await {
+        Ok(replayed) => (StatusCode::OK, Json(ReplayOut { replayed })).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `DELETE /dlq/{id}`: drop a dead-lettered event. Honours `If-Match`.
+pub async fn purge_dlq<S: EventStore + Clone>(State(state): State<std::sync::Arc<HttpState<S>>>, Path(id): Path<String>, IfMatch(expected): IfMatch) -> impl IntoResponse {
+    match state.dlq.purge(&id, expected).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// `Retry-After` value for `delay`: whole seconds, rounded up, at least one.
+fn retry_after_secs(delay: std::time::Duration) -> String {
+    delay.as_millis().div_ceil(1000).max(1).to_string()
+}
+
+/// A record as JSON with its version as the `ETag`.
+fn record_response(status: StatusCode, rec: EventRecord) -> Response {
+    let tag = etag(rec.version);
+    (status, [(header::ETAG, tag)], Json(EventStatusOut::from(rec))).into_response()
+}
+
+fn store_error_response(e: StoreError) -> Response {
+    match e {
+        StoreError::NotFound => (StatusCode::NOT_FOUND, "not found").into_response(),
+        StoreError::Evicted { .. } => (StatusCode::GONE, e.to_string()).into_response(),
+        StoreError::InvalidTransition { .. } | StoreError::NotDeadLettered { .. } => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        StoreError::VersionConflict { .. } => (StatusCode::PRECONDITION_FAILED, e.to_string()).into_response(),
+        StoreError::Backend(_) => {
+            tracing::error!(%e, "store error");
+            (StatusCode::INTERNAL_SERVER_ERROR, "store error").into_response()
+        }
+    }
+}
+
+pub async fn healthz<S>(State(state): State<std::sync::Arc<HttpState<S>>>) -> impl IntoResponse {
     let q = state.telemetry.queue_depth.get();
     (StatusCode::OK, format!("ok - queue_depth={}", q))
 }
 
-pub async fn metrics(State(state): State<std::sync::Arc<HttpState>>) -> impl IntoResponse {
+pub async fn metrics<S>(State(state): State<std::sync::Arc<HttpState<S>>>) -> impl IntoResponse {
     let body = state.telemetry.gather();
     (StatusCode::OK, body)
 }
@@ -2,7 +2,8 @@ pub mod routes;
 pub mod handlers;
 pub mod types;
 pub mod extractors;
-pub mod tests;
+#[cfg(test)]
+mod tests;
 
 pub use routes::*;
 pub use handlers::*;
@@ -1,17 +1,27 @@
-use crate::http::handlers::{get_event, healthz, metrics, post_events, HttpState};
-use axum::{routing::get, routing::post, Router};
+use crate::http::handlers::{admin_fail, admin_requeue, cancel_event, get_event, get_event_history, healthz, list_breakers, list_dlq, list_events, metrics, post_events, purge_dlq, replay_dlq, replay_dlq_matching, HttpState};
+use crate::store::EventStore;
+use axum::{routing::delete, routing::get, routing::post, Router};
 
-pub fn router(state: std::sync::Arc<HttpState>) -> Router {
+pub fn router<S: EventStore + Clone>(state: std::sync::Arc<HttpState<S>>) -> Router {
     Router::new()
-        .route("/events", post(post_events))
-        .route("/events/:id", get(get_event))
-        .route("/healthz", get(healthz))
-        .route("/metrics", get(metrics))
+        .route("/events", post(post_events::<S>).get(list_events::<S>))
+        .route("/events/:id", get(get_event::<S>))
+        .route("/events/:id/history", get(get_event_history::<S>))
+        .route("/events/:id/cancel", post(cancel_event::<S>))
+        .route("/admin/events/:id/requeue", post(admin_requeue::<S>))
+        .route("/admin/events/:id/fail", post(admin_fail::<S>))
+        .route("/admin/breakers", get(list_breakers::<S>))
+        .route("/dlq", get(list_dlq::<S>))
+        .route("/dlq/replay", post(replay_dlq_matching::<S>))
+        .route("/dlq/:id", delete(purge_dlq::<S>))
+        .route("/dlq/:id/replay", post(replay_dlq::<S>))
+        .route("/healthz", get(healthz::<S>))
+        .route("/metrics", get(metrics::<S>))
         .with_state(state)
 }
 
 // The router is returned so the caller can run the server and control graceful
 // shutdown. Using the router directly avoids re-export mismatches for `Server`.
-pub fn build_router(state: std::sync::Arc<HttpState>) -> Router {
+pub fn build_router<S: EventStore + Clone>(state: std::sync::Arc<HttpState<S>>) -> Router {
     router(state)
 }
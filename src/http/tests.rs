@@ -1,46 +1,375 @@
-#[cfg(test)]
-mod tests {
-    use axum::extract::FromRequestParts;
-    use crate::http::extractors::RequestId;
-    use axum::http::Request;
-    use axum::http::header::HeaderName;
-    use axum::http::HeaderValue;
-    use std::sync::Arc;
-    use crate::store::MemoryStore;
-    use crate::telemetry::Telemetry;
-    use crate::service::IngestService;
-    use tokio::sync::mpsc;
-    use crate::http::handlers::get_event;
-    use axum::extract::State as AxState;
-    use axum::response::IntoResponse;
-
-    #[tokio::test]
-    async fn request_id_extractor_generates_uuid_when_missing() {
-        let req = Request::builder().uri("/").body(()).unwrap();
-        let (mut parts, _body) = req.into_parts();
-        let rid = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
-        assert!(!rid.0.is_empty());
+use axum::extract::FromRequestParts;
+use crate::http::extractors::RequestId;
+use axum::http::Request;
+use axum::http::header::HeaderName;
+use axum::http::HeaderValue;
+use std::sync::Arc;
+use crate::store::MemoryStore;
+use crate::telemetry::Telemetry;
+use crate::service::{DeadLetterQueue, IngestService};
+use crate::queue::MemoryQueue;
+use crate::http::handlers::get_event;
+use axum::extract::State as AxState;
+use axum::response::IntoResponse;
+
+#[tokio::test]
+async fn request_id_extractor_generates_uuid_when_missing() {
+    let req = Request::builder().uri("/").body(()).unwrap();
+    let (mut parts, _body) = req.into_parts();
+    let rid = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
+    assert!(!rid.0.is_empty());
+}
+
+#[tokio::test]
+async fn request_id_extractor_uses_header() {
+    let req = Request::builder().uri("/").body(()).unwrap();
+    let (mut parts, _body) = req.into_parts();
+    parts.headers.insert(HeaderName::from_static("x-request-id"), HeaderValue::from_static("testid"));
+    let rid = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
+    assert_eq!(rid.0, "testid");
+}
+
+#[tokio::test]
+async fn get_event_missing_returns_404() {
+    // build minimal HttpState
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+
+    let resp = get_event(AxState(state.clone()), axum::extract::Path("nope".to_string())).await.into_response();
+    assert_eq!(resp.status(), axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn if_match_extractor_parses_strong_tags() {
+    use crate::http::extractors::IfMatch;
+    async fn parse(v: Option<&'static str>) -> Result<IfMatch, axum::http::StatusCode> {
+        let mut req = Request::builder().uri("/");
+        if let Some(v) = v {
+            req = req.header("if-match", v);
+        }
+        let (mut parts, _body) = req.body(()).unwrap().into_parts();
+        IfMatch::from_request_parts(&mut parts, &()).await.map_err(|(status, _)| status)
+    }
+    assert_eq!(parse(None).await, Ok(IfMatch(None)));
+    assert_eq!(parse(Some("*")).await, Ok(IfMatch(None)));
+    assert_eq!(parse(Some("\"7\"")).await, Ok(IfMatch(Some(7))));
+    assert_eq!(parse(Some("W/\"7\"")).await, Err(axum::http::StatusCode::PRECONDITION_FAILED));
+    assert_eq!(parse(Some("\"1\", \"2\"")).await, Err(axum::http::StatusCode::BAD_REQUEST));
+}
+
+#[tokio::test]
+async fn admin_requeue_honours_if_match() {
+    use crate::http::extractors::IfMatch;
+    use crate::http::handlers::admin_requeue;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::store::EventStore;
+    use axum::http::{header, StatusCode};
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    store.insert_if_absent(event("a1")).await.unwrap();
+    store.claim_for_processing("a1", OWNER, LEASE).await.unwrap();
+
+    let resp = get_event(AxState(state.clone()), axum::extract::Path("a1".to_string())).await.into_response();
+    assert_eq!(resp.headers()[header::ETAG], "\"2\"");
+
+    let path = || axum::extract::Path("a1".to_string());
+    let resp = admin_requeue(AxState(state.clone()), path(), IfMatch(Some(1))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+    let resp = admin_requeue(AxState(state.clone()), path(), IfMatch(Some(2))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::ETAG], "\"3\"");
+
+    // already back in Received: nothing to take away from a worker
+    let resp = admin_requeue(AxState(state.clone()), path(), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn get_event_history_lists_changes() {
+    use crate::http::handlers::get_event_history;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::store::EventStore;
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    store.insert_if_absent(event("h1")).await.unwrap();
+    store.claim_for_processing("h1", OWNER, LEASE).await.unwrap();
+    store.set_error_and_mark_received("h1", "transient".into(), None).await.unwrap();
+
+    let resp = get_event_history(AxState(state.clone()), axum::extract::Path("h1".to_string())).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), 16_384).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let history = v["history"].as_array().unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[2]["from"], "Processing");
+    assert_eq!(history[2]["to"], "Received");
+    assert_eq!(history[2]["worker"], OWNER);
+    assert_eq!(history[2]["detail"], "error: transient");
+
+    let resp = get_event_history(AxState(state.clone()), axum::extract::Path("nope".to_string())).await.into_response();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn evicted_events_are_gone_but_still_deduplicated() {
+    use crate::http::handlers::post_events;
+    use crate::http::types::EventIn;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::store::{EventStore, RetentionPolicy};
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    store.insert_if_absent(event("g1")).await.unwrap();
+    store.claim_for_processing("g1", OWNER, LEASE).await.unwrap();
+    store.set_result("g1", serde_json::json!({}), None).await.unwrap();
+    let policy = RetentionPolicy { completed_ttl: Some(std::time::Duration::ZERO), ..Default::default() };
+    store.evict(&policy, chrono::Utc::now()).await.unwrap();
+
+    let resp = get_event(AxState(state.clone()), axum::extract::Path("g1".to_string())).await.into_response();
+    assert_eq!(resp.status(), StatusCode::GONE);
+
+    let ev_in = EventIn { event_id: "g1".to_string(), event_type: "user.login_failed".to_string(), occurred_at: chrono::Utc::now(), payload: serde_json::json!({}), process_after: None, delay_ms: None, partition_key: None, priority: None };
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), 16_384).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["status"], "Completed");
+    assert_eq!(v["evicted"], true);
+    assert_eq!(telemetry.events_deduped.get(), 1);
+}
+
+#[tokio::test]
+async fn list_events_filters_and_pages() {
+    use crate::http::handlers::list_events;
+    use crate::http::types::ListEventsIn;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::store::EventStore;
+    use axum::extract::Query;
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    for id in ["n1", "n2", "n3"] {
+        store.insert_if_absent(event(id)).await.unwrap();
     }
+    store.claim_for_processing("n2", OWNER, LEASE).await.unwrap();
 
-    #[tokio::test]
-    async fn request_id_extractor_uses_header() {
-        let req = Request::builder().uri("/").body(()).unwrap();
-        let (mut parts, _body) = req.into_parts();
-        parts.headers.insert(HeaderName::from_static("x-request-id"), HeaderValue::from_static("testid"));
-        let rid = RequestId::from_request_parts(&mut parts, &()).await.unwrap();
-        assert_eq!(rid.0, "testid");
+    async fn page(state: &Arc<crate::http::handlers::HttpState>, params: ListEventsIn) -> (StatusCode, serde_json::Value) {
+        let resp = list_events(AxState(state.clone()), Query(params)).await.into_response();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), 65_536).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
     }
 
-    #[tokio::test]
-    async fn get_event_missing_returns_404() {
-        // build minimal HttpState
-        let store = MemoryStore::new();
-        let telemetry = Telemetry::new();
-        let (tx, _rx) = mpsc::channel::<String>(8);
-        let ingest = IngestService::new(store.clone(), tx, telemetry.clone());
-        let state = Arc::new(crate::http::handlers::HttpState { ingest, store: store.clone(), telemetry: telemetry.clone() });
-
-        let resp = get_event(AxState(state.clone()), axum::extract::Path("nope".to_string())).await.into_response();
-        assert_eq!(resp.status(), axum::http::StatusCode::NOT_FOUND);
+    let (status, v) = page(&state, ListEventsIn { status: Some("Received".into()), limit: Some(1), ..Default::default() }).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["events"][0]["event_id"], "n1");
+    let cursor = v["next_cursor"].as_str().unwrap().to_string();
+    let (_, v) = page(&state, ListEventsIn { status: Some("Received".into()), limit: Some(1), cursor: Some(cursor), ..Default::default() }).await;
+    assert_eq!(v["events"][0]["event_id"], "n3");
+    assert!(v["next_cursor"].is_null());
+
+    let (status, _) = page(&state, ListEventsIn { status: Some("Bogus".into()), ..Default::default() }).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = page(&state, ListEventsIn { cursor: Some("not-a-cursor".into()), ..Default::default() }).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn dlq_lists_replays_and_purges() {
+    use crate::http::handlers::{list_dlq, purge_dlq, replay_dlq, replay_dlq_matching};
+    use crate::http::extractors::IfMatch;
+    use crate::http::types::{ListEventsIn, ReplayIn};
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::queue::WorkQueue;
+    use crate::store::EventStore;
+    use axum::extract::{Path, Query};
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    for id in ["x1", "x2", "x3", "ok"] {
+        store.insert_if_absent(event(id)).await.unwrap();
+        store.claim_for_processing(id, OWNER, LEASE).await.unwrap();
+        if id == "ok" {
+            store.set_result(id, serde_json::json!({}), None).await.unwrap();
+        } else {
+            store.set_failed(id, format!("{id} broke").into(), None).await.unwrap();
+        }
     }
+
+    let resp = list_dlq(AxState(state.clone()), Query(ListEventsIn::default())).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), 65_536).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let events = v["events"].as_array().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["last_error"]["details"], "x1 broke");
+    assert_eq!(events[0]["payload"], serde_json::json!({"user_id": "u1"}));
+    assert_eq!(events[0]["history"].as_array().unwrap().len(), 3);
+
+    // a stale If-Match is refused, the current version replays
+    let version = store.get("x1").await.unwrap().version;
+    let resp = replay_dlq(AxState(state.clone()), Path("x1".to_string()), IfMatch(Some(version - 1))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let resp = replay_dlq(AxState(state.clone()), Path("x1".to_string()), IfMatch(Some(version))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(queue.peek(10).await.unwrap(), vec!["x1".to_string()]);
+    let resp = replay_dlq(AxState(state.clone()), Path("ok".to_string()), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let resp = replay_dlq_matching(AxState(state.clone()), axum::Json(ReplayIn { limit: Some(1), ..Default::default() })).await.into_response();
+    let body = axum::body::to_bytes(resp.into_body(), 65_536).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["replayed"], serde_json::json!(["x2"]));
+
+    let resp = purge_dlq(AxState(state.clone()), Path("x3".to_string()), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = purge_dlq(AxState(state.clone()), Path("x3".to_string()), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = purge_dlq(AxState(state.clone()), Path("ok".to_string()), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn closed_ingest_answers_503() {
+    use crate::http::handlers::post_events;
+    use crate::http::types::EventIn;
+    use crate::store::EventStore;
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest: ingest.clone(), dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+
+    ingest.close();
+    let ev_in = EventIn { event_id: "c1".to_string(), event_type: "user.login_failed".to_string(), occurred_at: chrono::Utc::now(), payload: serde_json::json!({}), process_after: None, delay_ms: None, partition_key: None, priority: None };
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(matches!(store.get("c1").await, Err(crate::store::StoreError::NotFound)));
+}
+
+#[tokio::test]
+async fn full_queue_answers_429_with_retry_after() {
+    use crate::http::handlers::post_events;
+    use crate::http::types::EventIn;
+    use crate::service::OverloadPolicy;
+    use crate::store::EventStore;
+    use axum::http::{header, StatusCode};
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new().with_capacity(1);
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let overload = OverloadPolicy { max_wait: std::time::Duration::ZERO, retry_after: std::time::Duration::from_millis(2500) };
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone()).with_overload_policy(overload);
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+
+    let ev_in = |id: &str| EventIn { event_id: id.to_string(), event_type: "user.login_failed".to_string(), occurred_at: chrono::Utc::now(), payload: serde_json::json!({}), process_after: None, delay_ms: None, partition_key: None, priority: None };
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in("o1"))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in("o2"))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()[header::RETRY_AFTER], "3");
+    assert!(matches!(store.get("o2").await, Err(crate::store::StoreError::NotFound)));
+    // a duplicate needs no room
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in("o1"))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn scheduled_events_show_their_status_and_can_be_cancelled() {
+    use crate::http::handlers::{cancel_event, post_events};
+    use crate::http::extractors::IfMatch;
+    use crate::http::types::EventIn;
+    use crate::queue::WorkQueue;
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
+    let ev_in = |id: &str, process_after, delay_ms| EventIn { event_id: id.to_string(), event_type: "user.login_failed".to_string(), occurred_at: chrono::Utc::now(), payload: serde_json::json!({}), process_after, delay_ms, partition_key: None, priority: None };
+
+    let resp = post_events(AxState(state.clone()), axum::Json(ev_in("s1", None, Some(60_000)))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let body = axum::body::to_bytes(resp.into_body(), 16_384).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["status"], "Scheduled");
+    assert!(v["not_before"].is_string());
+
+    let both = ev_in("s2", Some(chrono::Utc::now()), Some(1));
+    let resp = post_events(AxState(state.clone()), axum::Json(both)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = cancel_event(AxState(state.clone()), Path("s1".to_string()), IfMatch(Some(1))).await.into_response();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), 16_384).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["status"], "Cancelled");
+    assert_eq!(queue.scheduled().await.unwrap(), 0);
+
+    let resp = cancel_event(AxState(state.clone()), Path("s1".to_string()), IfMatch(None)).await.into_response();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn admin_breakers_lists_each_handlers_state() {
+    use crate::http::handlers::list_breakers;
+    use crate::service::{BreakerPolicy, Breakers};
+    use std::time::Duration;
+
+    let store = MemoryStore::new();
+    let telemetry = Telemetry::new();
+    let queue = MemoryQueue::new();
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+    let ingest = IngestService::new(store.clone(), queue, telemetry.clone());
+    let breakers = Breakers::new(BreakerPolicy { window: 1, min_calls: 1, failure_rate: 1.0, open_for: Duration::from_secs(60), probes: 1 });
+    breakers.register("user.login_failed");
+    breakers.try_acquire("billing.*").unwrap().failed();
+    let state = Arc::new(crate::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers });
+
+    let resp = list_breakers(AxState(state)).await.into_response();
+    assert_eq!(resp.status(), axum::http::StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), 16_384).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v[0]["handler"], "billing.*");
+    assert_eq!(v[0]["state"], "open");
+    assert!(v[0]["retry_in_ms"].as_u64().unwrap() > 0);
+    assert_eq!(v[1], serde_json::json!({"handler": "user.login_failed", "state": "closed", "calls": 0, "failures": 0}));
 }
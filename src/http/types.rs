@@ -1,4 +1,6 @@
-use crate::domain::event::{EventPayload, EventType};
+use crate::domain::event::{EventPayload, EventType, Priority};
+use crate::domain::state::EventStatus;
+use crate::store::{Cursor, EventPage, EventQuery};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -9,6 +11,21 @@ pub struct EventIn {
     pub event_type: String,
     pub occurred_at: DateTime<Utc>,
     pub payload: Value,
+    /// Process no earlier than this; the event is `Scheduled` until then.
+    #[serde(default)]
+    pub process_after: Option<DateTime<Utc>>,
+    /// Like `process_after`, relative to now. At most one of the two may be
+    /// given.
+    #[serde(default)]
+    pub delay_ms: Option<u64>,
+    /// Events with the same key are processed one at a time, in order. If
+    /// absent, taken from the configured payload field.
+    #[serde(default)]
+    pub partition_key: Option<String>,
+    /// `high`, `normal` or `low`. If absent, the configured priority for the
+    /// event type.
+    #[serde(default)]
+    pub priority: Option<Priority>,
 }
 
 #[derive(Debug, Serialize)]
@@ -16,10 +33,180 @@ pub struct EventStatusOut {
     pub event_id: String,
     pub status: String,
     pub attempts: u32,
-    pub last_error: Option<String>,
+    pub last_error: Option<crate::domain::error::EventError>,
     pub result: Option<Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub version: u64,
+    /// Backoff chosen before the most recent retry.
+    pub retry_delay_ms: Option<u64>,
+    /// When a `Scheduled` event or a pending retry becomes due.
+    pub not_before: Option<DateTime<Utc>>,
+    pub partition_key: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+/// Query string of `GET /events`. `*_from` bounds are inclusive, `*_to`
+/// bounds exclusive; timestamps are RFC 3339.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListEventsIn {
+    pub status: Option<String>,
+    pub event_type: Option<String>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub occurred_from: Option<DateTime<Utc>>,
+    pub occurred_to: Option<DateTime<Utc>>,
+    pub min_attempts: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl ListEventsIn {
+    pub const DEFAULT_LIMIT: usize = 50;
+    pub const MAX_LIMIT: usize = 1000;
+
+    pub fn into_query(self) -> Result<EventQuery, String> {
+        Ok(EventQuery {
+            status: self.status.map(|s| s.parse::<EventStatus>()).transpose()?,
+            event_type: self.event_type.map(|s| EventType::try_from(s.clone()).unwrap_or(EventType::Other(s))),
+            created_from: self.created_from,
+            created_to: self.created_to,
+            occurred_from: self.occurred_from,
+            occurred_to: self.occurred_to,
+            min_attempts: self.min_attempts,
+            after: self.cursor.map(|c| c.parse::<Cursor>()).transpose()?,
+            limit: self.limit.unwrap_or(Self::DEFAULT_LIMIT).clamp(1, Self::MAX_LIMIT),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventPageOut {
+    pub events: Vec<EventStatusOut>,
+    pub next_cursor: Option<String>,
+}
+
+impl From<EventPage> for EventPageOut {
+    fn from(page: EventPage) -> Self {
+        Self { events: page.records.into_iter().map(EventStatusOut::from).collect(), next_cursor: page.next_cursor.map(|c| c.encode()) }
+    }
+}
+
+/// A dead-lettered event with everything needed to diagnose it.
+#[derive(Debug, Serialize)]
+pub struct DeadLetterOut {
+    pub event_id: String,
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    pub payload: Value,
+    pub attempts: u32,
+    pub last_error: Option<crate::domain::error::EventError>,
+    pub created_at: DateTime<Utc>,
+    /// When the event was dead-lettered.
+    pub updated_at: DateTime<Utc>,
+    pub version: u64,
+    pub history: Vec<crate::domain::event::StatusChange>,
+}
+
+impl From<crate::domain::event::EventRecord> for DeadLetterOut {
+    fn from(rec: crate::domain::event::EventRecord) -> Self {
+        Self {
+            event_id: rec.event.event_id,
+            event_type: rec.event.event_type.into(),
+            occurred_at: rec.event.occurred_at,
+            payload: rec.event.payload.0,
+            attempts: rec.attempts,
+            last_error: rec.last_error,
+            created_at: rec.created_at,
+            updated_at: rec.updated_at,
+            version: rec.version,
+            history: rec.history,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetterPageOut {
+    pub events: Vec<DeadLetterOut>,
+    pub next_cursor: Option<String>,
+}
+
+impl From<EventPage> for DeadLetterPageOut {
+    fn from(page: EventPage) -> Self {
+        Self { events: page.records.into_iter().map(DeadLetterOut::from).collect(), next_cursor: page.next_cursor.map(|c| c.encode()) }
+    }
+}
+
+/// Body of `POST /dlq/replay`: which dead letters to replay, with the same
+/// meaning as the `GET /events` filters. `limit` caps how many are replayed.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReplayIn {
+    pub event_type: Option<String>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub occurred_from: Option<DateTime<Utc>>,
+    pub occurred_to: Option<DateTime<Utc>>,
+    pub min_attempts: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+impl ReplayIn {
+    /// The query to page through matches with and how many to replay.
+    pub fn into_query(self) -> Result<(EventQuery, usize), String> {
+        let max = self.limit.unwrap_or(ListEventsIn::MAX_LIMIT);
+        let list = ListEventsIn {
+            event_type: self.event_type,
+            created_from: self.created_from,
+            created_to: self.created_to,
+            occurred_from: self.occurred_from,
+            occurred_to: self.occurred_to,
+            min_attempts: self.min_attempts,
+            limit: Some(ListEventsIn::DEFAULT_LIMIT),
+            ..Default::default()
+        };
+        Ok((list.into_query()?, max))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayOut {
+    pub replayed: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreakerOut {
+    pub handler: String,
+    /// `closed`, `open` or `half_open`.
+    pub state: &'static str,
+    pub calls: usize,
+    pub failures: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_ms: Option<u64>,
+}
+
+impl From<crate::service::BreakerStatus> for BreakerOut {
+    fn from(status: crate::service::BreakerStatus) -> Self {
+        Self {
+            handler: status.handler,
+            state: status.state.as_str(),
+            calls: status.calls,
+            failures: status.failures,
+            retry_in_ms: status.retry_in.map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventHistoryOut {
+    pub event_id: String,
+    pub history: Vec<crate::domain::event::StatusChange>,
+}
+
+impl From<crate::domain::event::EventRecord> for EventHistoryOut {
+    fn from(rec: crate::domain::event::EventRecord) -> Self {
+        Self { event_id: rec.event.event_id, history: rec.history }
+    }
 }
 
 impl From<crate::domain::event::EventRecord> for EventStatusOut {
@@ -32,14 +219,33 @@ impl From<crate::domain::event::EventRecord> for EventStatusOut {
             result: rec.result,
             created_at: rec.created_at,
             updated_at: rec.updated_at,
+            version: rec.version,
+            retry_delay_ms: rec.retry_delay_ms,
+            not_before: rec.not_before,
+            partition_key: rec.event.partition_key,
+            priority: rec.event.priority,
         }
     }
 }
 
 impl EventIn {
+    /// When the event should be processed, from `process_after` or
+    /// `delay_ms`; `None` means straight away.
+    pub fn process_after(&self, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, String> {
+        match (self.process_after, self.delay_ms) {
+            (Some(_), Some(_)) => Err("give either process_after or delay_ms, not both".to_string()),
+            (Some(at), None) => Ok(Some(at)),
+            (None, Some(ms)) => {
+                let delay = i64::try_from(ms).ok().map(chrono::Duration::milliseconds);
+                delay.and_then(|d| now.checked_add_signed(d)).map(Some).ok_or_else(|| format!("delay_ms {ms} is out of range"))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
     pub fn into_domain(self) -> crate::domain::event::Event {
         let s = self.event_type.clone();
         let et = EventType::try_from(s.clone()).unwrap_or(EventType::Other(s));
-        crate::domain::event::Event { event_id: self.event_id, event_type: et, occurred_at: self.occurred_at, payload: EventPayload(self.payload) }
+        crate::domain::event::Event { event_id: self.event_id, event_type: et, occurred_at: self.occurred_at, payload: EventPayload(self.payload), partition_key: self.partition_key, priority: self.priority }
     }
 }
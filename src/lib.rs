@@ -1,10 +1,13 @@
+pub mod config;
 pub mod domain;
+pub mod queue;
 pub mod store;
 pub mod service;
 pub mod telemetry;
 pub mod http;
 
 pub use domain::*;
+pub use queue::*;
 pub use store::*;
 pub use service::*;
 pub use telemetry::*;
@@ -5,14 +5,14 @@ use serde_json::json;
 
 use event_processing_service::telemetry::init_tracing;
 use event_processing_service::Telemetry;
-use event_processing_service::store::MemoryStore;
-use event_processing_service::service::{IngestService, run_processor_pool};
-use event_processing_service::domain::event::Event;
+use event_processing_service::config::{Config, StoreBackend};
+use event_processing_service::queue::MemoryQueue;
+use event_processing_service::store::{EventStore, FileStore, FileStoreOptions, MemoryStore};
+use event_processing_service::service::{Breakers, DeadLetterQueue, HandlerRegistry, InFlight, IngestService, refresh_dlq_depth, release_orphaned_claims, run_lease_reaper, run_processor_pool, run_retention, RetryPolicies, TypedEvent, TypedHandler};
+use event_processing_service::domain::error::HandlerError;
+use event_processing_service::domain::event::{Event, EventType, UserLoginFailed};
 use event_processing_service::http::handlers::HttpState;
-use event_processing_service::http::types::{EventIn, EventStatusOut};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use hyper::service::{make_service_fn, service_fn};
-use std::convert::Infallible;
+use event_processing_service::http::routes::build_router;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,121 +21,124 @@ async fn main() -> anyhow::Result<()> {
     info!(%addr, "starting background processor");
 
     let telemetry = Telemetry::new();
-    let store = MemoryStore::new();
-    let (tx, rx) = tokio::sync::mpsc::channel::<String>(100);
-    let ingest = IngestService::new(store.clone(), tx.clone(), telemetry.clone());
+    let config = Config::from_env()?;
+    let store: Arc<dyn EventStore> = match config.store {
+        StoreBackend::Memory => Arc::new(MemoryStore::new()),
+        StoreBackend::File => {
+            let opts = FileStoreOptions { snapshot_interval: config.snapshot_interval, ..Default::default() };
+            Arc::new(FileStore::open_with(&config.data_dir, opts).await?)
+        }
+        #[cfg(feature = "sqlite")]
+        StoreBackend::Sqlite => Arc::new(event_processing_service::store::SqliteStore::open(config.data_dir.join("events.db")).await?),
+        #[cfg(not(feature = "sqlite"))]
+        StoreBackend::Sqlite => anyhow::bail!("this build does not include the sqlite backend"),
+    };
+    let queue = MemoryQueue::new()
+        .with_depth_gauge(telemetry.queue_depth.clone())
+        .with_scheduled_gauge(telemetry.events_scheduled.clone())
+        .with_lane_weights(config.lane_weights)
+        .with_lane_metrics(telemetry.lane_depth.clone(), telemetry.lane_wait.clone());
+    let queue = match config.queue_capacity {
+        Some(capacity) => queue.with_capacity(capacity),
+        None => queue,
+    };
+    let queue = config
+        .concurrency_limits
+        .iter()
+        .fold(queue.with_bulkhead_metrics(telemetry.bulkhead_in_flight.clone(), telemetry.bulkhead_limit_hits.clone()), |queue, (pattern, limit)| queue.with_concurrency_limit(pattern.clone(), *limit));
+    // handlers still running when an operator takes their event away, or at
+    // the shutdown deadline, are cancelled through this
+    let in_flight = InFlight::new();
+    let ingest = IngestService::new(store.clone(), queue.clone(), telemetry.clone())
+        .with_in_flight(in_flight.clone())
+        .with_partition_field(config.partition_field.clone())
+        .with_priorities(config.priorities.clone())
+        .with_overload_policy(config.overload.clone());
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
 
     // example handler: echo payload unless payload contains {"fail": true}
+    // (retried) or {"fail": "permanent"} (dead-lettered straight away)
     let handler = |ev: Event| async move {
-        if ev.payload.0.get("fail").and_then(|v| v.as_bool()).unwrap_or(false) {
-            Err("simulated failure".to_string())
-        } else {
-            Ok(json!({"echo": ev.payload.0}).into())
+        match ev.payload.0.get("fail") {
+            Some(serde_json::Value::Bool(true)) => Err(HandlerError::retryable("simulated", "simulated failure")),
+            Some(serde_json::Value::String(s)) if s == "permanent" => Err(HandlerError::permanent("simulated", "simulated permanent failure")),
+            _ => Ok(json!({"echo": ev.payload.0})),
         }
     };
 
-    let shared_rx = std::sync::Arc::new(tokio::sync::Mutex::new(rx));
-    run_processor_pool(store.clone(), shared_rx, tx.clone(),
+    // login failures are decoded into their payload struct first; a payload
+    // without a `user_id` is dead-lettered as invalid
+    let login_failed = TypedHandler::new(|ev: TypedEvent<UserLoginFailed>| async move {
+        Ok(json!({"user_id": ev.payload.user_id, "failure_count": ev.payload.failure_count}))
+    });
+    // pause events whose handler keeps failing instead of burning their retries
+    let breakers = match &config.breaker {
+        Some(policy) => Breakers::new(policy.clone()).with_metrics(telemetry.breaker_state.clone(), telemetry.breaker_opened.clone(), telemetry.breaker_rejections.clone()),
+        None => Breakers::default(),
+    };
+    let handlers = HandlerRegistry::new()
+        .on(EventType::UserLoginFailed, login_failed)
+        .on("*", handler)
+        .timeout(config.handler_timeout)
+        .with_in_flight(in_flight)
+        .with_breakers(breakers.clone());
+
+    // no worker is running yet, so any claim still in the store is abandoned
+    let orphaned = release_orphaned_claims(&store, &telemetry).await?;
+    if orphaned > 0 {
+        info!(orphaned, "released claims left over from previous run");
+    }
+
+    let processor = run_processor_pool(store.clone(), queue.clone(),
     4, // worker count
-    5, telemetry.clone(), handler);
+    RetryPolicies::default(), telemetry.clone(), handlers);
+
+    // pick up work that was accepted before a restart
+    let recovered = ingest.enqueue_pending().await?;
+    if recovered > 0 {
+        info!(recovered, "re-enqueued pending events");
+    }
+    let dead_letters = refresh_dlq_depth(&store, &telemetry).await?;
+    if dead_letters > 0 {
+        info!(dead_letters, "dead-letter queue is not empty");
+    }
+    run_lease_reaper(store.clone(), queue.clone(), telemetry.clone(), config.reaper_interval);
+    run_retention(store.clone(), config.retention.clone(), telemetry.clone(), config.retention_interval);
 
     // build HTTP state
-    let http_state = Arc::new(HttpState { ingest: ingest.clone(), store: store.clone(), telemetry: telemetry.clone() });
+    let http_state = Arc::new(HttpState { ingest: ingest.clone(), dlq, store: store.clone(), telemetry: telemetry.clone(), breakers });
 
     // compile-time checks: ensure individual components are Send+Sync+'static.
     fn _assert_send_sync<T: Send + Sync + 'static>() {}
     _assert_send_sync::<HttpState>();
     _assert_send_sync::<event_processing_service::service::IngestService>();
     _assert_send_sync::<event_processing_service::store::MemoryStore>();
+    _assert_send_sync::<event_processing_service::store::FileStore>();
     _assert_send_sync::<event_processing_service::Telemetry>();
 
-    // println!("listening on {}", listener.local_addr().unwrap());
-
-        // run a small hyper service that forwards to our handlers without using axum
-        let make_svc = make_service_fn(move |_conn| {
-            let state = http_state.clone();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                    let state = state.clone();
-                    async move {
-                        // routing
-                        let path = req.uri().path().to_string();
-                        match (req.method(), path.as_str()) {
-                            // POST /events
-                            (&Method::POST, "/events") => {
-                                let bytes = match hyper::body::to_bytes(req.into_body()).await {
-                                    Ok(b) => b,
-                                    Err(e) => {
-                                        tracing::error!(%e, "body read error");
-                                        let mut resp = Response::new(Body::from("body read error"));
-                                        *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-                                        return Ok::<_, Infallible>(resp);
-                                    }
-                                };
-                                let evt_in: EventIn = match serde_json::from_slice(&bytes) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        tracing::error!(%e, "json parse error");
-                                        let mut resp = Response::new(Body::from("invalid json"));
-                                        *resp.status_mut() = StatusCode::BAD_REQUEST;
-                                        return Ok::<_, Infallible>(resp);
-                                    }
-                                };
-                                let ev = evt_in.into_domain();
-                                let (rec, inserted) = state.ingest.ingest(ev).await;
-                                let out = EventStatusOut::from(rec);
-                                let body = serde_json::to_vec(&out).unwrap_or_default();
-                                let mut resp = Response::new(Body::from(body));
-                                *resp.status_mut() = if inserted { StatusCode::ACCEPTED } else { StatusCode::OK };
-                                return Ok::<_, Infallible>(resp);
-                            }
-                            // GET /events/{id}
-                            (&Method::GET, p) if p.starts_with("/events/") => {
-                                let id = p.trim_start_matches("/events/");
-                                match state.store.get(id).await {
-                                    Ok(rec) => {
-                                        let out = EventStatusOut::from(rec);
-                                        let body = serde_json::to_vec(&out).unwrap_or_default();
-                                        let mut resp = Response::new(Body::from(body));
-                                        *resp.status_mut() = StatusCode::OK;
-                                        return Ok::<_, Infallible>(resp);
-                                    }
-                                    Err(_) => {
-                                        let mut resp = Response::new(Body::from("not found"));
-                                        *resp.status_mut() = StatusCode::NOT_FOUND;
-                                        return Ok::<_, Infallible>(resp);
-                                    }
-                                }
-                            }
-                            // healthz
-                            (&Method::GET, "/healthz") => {
-                                let q = state.telemetry.queue_depth.get();
-                                let body = format!("ok - queue_depth={}", q);
-                                return Ok::<_, Infallible>(Response::new(Body::from(body)));
-                            }
-                            // metrics
-                            (&Method::GET, "/metrics") => {
-                                let body = state.telemetry.gather();
-                                return Ok::<_, Infallible>(Response::new(Body::from(body)));
-                            }
-                            _ => {
-                                let mut resp = Response::new(Body::from("not found"));
-                                *resp.status_mut() = StatusCode::NOT_FOUND;
-                                return Ok::<_, Infallible>(resp);
-                            }
-                        }
-                    }
-                }))
-            }
-        });
-
-        let server = Server::bind(&addr).serve(make_svc).with_graceful_shutdown(async move {
-            tokio::signal::ctrl_c().await.ok();
-            info!("shutting down");
-        });
+    let app = build_router(http_state);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!(%addr, "listening");
+    // the server keeps running (answering ingest with 503) until the
+    // processor has drained
+    let (stop_serving, stopped) = tokio::sync::oneshot::channel::<()>();
+    let mut server = tokio::spawn(async move {
+        axum::serve(listener, app).with_graceful_shutdown(async move { stopped.await.ok(); }).await
+    });
+    tokio::select! {
+        res = &mut server => {
+            res??;
+            return Ok(());
+        }
+        _ = tokio::signal::ctrl_c() => {}
+    }
 
-        info!(%addr, "listening");
-        server.await?;
+    info!("shutting down");
+    ingest.close();
+    let report = processor.shutdown(config.shutdown_timeout).await;
+    info!(interrupted = report.interrupted, queued = report.queued, "processor stopped");
+    let _ = stop_serving.send(());
+    server.await??;
 
     Ok(())
 }
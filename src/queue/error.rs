@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    /// The queue was closed and accepts no more work.
+    #[error("queue is closed")]
+    Closed,
+
+    /// The queue is at capacity and turned new work away.
+    #[error("queue is full")]
+    Full,
+
+    /// An ack or nack named a delivery the queue no longer holds a lease
+    /// for, typically because the lease expired and the id was redelivered.
+    #[error("unknown delivery {0}")]
+    UnknownDelivery(u64),
+
+    /// The underlying queue backend failed.
+    #[error("queue backend error: {0}")]
+    Backend(String),
+}
@@ -0,0 +1,857 @@
+use crate::domain::event::Priority;
+use crate::queue::{Delivery, Job, QueueError, Scheduler, WorkQueue};
+use crate::service::registry::glob_match;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use prometheus::{Gauge, GaugeVec, HistogramVec, IntCounterVec};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// How much of the queue's attention each lane gets, highest priority first,
+/// unless set with `MemoryQueue::with_lane_weights`: while all lanes are
+/// busy, four high, two normal and one low priority id are handed out in
+/// every seven.
+pub const DEFAULT_LANE_WEIGHTS: [u32; 3] = [4, 2, 1];
+
+/// In-process `WorkQueue`. Idle workers wait on a `Notify` rather than on a
+/// lock; the state mutex is only held to push or pop, never across an await,
+/// so workers never queue up behind each other.
+///
+/// Ids with a partition key are handed out one key at a time, in the order
+/// they became ready whatever their lane: `dequeue` skips an id while another
+/// delivery of its key is outstanding or an earlier id of its key is still
+/// waiting, and a nacked delivery keeps its key until it has been redelivered
+/// and settled.
+/// Ids whose concurrency group is at its limit (see
+/// `with_concurrency_limit`) are skipped the same way, so they stay queued
+/// instead of tying up a worker. Skipping scans the ready ids in order, which
+/// is cheap as long as few of them are held up behind a busy key or group.
+#[derive(Clone, Default)]
+pub struct MemoryQueue {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    notify: Notify,
+    /// Wakes `offer` calls waiting for a full queue to drain.
+    room: Notify,
+}
+
+/// The id that has a partition key to itself, and the delivery it is out
+/// on; `None` while it waits to be redelivered.
+struct Holder {
+    id: String,
+    token: Option<u64>,
+}
+
+/// A ready job and when it became ready.
+struct Queued {
+    job: Job,
+    since: Instant,
+    /// Its place in the order jobs became ready.
+    seq: u64,
+    /// Already counted as held back by its bulkhead.
+    held: bool,
+}
+
+/// A handed-out job, until it is settled.
+struct Lease {
+    job: Job,
+    expires: Instant,
+    /// The bulkhead it counts against.
+    bulkhead: Option<String>,
+}
+
+/// The ready jobs, one lane per `Priority` (indexed in `Priority::ALL`
+/// order), served by smooth weighted round-robin: on every turn each lane
+/// with a job to give gains its weight in credit, and the lane with the most
+/// credit is served and pays back the weights of all the lanes that took
+/// part. Lanes get turns in proportion to their weights, evenly spread out.
+///
+/// Across lanes, the ready jobs of each partition key form a FIFO, and only
+/// the one at its head may be taken, so a later job of a key never overtakes
+/// an earlier one by sitting in a busier lane or a freer bulkhead.
+struct Lanes {
+    ready: [VecDeque<Queued>; 3],
+    weights: [u32; 3],
+    credit: [i64; 3],
+    next_seq: u64,
+    /// `seq`s of the ready jobs per partition key, in the order they may go.
+    by_key: HashMap<String, VecDeque<u64>>,
+}
+
+impl Default for Lanes {
+    fn default() -> Self {
+        Self { ready: Default::default(), weights: DEFAULT_LANE_WEIGHTS, credit: [0; 3], next_seq: 0, by_key: HashMap::new() }
+    }
+}
+
+/// Whether `q` is the next ready job of its key, or has none.
+fn heads_its_key(by_key: &HashMap<String, VecDeque<u64>>, q: &Queued) -> bool {
+    q.job.key.as_ref().is_none_or(|key| by_key.get(key).and_then(VecDeque::front) == Some(&q.seq))
+}
+
+impl Lanes {
+    /// Add a ready job behind its lane and its key, or with `first` ahead of
+    /// the other ready jobs of its key.
+    fn push(&mut self, job: Job, first: bool) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(key) = &job.key {
+            let seqs = self.by_key.entry(key.clone()).or_default();
+            if first {
+                seqs.push_front(seq);
+            } else {
+                seqs.push_back(seq);
+            }
+        }
+        self.ready[job.priority as usize].push_back(Queued { job, since: Instant::now(), seq, held: false });
+    }
+
+    fn len(&self) -> usize {
+        self.ready.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.iter().all(VecDeque::is_empty)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Queued> {
+        self.ready.iter().flatten()
+    }
+
+    /// Take the next job, considering in each lane only the first one that
+    /// heads its key and that `can_take` accepts.
+    fn pop(&mut self, mut can_take: impl FnMut(&mut Queued) -> bool) -> Option<Queued> {
+        let mut candidates = Vec::new();
+        let Self { ready, by_key, .. } = self;
+        for (lane, ready) in ready.iter_mut().enumerate() {
+            if let Some(pos) = ready.iter_mut().position(|q| heads_its_key(by_key, q) && can_take(q)) {
+                candidates.push((lane, pos));
+            }
+        }
+        let total: i64 = candidates.iter().map(|&(lane, _)| i64::from(self.weights[lane])).sum();
+        for &(lane, _) in &candidates {
+            self.credit[lane] += i64::from(self.weights[lane]);
+        }
+        // on a tie, the higher priority lane goes first
+        let &(lane, pos) = candidates.iter().max_by_key(|&&(lane, _)| (self.credit[lane], Reverse(lane)))?;
+        self.credit[lane] -= total;
+        let queued = self.ready[lane].remove(pos)?;
+        if let Some(key) = &queued.job.key {
+            if let Some(seqs) = self.by_key.get_mut(key) {
+                seqs.pop_front();
+                if seqs.is_empty() {
+                    self.by_key.remove(key);
+                }
+            }
+        }
+        Some(queued)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    lanes: Lanes,
+    /// Nacked ids waiting out their delay and ids enqueued for later.
+    scheduled: Scheduler,
+    /// The jobs behind the scheduled ids.
+    scheduled_jobs: HashMap<String, Job>,
+    /// Handed-out deliveries by token.
+    leased: HashMap<u64, Lease>,
+    holders: HashMap<String, Holder>,
+    next_token: u64,
+    closed: bool,
+    /// Most ready ids `offer` lets wait; scheduled ones are not counted.
+    capacity: Option<usize>,
+    /// `(pattern, limit)` per bulkhead, first match wins.
+    bulkheads: Vec<(String, usize)>,
+    /// Deliveries out per bulkhead.
+    in_flight: HashMap<String, usize>,
+    bulkhead_in_flight: Option<GaugeVec>,
+    bulkhead_hits: Option<IntCounterVec>,
+    depth: Option<Gauge>,
+    scheduled_gauge: Option<Gauge>,
+    lane_depth: Option<GaugeVec>,
+    lane_wait: Option<HistogramVec>,
+}
+
+/// Whether `job` may be handed out now, given who holds its key.
+fn is_free(holders: &HashMap<String, Holder>, job: &Job) -> bool {
+    match job.key.as_ref().and_then(|key| holders.get(key)) {
+        None => true,
+        // a retry coming back for the key it kept
+        Some(holder) => holder.id == job.id && holder.token.is_none(),
+    }
+}
+
+/// The bulkhead `job` counts against: the first whose pattern matches its
+/// group.
+fn bulkhead_of<'a>(bulkheads: &'a [(String, usize)], job: &Job) -> Option<&'a (String, usize)> {
+    let group = job.group.as_deref()?;
+    bulkheads.iter().find(|(pattern, _)| glob_match(pattern, group))
+}
+
+impl State {
+    fn promote_due(&mut self) {
+        for id in self.scheduled.pop_due(Utc::now()) {
+            let job = match self.scheduled_jobs.remove(&id) {
+                Some(job) => job,
+                None => Job::new(id),
+            };
+            self.ready(job);
+        }
+    }
+
+    /// Make `job` ready. A retry coming back for the key it kept goes ahead
+    /// of the ids that queued up for that key in the meantime.
+    fn ready(&mut self, job: Job) {
+        let returning = job.key.as_ref().and_then(|key| self.holders.get(key)).is_some_and(|h| h.id == job.id && h.token.is_none());
+        self.lanes.push(job, returning);
+    }
+
+    /// Add `job` to its lane, or schedule it if it is due later.
+    fn add(&mut self, job: Job) {
+        match job.at.filter(|at| *at > Utc::now()) {
+            Some(at) => self.schedule(job, at),
+            None => self.ready(job),
+        }
+        self.update_depth();
+    }
+
+    fn is_full(&self) -> bool {
+        // ids scheduled for later or backing off are not waiting for a worker
+        self.capacity.is_some_and(|cap| self.lanes.len() >= cap)
+    }
+
+    fn schedule(&mut self, job: Job, at: DateTime<Utc>) {
+        self.scheduled.schedule(job.id.clone(), at);
+        self.scheduled_jobs.insert(job.id.clone(), job);
+    }
+
+    /// Lease the next ready job that is free to go.
+    fn take(&mut self, lease: Duration) -> Option<Delivery> {
+        let (holders, bulkheads, in_flight, hits) = (&self.holders, &self.bulkheads, &self.in_flight, &self.bulkhead_hits);
+        let Queued { job, since, .. } = self.lanes.pop(|q| {
+            if !is_free(holders, &q.job) {
+                return false;
+            }
+            match bulkhead_of(bulkheads, &q.job) {
+                Some((name, limit)) if in_flight.get(name).is_some_and(|n| n >= limit) => {
+                    if !q.held {
+                        q.held = true;
+                        if let Some(hits) = hits {
+                            hits.with_label_values(&[name]).inc();
+                        }
+                    }
+                    false
+                }
+                _ => true,
+            }
+        })?;
+        if let Some(wait) = &self.lane_wait {
+            wait.with_label_values(&[job.priority.as_str()]).observe(since.elapsed().as_secs_f64());
+        }
+        let token = self.next_token;
+        self.next_token += 1;
+        if let Some(key) = &job.key {
+            self.holders.insert(key.clone(), Holder { id: job.id.clone(), token: Some(token) });
+        }
+        let bulkhead = bulkhead_of(&self.bulkheads, &job).map(|(name, _)| name.clone());
+        if let Some(name) = &bulkhead {
+            *self.in_flight.entry(name.clone()).or_default() += 1;
+            self.report_in_flight(name);
+        }
+        let delivery = Delivery { id: job.id.clone(), key: job.key.clone(), priority: job.priority, group: job.group.clone(), token };
+        self.leased.insert(token, Lease { job, expires: Instant::now() + lease, bulkhead });
+        Some(delivery)
+    }
+
+    /// Take the lease `token` back. With `keep_key` its key stays held for
+    /// the redelivery, otherwise it is freed for the next id.
+    fn settle(&mut self, token: u64, keep_key: bool) -> Option<Job> {
+        let Lease { job, bulkhead, .. } = self.leased.remove(&token)?;
+        if let Some(name) = bulkhead {
+            if let Some(n) = self.in_flight.get_mut(&name) {
+                *n = n.saturating_sub(1);
+            }
+            self.report_in_flight(&name);
+        }
+        if let Some(key) = &job.key {
+            if self.holders.get(key).is_some_and(|h| h.token == Some(token)) {
+                if keep_key {
+                    self.holders.insert(key.clone(), Holder { id: job.id.clone(), token: None });
+                } else {
+                    self.holders.remove(key);
+                }
+            }
+        }
+        Some(job)
+    }
+
+    fn report_in_flight(&self, bulkhead: &str) {
+        if let Some(gauges) = &self.bulkhead_in_flight {
+            gauges.with_label_values(&[bulkhead]).set(self.in_flight.get(bulkhead).copied().unwrap_or(0) as f64);
+        }
+    }
+
+    fn update_depth(&self) {
+        if let Some(gauge) = &self.depth {
+            gauge.set((self.lanes.len() + self.scheduled.len()) as f64);
+        }
+        if let Some(gauge) = &self.scheduled_gauge {
+            gauge.set(self.scheduled.len() as f64);
+        }
+        if let Some(gauges) = &self.lane_depth {
+            for (priority, ready) in Priority::ALL.iter().zip(&self.lanes.ready) {
+                gauges.with_label_values(&[priority.as_str()]).set(ready.len() as f64);
+            }
+        }
+    }
+}
+
+/// Wall-clock time `delay` from now, saturating far in the future.
+fn after(delay: Duration) -> DateTime<Utc> {
+    let now = Utc::now();
+    chrono::Duration::from_std(delay).ok().and_then(|d| now.checked_add_signed(d)).unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+impl MemoryQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `gauge` (normally `Telemetry::queue_depth`) equal to `len()`.
+    pub fn with_depth_gauge(self, gauge: Gauge) -> Self {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            state.depth = Some(gauge);
+            state.update_depth();
+        }
+        self
+    }
+
+    /// Keep `gauge` (normally `Telemetry::events_scheduled`) equal to
+    /// `scheduled()`.
+    pub fn with_scheduled_gauge(self, gauge: Gauge) -> Self {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            state.scheduled_gauge = Some(gauge);
+            state.update_depth();
+        }
+        self
+    }
+
+    /// Serve the high, normal and low priority lanes in proportion to
+    /// `weights` (see `DEFAULT_LANE_WEIGHTS`). A weight of `0` is taken as
+    /// `1`, so no lane is ever starved.
+    pub fn with_lane_weights(self, weights: [u32; 3]) -> Self {
+        self.state().lanes.weights = weights.map(|w| w.max(1));
+        self
+    }
+
+    /// Report the ready ids in each lane on `depth` and how long ids waited
+    /// in their lane on `wait` (normally `Telemetry::lane_depth` and
+    /// `Telemetry::lane_wait`), both labelled by `lane`.
+    pub fn with_lane_metrics(self, depth: GaugeVec, wait: HistogramVec) -> Self {
+        {
+            let mut state = self.state();
+            state.lane_depth = Some(depth);
+            state.lane_wait = Some(wait);
+            state.update_depth();
+        }
+        self
+    }
+
+    /// Let `offer` keep at most `capacity` ready ids waiting; ids scheduled
+    /// for later or backing off do not count. Ids from `enqueue_job`
+    /// (redeliveries, recovered work) are always accepted, even past it.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.state().capacity = Some(capacity);
+        self
+    }
+
+    /// Hand out at most `limit` ids at a time whose group (normally their
+    /// event type) matches `pattern`, e.g. `billing.*` or an exact type; all
+    /// groups matching one pattern share its limit. Ids over the limit stay
+    /// queued. A limit of `0` is taken as `1`.
+    pub fn with_concurrency_limit(self, pattern: impl Into<String>, limit: usize) -> Self {
+        self.state().bulkheads.push((pattern.into(), limit.max(1)));
+        self
+    }
+
+    /// Report deliveries out per bulkhead on `in_flight` and ids held back by
+    /// a full bulkhead on `hits` (normally `Telemetry::bulkhead_in_flight`
+    /// and `Telemetry::bulkhead_limit_hits`), both labelled by `group`, the
+    /// bulkhead's pattern.
+    pub fn with_bulkhead_metrics(self, in_flight: GaugeVec, hits: IntCounterVec) -> Self {
+        {
+            let mut state = self.state();
+            state.bulkhead_in_flight = Some(in_flight);
+            state.bulkhead_hits = Some(hits);
+        }
+        self
+    }
+
+    /// Every scheduled `(due_at, id)`, soonest first.
+    pub fn scheduled_entries(&self) -> Vec<(DateTime<Utc>, String)> {
+        self.state().scheduled.entries()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl WorkQueue for MemoryQueue {
+    async fn enqueue_job(&self, job: Job) -> Result<(), QueueError> {
+        let mut state = self.state();
+        if state.closed {
+            return Err(QueueError::Closed);
+        }
+        state.add(job);
+        // a waiting worker may need to wake earlier than it planned
+        self.inner.notify.notify_one();
+        Ok(())
+    }
+
+    async fn offer(&self, job: Job, wait: Duration) -> Result<(), QueueError> {
+        let deadline = Instant::now() + wait;
+        loop {
+            // register before looking, as in `dequeue`
+            let room = self.inner.room.notified();
+            tokio::pin!(room);
+            room.as_mut().enable();
+            {
+                let mut state = self.state();
+                if state.closed {
+                    return Err(QueueError::Closed);
+                }
+                if !state.is_full() {
+                    state.add(job);
+                    self.inner.notify.notify_one();
+                    return Ok(());
+                }
+            }
+            if tokio::time::timeout_at(deadline, room).await.is_err() {
+                return Err(QueueError::Full);
+            }
+        }
+    }
+
+    async fn cancel_scheduled(&self, id: &str) -> Result<bool, QueueError> {
+        let mut state = self.state();
+        let cancelled = state.scheduled.cancel(id).is_some();
+        if let Some(key) = state.scheduled_jobs.remove(id).and_then(|job| job.key) {
+            // a retry that kept its key will not come back for it
+            if state.holders.get(&key).is_some_and(|h| h.id == id && h.token.is_none()) {
+                state.holders.remove(&key);
+                self.inner.notify.notify_one();
+            }
+        }
+        state.update_depth();
+        Ok(cancelled)
+    }
+
+    async fn dequeue(&self, lease: Duration) -> Result<Option<Delivery>, QueueError> {
+        loop {
+            // register for wakeups before looking, so none is missed in between
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            let next_due = {
+                let mut state = self.state();
+                if state.closed {
+                    return Ok(None);
+                }
+                state.promote_due();
+                if let Some(delivery) = state.take(lease) {
+                    state.update_depth();
+                    self.inner.room.notify_waiters();
+                    if !state.lanes.is_empty() {
+                        // pass the wakeup on in case several ids became ready at once
+                        self.inner.notify.notify_one();
+                    }
+                    return Ok(Some(delivery));
+                }
+                state.scheduled.next_due()
+            };
+            match next_due {
+                Some(due) => {
+                    let wait = (due - Utc::now()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        _ = &mut notified => {}
+                        _ = tokio::time::sleep(wait) => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    async fn ack(&self, delivery: &Delivery) -> Result<(), QueueError> {
+        let mut state = self.state();
+        state.settle(delivery.token, false).ok_or(QueueError::UnknownDelivery(delivery.token))?;
+        // an id held back behind its key or bulkhead may be free to go
+        self.inner.notify.notify_one();
+        Ok(())
+    }
+
+    async fn nack(&self, delivery: &Delivery, delay: Duration) -> Result<(), QueueError> {
+        let mut state = self.state();
+        let job = state.settle(delivery.token, true).ok_or(QueueError::UnknownDelivery(delivery.token))?;
+        if delay.is_zero() {
+            state.ready(job);
+        } else {
+            state.schedule(job, after(delay));
+        }
+        state.update_depth();
+        // a waiting worker may need to wake earlier than it planned
+        self.inner.notify.notify_one();
+        Ok(())
+    }
+
+    async fn peek(&self, limit: usize) -> Result<Vec<String>, QueueError> {
+        let mut state = self.state();
+        state.promote_due();
+        Ok(state.lanes.iter().take(limit).map(|q| q.job.id.clone()).collect())
+    }
+
+    async fn len(&self) -> Result<usize, QueueError> {
+        let state = self.state();
+        Ok(state.lanes.len() + state.scheduled.len())
+    }
+
+    async fn scheduled(&self) -> Result<usize, QueueError> {
+        Ok(self.state().scheduled.len())
+    }
+
+    async fn release_expired(&self) -> Result<usize, QueueError> {
+        let mut state = self.state();
+        let now = Instant::now();
+        let expired: Vec<u64> = state.leased.iter().filter(|(_, lease)| lease.expires <= now).map(|(token, _)| *token).collect();
+        for token in &expired {
+            if let Some(job) = state.settle(*token, true) {
+                state.ready(job);
+                self.inner.notify.notify_one();
+            }
+        }
+        state.update_depth();
+        Ok(expired.len())
+    }
+
+    fn close(&self) {
+        self.state().closed = true;
+        self.inner.notify.notify_waiters();
+        self.inner.room.notify_waiters();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state().closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn delivers_in_order_and_tracks_depth() {
+        let gauge = Gauge::new("test_queue_depth", "test").unwrap();
+        let queue = MemoryQueue::new().with_depth_gauge(gauge.clone());
+        for id in ["a", "b", "c"] {
+            queue.enqueue(id.to_string()).await.unwrap();
+        }
+        assert_eq!(queue.peek(2).await.unwrap(), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(gauge.get() as i64, 3);
+
+        let a = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(a.id, "a");
+        assert_eq!(queue.len().await.unwrap(), 2);
+        assert_eq!(gauge.get() as i64, 2);
+        queue.ack(&a).await.unwrap();
+        assert_eq!(queue.ack(&a).await, Err(QueueError::UnknownDelivery(a.token)));
+
+        // a nacked id goes to the back
+        let b = queue.dequeue(LEASE).await.unwrap().unwrap();
+        queue.nack(&b, Duration::ZERO).await.unwrap();
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["c".to_string(), "b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn nacked_ids_wait_out_their_delay() {
+        let queue = MemoryQueue::new();
+        queue.enqueue("a".to_string()).await.unwrap();
+        let a = queue.dequeue(LEASE).await.unwrap().unwrap();
+        let start = Instant::now();
+        queue.nack(&a, Duration::from_millis(50)).await.unwrap();
+        assert_eq!(queue.len().await.unwrap(), 1);
+        assert!(queue.peek(1).await.unwrap().is_empty());
+
+        let again = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(again.id, "a");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn future_dated_ids_wait_and_can_be_cancelled() {
+        let gauge = Gauge::new("test_scheduled", "test").unwrap();
+        let queue = MemoryQueue::new().with_scheduled_gauge(gauge.clone());
+        let at = Utc::now() + chrono::Duration::milliseconds(50);
+        queue.enqueue_at("later".to_string(), at).await.unwrap();
+        queue.enqueue_at("never".to_string(), at).await.unwrap();
+        // already due: ready straight away
+        queue.enqueue_at("now".to_string(), Utc::now() - chrono::Duration::seconds(1)).await.unwrap();
+        assert_eq!((queue.len().await.unwrap(), queue.scheduled().await.unwrap()), (3, 2));
+        assert_eq!(gauge.get() as i64, 2);
+
+        assert!(queue.cancel_scheduled("never").await.unwrap());
+        assert!(!queue.cancel_scheduled("now").await.unwrap());
+        assert_eq!(queue.scheduled_entries(), vec![(at, "later".to_string())]);
+
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "now");
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "later");
+        assert!(Utc::now() >= at);
+        assert_eq!(queue.len().await.unwrap(), 0);
+        assert_eq!(gauge.get() as i64, 0);
+    }
+
+    #[tokio::test]
+    async fn one_delivery_per_partition_key_at_a_time() {
+        let queue = MemoryQueue::new();
+        let keyed = |id: &str, key: &str| Job { key: Some(key.to_string()), ..Job::new(id) };
+        queue.enqueue_job(keyed("u1-a", "u1")).await.unwrap();
+        queue.enqueue_job(keyed("u1-b", "u1")).await.unwrap();
+        queue.enqueue_job(keyed("u2-a", "u2")).await.unwrap();
+        queue.enqueue("free".to_string()).await.unwrap();
+
+        // u1-b waits behind u1-a; other keys and unkeyed ids pass it
+        let u1a = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!((u1a.id.as_str(), u1a.key.as_deref()), ("u1-a", Some("u1")));
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "u2-a");
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "free");
+        assert!(tokio::time::timeout(Duration::from_millis(30), queue.dequeue(LEASE)).await.is_err());
+
+        // a nacked delivery keeps the key through its delay
+        queue.nack(&u1a, Duration::from_millis(30)).await.unwrap();
+        let retry = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(retry.id, "u1-a");
+
+        // a duplicate of the holder waits its turn behind u1-b
+        queue.enqueue_job(keyed("u1-a", "u1")).await.unwrap();
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["u1-b".to_string(), "u1-a".to_string()]);
+        queue.ack(&retry).await.unwrap();
+        let u1b = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(u1b.id, "u1-b");
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.dequeue(LEASE).await.unwrap().unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        queue.ack(&u1b).await.unwrap();
+        assert_eq!(waiting.await.unwrap().id, "u1-a");
+    }
+
+    #[tokio::test]
+    async fn a_keys_ids_keep_their_order_across_lanes_and_bulkheads() {
+        let queue = MemoryQueue::new().with_concurrency_limit("slow", 1);
+        let job = |id: &str, priority: Priority, group: &str| Job { key: Some("u1".to_string()), priority, group: Some(group.to_string()), ..Job::new(id) };
+        // a later high-priority id of the key does not overtake an earlier
+        // low-priority one, though its lane is served first
+        queue.enqueue_job(job("u1-1", Priority::Low, "misc")).await.unwrap();
+        queue.enqueue_job(job("u1-2", Priority::High, "misc")).await.unwrap();
+        queue.enqueue_job(Job { priority: Priority::High, ..Job::new("other") }).await.unwrap();
+        let first = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(first.id, "other");
+        let first = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(first.id, "u1-1");
+        queue.ack(&first).await.unwrap();
+        let second = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(second.id, "u1-2");
+        queue.ack(&second).await.unwrap();
+
+        // nor does one in a free bulkhead overtake one held by a full bulkhead
+        queue.enqueue_job(Job { group: Some("slow".to_string()), ..Job::new("busy") }).await.unwrap();
+        let busy = queue.dequeue(LEASE).await.unwrap().unwrap();
+        queue.enqueue_job(job("u1-3", Priority::Normal, "slow")).await.unwrap();
+        queue.enqueue_job(job("u1-4", Priority::High, "misc")).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(30), queue.dequeue(LEASE)).await.is_err());
+        queue.ack(&busy).await.unwrap();
+        let third = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(third.id, "u1-3");
+        queue.ack(&third).await.unwrap();
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "u1-4");
+    }
+
+    #[tokio::test]
+    async fn lanes_are_served_by_weight_and_report_depth_and_wait() {
+        let depth = GaugeVec::new(prometheus::Opts::new("test_lane_depth", "test"), &["lane"]).unwrap();
+        let wait = HistogramVec::new(prometheus::HistogramOpts::new("test_lane_wait", "test"), &["lane"]).unwrap();
+        let queue = MemoryQueue::new().with_lane_metrics(depth.clone(), wait.clone());
+        for i in 0..10 {
+            for priority in [Priority::Low, Priority::Normal, Priority::High] {
+                queue.enqueue_job(Job { priority, ..Job::new(format!("{}-{i}", priority.as_str())) }).await.unwrap();
+            }
+        }
+        assert_eq!(depth.with_label_values(&["high"]).get() as i64, 10);
+        assert_eq!(queue.peek(1).await.unwrap(), vec!["high-0".to_string()]);
+
+        // 4:2:1 while every lane is busy, in order within each lane
+        let mut served = Vec::new();
+        for _ in 0..14 {
+            served.push(queue.dequeue(LEASE).await.unwrap().unwrap());
+        }
+        let count = |served: &[Delivery], p: Priority| served.iter().filter(|d| d.priority == p).count();
+        assert_eq!((count(&served, Priority::High), count(&served, Priority::Normal), count(&served, Priority::Low)), (8, 4, 2));
+        assert!(served[..7].iter().any(|d| d.priority == Priority::Low), "low lane was starved");
+        let lows: Vec<_> = served.iter().filter(|d| d.priority == Priority::Low).map(|d| d.id.as_str()).collect();
+        assert_eq!(lows, ["low-0", "low-1"]);
+
+        // the high lane runs dry and the rest share its turns
+        for _ in 0..16 {
+            served.push(queue.dequeue(LEASE).await.unwrap().unwrap());
+        }
+        assert_eq!(count(&served, Priority::High), 10);
+        assert_eq!(depth.with_label_values(&["high"]).get() as i64, 0);
+        assert_eq!(wait.with_label_values(&["low"]).get_sample_count(), count(&served, Priority::Low) as u64);
+
+        // a zero weight still gets a turn
+        let queue = MemoryQueue::new().with_lane_weights([1, 1, 0]);
+        for priority in [Priority::Low, Priority::High, Priority::High] {
+            queue.enqueue_job(Job { priority, ..Job::new(priority.as_str()) }).await.unwrap();
+        }
+        let mut priorities = Vec::new();
+        for _ in 0..3 {
+            priorities.push(queue.dequeue(LEASE).await.unwrap().unwrap().priority);
+        }
+        assert_eq!(priorities, [Priority::High, Priority::Low, Priority::High]);
+    }
+
+    #[tokio::test]
+    async fn offer_waits_for_room_up_to_its_deadline() {
+        let queue = MemoryQueue::new().with_capacity(2);
+        queue.offer(Job::new("a"), Duration::ZERO).await.unwrap();
+        queue.offer(Job::new("b"), Duration::ZERO).await.unwrap();
+        assert_eq!(queue.offer(Job::new("c"), Duration::from_millis(20)).await, Err(QueueError::Full));
+        // redeliveries and recovered work are never refused
+        queue.enqueue("d".to_string()).await.unwrap();
+        assert_eq!(queue.len().await.unwrap(), 3);
+
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.offer(Job::new("e"), Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        queue.dequeue(LEASE).await.unwrap().unwrap();
+        queue.dequeue(LEASE).await.unwrap().unwrap();
+        waiting.await.unwrap().unwrap();
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["d".to_string(), "e".to_string()]);
+
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.offer(Job::new("f"), Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue.close();
+        assert_eq!(waiting.await.unwrap(), Err(QueueError::Closed));
+    }
+
+    #[tokio::test]
+    async fn scheduled_and_backing_off_ids_leave_room_for_ready_ones() {
+        let queue = MemoryQueue::new().with_capacity(1);
+        let later = Utc::now() + chrono::Duration::hours(1);
+        queue.offer(Job { at: Some(later), ..Job::new("s1") }, Duration::ZERO).await.unwrap();
+        queue.offer(Job::new("r1"), Duration::ZERO).await.unwrap();
+        let r1 = queue.dequeue(LEASE).await.unwrap().unwrap();
+        queue.nack(&r1, Duration::from_secs(3600)).await.unwrap();
+        assert_eq!(queue.scheduled().await.unwrap(), 2);
+
+        // neither the future-dated ingest nor the retry backing off counts
+        queue.offer(Job::new("r2"), Duration::ZERO).await.unwrap();
+        assert_eq!(queue.offer(Job::new("r3"), Duration::ZERO).await, Err(QueueError::Full));
+    }
+
+    #[tokio::test]
+    async fn bulkheads_cap_deliveries_per_group_and_count_held_ids() {
+        let in_flight = GaugeVec::new(prometheus::Opts::new("test_bulkhead_in_flight", "test"), &["group"]).unwrap();
+        let hits = IntCounterVec::new(prometheus::Opts::new("test_bulkhead_hits", "test"), &["group"]).unwrap();
+        let queue = MemoryQueue::new().with_concurrency_limit("billing.*", 1).with_bulkhead_metrics(in_flight.clone(), hits.clone());
+        let job = |id: &str, group: &str| Job { group: Some(group.to_string()), ..Job::new(id) };
+        queue.enqueue_job(job("b1", "billing.invoice")).await.unwrap();
+        queue.enqueue_job(job("b2", "billing.refund")).await.unwrap();
+        queue.enqueue_job(job("o1", "user.login_failed")).await.unwrap();
+
+        // b2 shares b1's bulkhead and waits; o1 is not limited
+        let b1 = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(b1.id, "b1");
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "o1");
+        assert!(tokio::time::timeout(Duration::from_millis(30), queue.dequeue(LEASE)).await.is_err());
+        assert_eq!(in_flight.with_label_values(&["billing.*"]).get() as i64, 1);
+        // counted once however often it is passed over
+        assert_eq!(hits.with_label_values(&["billing.*"]).get(), 1);
+
+        // a retry waiting out its delay does not hold a slot
+        queue.nack(&b1, Duration::from_secs(60)).await.unwrap();
+        let b2 = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(b2.id, "b2");
+        queue.ack(&b2).await.unwrap();
+        assert_eq!(in_flight.with_label_values(&["billing.*"]).get() as i64, 0);
+    }
+
+    #[tokio::test]
+    async fn expired_leases_are_redelivered() {
+        let queue = MemoryQueue::new();
+        queue.enqueue("a".to_string()).await.unwrap();
+        let first = queue.dequeue(Duration::from_millis(1)).await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(queue.release_expired().await.unwrap(), 1);
+        let second = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(second.id, "a");
+        assert_ne!(second.token, first.token);
+        // the first worker's lease is gone
+        assert!(queue.ack(&first).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_workers_each_get_distinct_ids_and_close_wakes_them() {
+        let queue = MemoryQueue::new();
+        let mut workers = Vec::new();
+        for _ in 0..4 {
+            let queue = queue.clone();
+            workers.push(tokio::spawn(async move {
+                let mut got = Vec::new();
+                while let Some(d) = queue.dequeue(LEASE).await.unwrap() {
+                    queue.ack(&d).await.unwrap();
+                    got.push(d.id);
+                }
+                got
+            }));
+        }
+        for i in 0..100 {
+            queue.enqueue(format!("e{i}")).await.unwrap();
+        }
+        while queue.len().await.unwrap() > 0 {
+            tokio::task::yield_now().await;
+        }
+        queue.close();
+        let mut all = Vec::new();
+        for w in workers {
+            all.extend(w.await.unwrap());
+        }
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), 100);
+        assert_eq!(queue.enqueue("late".to_string()).await, Err(QueueError::Closed));
+    }
+}
@@ -0,0 +1,9 @@
+mod error;
+mod memory;
+mod scheduler;
+mod work_queue;
+
+pub use error::QueueError;
+pub use memory::{MemoryQueue, DEFAULT_LANE_WEIGHTS};
+pub use scheduler::Scheduler;
+pub use work_queue::{Delivery, Job, WorkQueue};
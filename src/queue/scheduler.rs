@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Ids waiting for a point in time: retries waiting out their backoff and
+/// events dated for later processing. Entries are `(due_at, id)` in a binary
+/// heap, soonest first, with at most one live entry per id.
+///
+/// Rescheduling or cancelling an id leaves its old heap entry behind; such
+/// stale entries are skipped when they surface and swept out once they
+/// outnumber the live ones. Due times are wall-clock, so `entries` can be
+/// persisted and collected back into a `Scheduler` after a restart.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    heap: BinaryHeap<Reverse<(DateTime<Utc>, String)>>,
+    /// The live due time of every scheduled id.
+    due: HashMap<String, DateTime<Utc>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedule `id` for `at`, replacing any schedule it already had.
+    pub fn schedule(&mut self, id: String, at: DateTime<Utc>) {
+        self.due.insert(id.clone(), at);
+        self.heap.push(Reverse((at, id)));
+        self.compact();
+    }
+
+    /// Drop `id`'s schedule. Returns when it was due, if it was scheduled.
+    pub fn cancel(&mut self, id: &str) -> Option<DateTime<Utc>> {
+        let at = self.due.remove(id);
+        self.compact();
+        at
+    }
+
+    pub fn due_at(&self, id: &str) -> Option<DateTime<Utc>> {
+        self.due.get(id).copied()
+    }
+
+    /// Number of scheduled ids.
+    pub fn len(&self) -> usize {
+        self.due.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.due.is_empty()
+    }
+
+    /// When the soonest entry is due.
+    pub fn next_due(&mut self) -> Option<DateTime<Utc>> {
+        self.skip_stale();
+        self.heap.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Remove and return every id due at or before `now`, soonest first.
+    pub fn pop_due(&mut self, now: DateTime<Utc>) -> Vec<String> {
+        let mut ids = Vec::new();
+        while self.next_due().is_some_and(|at| at <= now) {
+            if let Some(Reverse((_, id))) = self.heap.pop() {
+                self.due.remove(&id);
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Every scheduled `(due_at, id)`, soonest first.
+    pub fn entries(&self) -> Vec<(DateTime<Utc>, String)> {
+        let mut entries: Vec<_> = self.due.iter().map(|(id, at)| (*at, id.clone())).collect();
+        entries.sort();
+        entries
+    }
+
+    fn is_live(&self, at: &DateTime<Utc>, id: &str) -> bool {
+        self.due.get(id) == Some(at)
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(Reverse((at, id))) = self.heap.peek() {
+            if self.is_live(at, id) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+
+    fn compact(&mut self) {
+        if self.heap.len() > 2 * self.due.len() + 16 {
+            self.heap = self.due.iter().map(|(id, at)| Reverse((*at, id.clone()))).collect();
+        }
+    }
+}
+
+impl FromIterator<(DateTime<Utc>, String)> for Scheduler {
+    fn from_iter<I: IntoIterator<Item = (DateTime<Utc>, String)>>(entries: I) -> Self {
+        let mut scheduler = Self::new();
+        for (at, id) in entries {
+            scheduler.schedule(id, at);
+        }
+        scheduler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn pops_due_ids_in_order_and_honours_reschedules_and_cancels() {
+        let now = Utc::now();
+        let mut s = Scheduler::new();
+        s.schedule("late".to_string(), now + Duration::seconds(60));
+        s.schedule("b".to_string(), now + Duration::seconds(2));
+        s.schedule("a".to_string(), now + Duration::seconds(1));
+        s.schedule("gone".to_string(), now);
+        assert_eq!(s.cancel("gone"), Some(now));
+        // moved from a minute out to before "b"
+        s.schedule("late".to_string(), now + Duration::milliseconds(1500));
+        assert_eq!(s.len(), 3);
+        assert_eq!(s.next_due(), Some(now + Duration::seconds(1)));
+
+        assert!(s.pop_due(now).is_empty());
+        assert_eq!(s.pop_due(now + Duration::seconds(5)), vec!["a", "late", "b"]);
+        assert!(s.is_empty());
+        assert_eq!(s.next_due(), None);
+    }
+
+    #[test]
+    fn entries_round_trip_and_stale_entries_are_swept() {
+        let now = Utc::now();
+        let mut s = Scheduler::new();
+        for i in 0..100 {
+            s.schedule("x".to_string(), now + Duration::seconds(i));
+        }
+        s.schedule("y".to_string(), now);
+        assert!(s.heap.len() <= 2 * s.len() + 16);
+
+        let restored: Scheduler = s.entries().into_iter().collect();
+        assert_eq!(restored.entries(), vec![(now, "y".to_string()), (now + Duration::seconds(99), "x".to_string())]);
+        assert_eq!(restored.due_at("x"), Some(now + Duration::seconds(99)));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::domain::event::{EventRecord, Priority};
+use crate::queue::QueueError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An event id to enqueue, with what the queue orders it by.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Job {
+    pub id: String,
+    /// Partition key; see `WorkQueue`.
+    pub key: Option<String>,
+    /// The lane the id waits in.
+    pub priority: Priority,
+    /// Concurrency group, normally the event type; queues may cap how many
+    /// ids of a group are out at once, and the processor finds the handler's
+    /// circuit breaker by it.
+    pub group: Option<String>,
+    /// Hand out no earlier than this; `None` or a time already past makes
+    /// the id ready at once.
+    pub at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    /// A job for `id` in the normal lane, without a key, ready now.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into(), ..Self::default() }
+    }
+
+    /// The job for `rec`: its partition key, priority and event type as
+    /// group, due at its `not_before`.
+    pub fn for_record(rec: &EventRecord) -> Self {
+        Self {
+            id: rec.event.event_id.clone(),
+            key: rec.event.partition_key.clone(),
+            priority: rec.event.priority.unwrap_or_default(),
+            group: Some(String::from(rec.event.event_type.clone())),
+            at: rec.not_before,
+        }
+    }
+}
+
+/// One event id handed out by `WorkQueue::dequeue`, leased to the caller
+/// until it is acked or nacked, or the lease runs out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub id: String,
+    /// The partition key the id was enqueued with.
+    pub key: Option<String>,
+    pub priority: Priority,
+    /// The concurrency group the id was enqueued with, normally its event
+    /// type.
+    pub group: Option<String>,
+    /// Identifies this delivery (not the event) to `ack` and `nack`.
+    pub token: u64,
+}
+
+/// The queue of event ids waiting to be processed. `MemoryQueue` is the
+/// in-process implementation; a persistent queue can stand in for it without
+/// the services noticing.
+///
+/// Delivery is at-least-once: an id whose lease runs out is handed out again,
+/// and the same id may be enqueued more than once. Workers rely on
+/// `EventStore::claim_for_processing` to drop the duplicates.
+///
+/// Ids enqueued with the same partition key are handed out one at a time,
+/// in the order they became ready: while a delivery of a key is out, or was
+/// nacked and waits to be redelivered, other ids of that key are held back.
+/// Ids of other keys, and ids without one, are not affected. A queue may
+/// likewise cap how many ids of one `Job::group` are out at once; ids over
+/// the cap wait their turn in the queue.
+///
+/// Ready ids wait in one lane per `Priority`. Lanes are served in proportion
+/// to their weights rather than strictly by priority, so a busy lane slows
+/// the lower ones down without starving them.
+#[async_trait]
+pub trait WorkQueue: Send + Sync + 'static {
+    /// Add `job` at the back of its lane, or schedule it for `job.at`.
+    /// Scheduling an id that is already scheduled moves it.
+    async fn enqueue_job(&self, job: Job) -> Result<(), QueueError>;
+
+    /// Add `id` at the back of the normal lane, without a partition key or a
+    /// group, so bulkheads do not hold it back and the processor only finds
+    /// its circuit breaker once it has claimed it. Enqueue an event's record
+    /// with `Job::for_record` instead.
+    async fn enqueue(&self, id: String) -> Result<(), QueueError> {
+        self.enqueue_job(Job::new(id)).await
+    }
+
+    /// Like `enqueue`, to be handed out no earlier than `at`.
+    async fn enqueue_at(&self, id: String, at: DateTime<Utc>) -> Result<(), QueueError> {
+        self.enqueue_job(Job { at: Some(at), ..Job::new(id) }).await
+    }
+
+    /// Like `enqueue_job`, for new work that a bounded queue may turn away:
+    /// while it is full, wait up to `wait` for room, then fail with
+    /// `QueueError::Full`. Redeliveries and recovered work go through
+    /// `enqueue_job` and are never refused.
+    async fn offer(&self, job: Job, wait: Duration) -> Result<(), QueueError> {
+        let _ = wait;
+        self.enqueue_job(job).await
+    }
+
+    /// Take `id` off the schedule before it comes due. Returns whether it
+    /// was scheduled; ids already handed out or ready are not affected.
+    async fn cancel_scheduled(&self, id: &str) -> Result<bool, QueueError>;
+
+    /// Wait for the next id and lease it for `lease`. Returns `None` once the
+    /// queue is closed.
+    async fn dequeue(&self, lease: Duration) -> Result<Option<Delivery>, QueueError>;
+
+    /// The delivery was dealt with; forget it, freeing its partition key.
+    async fn ack(&self, delivery: &Delivery) -> Result<(), QueueError>;
+
+    /// Give the delivery back, to be handed out again after `delay`. A
+    /// non-zero delay schedules it like `enqueue_at`. Its partition key stays
+    /// held until the redelivery is settled.
+    async fn nack(&self, delivery: &Delivery, delay: Duration) -> Result<(), QueueError>;
+
+    /// Up to `limit` ready ids, lane by lane from the highest, without
+    /// leasing them.
+    async fn peek(&self, limit: usize) -> Result<Vec<String>, QueueError>;
+
+    /// Ids waiting to be handed out, including scheduled ones; leased ids
+    /// are not counted.
+    async fn len(&self) -> Result<usize, QueueError>;
+
+    async fn is_empty(&self) -> Result<bool, QueueError> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Ids scheduled for later: retries waiting out their delay and events
+    /// dated for the future.
+    async fn scheduled(&self) -> Result<usize, QueueError>;
+
+    /// Return every delivery whose lease has run out to the queue. Returns
+    /// how many there were.
+    async fn release_expired(&self) -> Result<usize, QueueError>;
+
+    /// Stop handing out work: pending and future `dequeue` calls return
+    /// `None` and `enqueue` fails with `QueueError::Closed`.
+    fn close(&self);
+
+    fn is_closed(&self) -> bool;
+}
+
+#[async_trait]
+impl<T: WorkQueue + ?Sized> WorkQueue for Arc<T> {
+    async fn enqueue_job(&self, job: Job) -> Result<(), QueueError> {
+        (**self).enqueue_job(job).await
+    }
+
+    async fn enqueue(&self, id: String) -> Result<(), QueueError> {
+        (**self).enqueue(id).await
+    }
+
+    async fn enqueue_at(&self, id: String, at: DateTime<Utc>) -> Result<(), QueueError> {
+        (**self).enqueue_at(id, at).await
+    }
+
+    async fn offer(&self, job: Job, wait: Duration) -> Result<(), QueueError> {
+        (**self).offer(job, wait).await
+    }
+
+    async fn cancel_scheduled(&self, id: &str) -> Result<bool, QueueError> {
+        (**self).cancel_scheduled(id).await
+    }
+
+    async fn dequeue(&self, lease: Duration) -> Result<Option<Delivery>, QueueError> {
+        (**self).dequeue(lease).await
+    }
+
+    async fn ack(&self, delivery: &Delivery) -> Result<(), QueueError> {
+        (**self).ack(delivery).await
+    }
+
+    async fn nack(&self, delivery: &Delivery, delay: Duration) -> Result<(), QueueError> {
+        (**self).nack(delivery, delay).await
+    }
+
+    async fn peek(&self, limit: usize) -> Result<Vec<String>, QueueError> {
+        (**self).peek(limit).await
+    }
+
+    async fn len(&self) -> Result<usize, QueueError> {
+        (**self).len().await
+    }
+
+    async fn scheduled(&self) -> Result<usize, QueueError> {
+        (**self).scheduled().await
+    }
+
+    async fn release_expired(&self) -> Result<usize, QueueError> {
+        (**self).release_expired().await
+    }
+
+    fn close(&self) {
+        (**self).close()
+    }
+
+    fn is_closed(&self) -> bool {
+        (**self).is_closed()
+    }
+}
@@ -0,0 +1,402 @@
+use prometheus::{GaugeVec, IntCounterVec};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The longest a half-open breaker with every probe slot taken asks an event
+/// to wait before trying again.
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+
+/// Where a handler's circuit breaker stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Events go through; outcomes are counted.
+    Closed,
+    /// Too many recent calls failed: events are held back until `open_for`
+    /// has passed.
+    Open,
+    /// A few probe events go through to find out whether the dependency is
+    /// back.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+
+    /// Value of the `circuit_breaker_state` gauge.
+    pub fn gauge_value(&self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::HalfOpen => 1.0,
+            BreakerState::Open => 2.0,
+        }
+    }
+}
+
+/// When a breaker opens and how it recovers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerPolicy {
+    /// How many of the latest calls the failure rate is taken over.
+    pub window: usize,
+    /// Calls the window must hold before the breaker may open.
+    pub min_calls: usize,
+    /// Share of failed calls in the window, from 0 to 1, that opens the
+    /// breaker.
+    pub failure_rate: f64,
+    /// How long the breaker stays open before letting probes through.
+    pub open_for: Duration,
+    /// Events let through at once while half-open. That many successes
+    /// close the breaker again; any failure reopens it.
+    pub probes: usize,
+}
+
+impl Default for BreakerPolicy {
+    /// Opens when half of the last 20 calls failed, at least 10 calls in;
+    /// probes with one event after 30s.
+    fn default() -> Self {
+        Self { window: 20, min_calls: 10, failure_rate: 0.5, open_for: Duration::from_secs(30), probes: 1 }
+    }
+}
+
+/// A breaker's state as reported to operators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerStatus {
+    /// The route the handler is registered under.
+    pub handler: String,
+    pub state: BreakerState,
+    /// Calls in the window, and how many of them failed.
+    pub calls: usize,
+    pub failures: usize,
+    /// How long until an open breaker lets probes through.
+    pub retry_in: Option<Duration>,
+}
+
+struct Circuit {
+    state: BreakerState,
+    /// Outcomes of the latest calls while closed, `true` for a failure.
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    /// Probes out, and probes that succeeded, while half-open.
+    probing: usize,
+    probed: usize,
+    /// Bumped on every state change, so an outcome from a permit handed out
+    /// before it is ignored.
+    epoch: u64,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self { state: BreakerState::Closed, outcomes: VecDeque::new(), opened_at: Instant::now(), probing: 0, probed: 0, epoch: 0 }
+    }
+
+    fn failures(&self) -> usize {
+        self.outcomes.iter().filter(|failed| **failed).count()
+    }
+
+    fn enter(&mut self, state: BreakerState) {
+        self.state = state;
+        self.epoch += 1;
+        self.outcomes.clear();
+        self.probing = 0;
+        self.probed = 0;
+        if state == BreakerState::Open {
+            self.opened_at = Instant::now();
+        }
+    }
+
+    /// Time left open, moving to half-open once there is none.
+    fn remaining(&mut self, policy: &BreakerPolicy) -> Option<Duration> {
+        if self.state != BreakerState::Open {
+            return None;
+        }
+        match policy.open_for.checked_sub(self.opened_at.elapsed()).filter(|left| !left.is_zero()) {
+            Some(left) => Some(left),
+            None => {
+                self.enter(BreakerState::HalfOpen);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    state: Option<GaugeVec>,
+    opened: Option<IntCounterVec>,
+    rejected: Option<IntCounterVec>,
+}
+
+/// The circuit breakers of a `HandlerRegistry`'s handlers, one per
+/// registration, shared between the processor (which asks them before
+/// claiming an event) and the admin API. The default has no policy and lets
+/// everything through.
+#[derive(Clone, Default)]
+pub struct Breakers {
+    policy: Option<BreakerPolicy>,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Breakers {
+    pub fn new(policy: BreakerPolicy) -> Self {
+        Self { policy: Some(policy), ..Self::default() }
+    }
+
+    /// Report each breaker's state in `state`, labelled `handler` (0 closed,
+    /// 1 half-open, 2 open), count openings in `opened` and events held back
+    /// in `rejected`. Set before the handle is cloned.
+    pub fn with_metrics(mut self, state: GaugeVec, opened: IntCounterVec, rejected: IntCounterVec) -> Self {
+        self.metrics = Arc::new(Metrics { state: Some(state), opened: Some(opened), rejected: Some(rejected) });
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Track a breaker for `handler`, closed until its calls say otherwise.
+    pub fn register(&self, handler: &str) {
+        if self.policy.is_none() {
+            return;
+        }
+        let mut circuits = self.circuits.lock().unwrap();
+        if !circuits.contains_key(handler) {
+            circuits.insert(handler.to_string(), Circuit::new());
+            self.report(handler, BreakerState::Closed);
+        }
+    }
+
+    /// Let one event through `handler`'s breaker. `Err` carries how long
+    /// to hold the event back: what is left of an open breaker, or a short
+    /// wait while a half-open one has all its probes out.
+    pub fn try_acquire(&self, handler: &str) -> Result<BreakerPermit, Duration> {
+        let Some(policy) = &self.policy else {
+            return Ok(BreakerPermit { breakers: self.clone(), handler: None, epoch: 0, probe: false });
+        };
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(handler.to_string()).or_insert_with(Circuit::new);
+        if let Some(left) = circuit.remaining(policy) {
+            self.reject(handler);
+            return Err(left);
+        }
+        let probe = circuit.state == BreakerState::HalfOpen;
+        if probe {
+            if circuit.probing >= policy.probes.max(1) {
+                self.reject(handler);
+                return Err(PROBE_WAIT.min(policy.open_for));
+            }
+            circuit.probing += 1;
+        }
+        self.report(handler, circuit.state);
+        Ok(BreakerPermit { breakers: self.clone(), handler: Some(handler.to_string()), epoch: circuit.epoch, probe })
+    }
+
+    /// Every tracked breaker, by handler.
+    pub fn snapshot(&self) -> Vec<BreakerStatus> {
+        let Some(policy) = &self.policy else {
+            return Vec::new();
+        };
+        let mut circuits = self.circuits.lock().unwrap();
+        let mut statuses: Vec<_> = circuits
+            .iter_mut()
+            .map(|(handler, circuit)| {
+                let retry_in = circuit.remaining(policy);
+                self.report(handler, circuit.state);
+                BreakerStatus { handler: handler.clone(), state: circuit.state, calls: circuit.outcomes.len(), failures: circuit.failures(), retry_in }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.handler.cmp(&b.handler));
+        statuses
+    }
+
+    /// The state of `handler`'s breaker, if it is tracked.
+    pub fn state(&self, handler: &str) -> Option<BreakerState> {
+        self.snapshot().into_iter().find(|s| s.handler == handler).map(|s| s.state)
+    }
+
+    fn record(&self, handler: &str, epoch: u64, probe: bool, failed: bool) {
+        let Some(policy) = &self.policy else {
+            return;
+        };
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(handler).filter(|c| c.epoch == epoch) else {
+            return;
+        };
+        match circuit.state {
+            BreakerState::Closed => {
+                circuit.outcomes.push_back(failed);
+                while circuit.outcomes.len() > policy.window.max(1) {
+                    circuit.outcomes.pop_front();
+                }
+                let calls = circuit.outcomes.len();
+                let failures = circuit.failures();
+                if calls >= policy.min_calls && failures as f64 >= policy.failure_rate * calls as f64 {
+                    tracing::warn!(handler, calls, failures, "circuit breaker opened");
+                    self.open(handler, circuit);
+                }
+            }
+            BreakerState::HalfOpen if probe => {
+                circuit.probing = circuit.probing.saturating_sub(1);
+                if failed {
+                    tracing::warn!(handler, "circuit breaker probe failed, reopened");
+                    self.open(handler, circuit);
+                } else {
+                    circuit.probed += 1;
+                    if circuit.probed >= policy.probes.max(1) {
+                        circuit.enter(BreakerState::Closed);
+                        tracing::info!(handler, "circuit breaker closed");
+                        self.report(handler, BreakerState::Closed);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Free a probe slot whose event never reached the handler.
+    fn release(&self, handler: &str, epoch: u64) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(handler).filter(|c| c.epoch == epoch && c.state == BreakerState::HalfOpen) {
+            circuit.probing = circuit.probing.saturating_sub(1);
+        }
+    }
+
+    fn open(&self, handler: &str, circuit: &mut Circuit) {
+        circuit.enter(BreakerState::Open);
+        self.report(handler, BreakerState::Open);
+        if let Some(opened) = &self.metrics.opened {
+            opened.with_label_values(&[handler]).inc();
+        }
+    }
+
+    fn reject(&self, handler: &str) {
+        if let Some(rejected) = &self.metrics.rejected {
+            rejected.with_label_values(&[handler]).inc();
+        }
+    }
+
+    fn report(&self, handler: &str, state: BreakerState) {
+        if let Some(gauge) = &self.metrics.state {
+            gauge.with_label_values(&[handler]).set(state.gauge_value());
+        }
+    }
+}
+
+/// Leave to run one event through a handler, handed out by
+/// `Breakers::try_acquire`. Report how the call went with `succeeded` or
+/// `failed`; dropping the permit instead counts it as neither, for calls that
+/// say nothing about the handler's health.
+pub struct BreakerPermit {
+    breakers: Breakers,
+    handler: Option<String>,
+    epoch: u64,
+    probe: bool,
+}
+
+impl BreakerPermit {
+    pub fn succeeded(mut self) {
+        if let Some(handler) = self.handler.take() {
+            self.breakers.record(&handler, self.epoch, self.probe, false);
+        }
+    }
+
+    pub fn failed(mut self) {
+        if let Some(handler) = self.handler.take() {
+            self.breakers.record(&handler, self.epoch, self.probe, true);
+        }
+    }
+}
+
+impl Drop for BreakerPermit {
+    fn drop(&mut self) {
+        if let Some(handler) = self.handler.take().filter(|_| self.probe) {
+            self.breakers.release(&handler, self.epoch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> BreakerPolicy {
+        BreakerPolicy { window: 4, min_calls: 4, failure_rate: 0.5, open_for: Duration::from_millis(40), probes: 1 }
+    }
+
+    #[test]
+    fn opens_once_the_window_fails_often_enough() {
+        let breakers = Breakers::new(BreakerPolicy { failure_rate: 0.75, ..policy() });
+        breakers.register("billing.*");
+        let call = |failed: bool| {
+            let permit = breakers.try_acquire("billing.*").unwrap();
+            if failed { permit.failed() } else { permit.succeeded() }
+        };
+        // all failures, but not enough calls yet
+        call(true);
+        call(true);
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Closed));
+        // older outcomes slide out of the window
+        for failed in [false, false, false] {
+            call(failed);
+        }
+        assert_eq!((breakers.snapshot()[0].calls, breakers.snapshot()[0].failures), (4, 1));
+        call(true);
+        call(true);
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Closed));
+        call(true);
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Open));
+        let wait = breakers.try_acquire("billing.*").err().unwrap();
+        assert!(wait > Duration::ZERO && wait <= policy().open_for);
+        assert!(breakers.snapshot()[0].retry_in.is_some());
+    }
+
+    #[test]
+    fn half_open_probes_close_or_reopen_it() {
+        let breakers = Breakers::new(policy());
+        for _ in 0..4 {
+            breakers.try_acquire("h").unwrap().failed();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+
+        // one probe at a time; a failed probe reopens it at once
+        let probe = breakers.try_acquire("h").unwrap();
+        assert_eq!(breakers.state("h"), Some(BreakerState::HalfOpen));
+        assert!(breakers.try_acquire("h").is_err());
+        probe.failed();
+        assert_eq!(breakers.state("h"), Some(BreakerState::Open));
+
+        std::thread::sleep(Duration::from_millis(50));
+        // a probe that never reached the handler frees its slot
+        drop(breakers.try_acquire("h").unwrap());
+        breakers.try_acquire("h").unwrap().succeeded();
+        assert_eq!(breakers.state("h"), Some(BreakerState::Closed));
+        assert_eq!(breakers.snapshot()[0].calls, 0);
+    }
+
+    #[test]
+    fn stale_permits_and_disabled_breakers_change_nothing() {
+        let breakers = Breakers::new(policy());
+        let late = breakers.try_acquire("h").unwrap();
+        for _ in 0..4 {
+            breakers.try_acquire("h").unwrap().failed();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        breakers.try_acquire("h").unwrap().succeeded();
+        // handed out before the breaker opened: its failure is not counted
+        late.failed();
+        assert_eq!(breakers.state("h"), Some(BreakerState::Closed));
+        assert_eq!(breakers.snapshot()[0].calls, 0);
+
+        let off = Breakers::default();
+        for _ in 0..10 {
+            off.try_acquire("h").unwrap().failed();
+        }
+        assert!(off.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Cancellation tokens of the events being handled right now, shared between
+/// the processor (which hands them to handlers) and whoever needs to stop a
+/// handler early: an operator taking the event away, or shutdown.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    root: CancellationToken,
+    next: Arc<AtomicU64>,
+    tokens: Arc<Mutex<HashMap<String, (u64, CancellationToken)>>>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `id` as being handled. The token is cancelled by `cancel(id)`
+    /// or `cancel_all`; dropping the guard unregisters it.
+    pub fn start(&self, id: &str) -> InFlightGuard {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let token = self.root.child_token();
+        self.tokens.lock().unwrap().insert(id.to_string(), (seq, token.clone()));
+        InFlightGuard { in_flight: self.clone(), id: id.to_string(), seq, token }
+    }
+
+    /// Cancel the handler working on `id`. Returns whether one was.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.tokens.lock().unwrap().get(id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancel every handler, including ones started from now on.
+    pub fn cancel_all(&self) {
+        self.root.cancel();
+    }
+
+    /// Number of events being handled.
+    pub fn len(&self) -> usize {
+        self.tokens.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An event's registration in `InFlight`, removed on drop.
+pub struct InFlightGuard {
+    in_flight: InFlight,
+    id: String,
+    seq: u64,
+    token: CancellationToken,
+}
+
+impl InFlightGuard {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut tokens = self.in_flight.tokens.lock().unwrap();
+        // a later attempt at the same event may have registered since
+        if tokens.get(&self.id).is_some_and(|(seq, _)| *seq == self.seq) {
+            tokens.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_cancel_by_id_or_all_at_once() {
+        let in_flight = InFlight::new();
+        let a = in_flight.start("a");
+        let b = in_flight.start("b");
+        assert_eq!(in_flight.len(), 2);
+
+        assert!(in_flight.cancel("a"));
+        assert!(a.token().is_cancelled());
+        assert!(!b.token().is_cancelled());
+        assert!(!in_flight.cancel("missing"));
+
+        // an earlier attempt finishing late leaves the newer registration be
+        let a2 = in_flight.start("a");
+        drop(a);
+        assert_eq!(in_flight.len(), 2);
+        drop(a2);
+        assert_eq!(in_flight.len(), 1);
+
+        in_flight.cancel_all();
+        assert!(b.token().is_cancelled());
+        assert!(in_flight.start("c").token().is_cancelled());
+    }
+}
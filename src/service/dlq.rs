@@ -0,0 +1,120 @@
+use crate::domain::event::EventRecord;
+use crate::domain::state::EventStatus;
+use crate::queue::{Job, WorkQueue};
+use crate::store::{EventPage, EventQuery, EventStore, MemoryStore, StoreError};
+use crate::Telemetry;
+use std::sync::Arc;
+
+/// Operator access to the dead-letter queue: the records that ran out of
+/// retries (or were failed by an operator) and now sit in `Failed` with their
+/// payload, last error and full attempt history.
+#[derive(Clone)]
+pub struct DeadLetterQueue<S = MemoryStore> {
+    pub store: S,
+    pub queue: Arc<dyn WorkQueue>,
+    pub telemetry: Telemetry,
+}
+
+impl<S: EventStore + Clone> DeadLetterQueue<S> {
+    pub fn new(store: S, queue: impl WorkQueue, telemetry: Telemetry) -> Self {
+        Self { store, queue: Arc::new(queue), telemetry }
+    }
+
+    /// Dead-lettered records matching `query`; its `status` is ignored.
+    pub async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError> {
+        self.store.list(&EventQuery { status: Some(EventStatus::Failed), ..query.clone() }).await
+    }
+
+    /// Reset a dead-lettered record and enqueue it again.
+    pub async fn replay(&self, id: &str, expected: Option<u64>) -> Result<EventRecord, StoreError> {
+        self.store.replay(id, expected).await?;
+        self.telemetry.dlq_depth.dec();
+        let rec = self.store.get(id).await?;
+        if let Err(e) = self.queue.enqueue_job(Job::for_record(&rec)).await {
+            tracing::error!(event_id = %id, error = %e, "failed to enqueue replayed event");
+        }
+        Ok(rec)
+    }
+
+    /// Replay up to `max` dead-lettered records matching `query`, oldest
+    /// first. Records that change while this runs are skipped. Returns the
+    /// ids that were replayed.
+    pub async fn replay_matching(&self, query: &EventQuery, max: usize) -> Result<Vec<String>, StoreError> {
+        let mut query = EventQuery { status: Some(EventStatus::Failed), after: None, ..query.clone() };
+        let mut replayed = Vec::new();
+        while replayed.len() < max {
+            query.limit = query.limit.clamp(1, max - replayed.len());
+            let page = self.store.list(&query).await?;
+            for rec in &page.records {
+                match self.replay(&rec.event.event_id, Some(rec.version)).await {
+                    Ok(_) => replayed.push(rec.event.event_id.clone()),
+                    Err(StoreError::VersionConflict { .. } | StoreError::InvalidTransition { .. } | StoreError::NotFound | StoreError::Evicted { .. }) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            match page.next_cursor {
+                Some(cursor) => query.after = Some(cursor),
+                None => break,
+            }
+        }
+        Ok(replayed)
+    }
+
+    /// Delete a dead-lettered record for good. Its id is kept as a
+    /// tombstone, so a redelivery is still recognised as a duplicate.
+    pub async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.store.purge(id, expected).await?;
+        self.telemetry.dlq_depth.dec();
+        Ok(())
+    }
+}
+
+/// Set the `dlq_depth` gauge from the store, e.g. on startup or after
+/// retention evicted failed records.
+pub async fn refresh_dlq_depth<S: EventStore>(store: &S, telemetry: &Telemetry) -> Result<usize, StoreError> {
+    let depth = store.ids_by_status(EventStatus::Failed).await?.len();
+    telemetry.dlq_depth.set(depth as f64);
+    Ok(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::queue::MemoryQueue;
+
+    async fn dead_letter<S: EventStore>(store: &S, id: &str) {
+        store.insert_if_absent(event(id)).await.unwrap();
+        store.claim_for_processing(id, OWNER, LEASE).await.unwrap();
+        store.set_failed(id, format!("{id} failed").into(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_matching_resets_and_enqueues() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+        let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
+        for id in ["d1", "d2", "d3"] {
+            dead_letter(&store, id).await;
+        }
+        store.insert_if_absent(event("live")).await.unwrap();
+        assert_eq!(refresh_dlq_depth(&store, &telemetry).await.unwrap(), 3);
+
+        // small pages, capped below the number of matches
+        let query = EventQuery { limit: 1, ..Default::default() };
+        assert_eq!(dlq.replay_matching(&query, 2).await.unwrap(), vec!["d1".to_string(), "d2".to_string()]);
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["d1".to_string(), "d2".to_string()]);
+        let d1 = store.get("d1").await.unwrap();
+        assert_eq!((d1.status, d1.attempts, d1.last_error), (EventStatus::Received, 0, None));
+        assert_eq!(telemetry.dlq_depth.get() as i64, 1);
+        assert_eq!(telemetry.queue_depth.get() as i64, 2);
+
+        let page = dlq.list(&EventQuery { limit: 10, ..Default::default() }).await.unwrap();
+        assert_eq!(page.records.iter().map(|r| r.event.event_id.as_str()).collect::<Vec<_>>(), vec!["d3"]);
+
+        dlq.purge("d3", None).await.unwrap();
+        assert_eq!(telemetry.dlq_depth.get() as i64, 0);
+        assert!(matches!(dlq.purge("live", None).await, Err(StoreError::NotDeadLettered { status: EventStatus::Received })));
+    }
+}
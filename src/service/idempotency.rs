@@ -1,5 +1,5 @@
-use crate::store::MemoryStore;
+use crate::store::{EventStore, StoreError};
 
-pub async fn insert_if_absent(store: &MemoryStore, ev: crate::domain::event::Event) -> (crate::domain::event::EventRecord, bool) {
+pub async fn insert_if_absent<S: EventStore>(store: &S, ev: crate::domain::event::Event) -> Result<(crate::domain::event::EventRecord, bool), StoreError> {
     store.insert_if_absent(ev).await
 }
@@ -1,40 +1,285 @@
+use crate::domain::error::{ErrorKind, EventError};
 use crate::domain::event::{Event, EventRecord};
-use crate::store::MemoryStore;
+use crate::domain::state::EventStatus;
+use crate::queue::{Job, QueueError, WorkQueue};
+use crate::service::cancel::InFlight;
+use crate::service::priority::Priorities;
+use crate::store::{EventStore, MemoryStore, StoreError};
 use crate::Telemetry;
-use tokio::sync::mpsc;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::watch;
+
+#[derive(Error, Debug)]
+pub enum IngestError {
+    /// `IngestService::close` was called; the service is shutting down.
+    #[error("not accepting events: shutting down")]
+    Closed,
+
+    /// The work queue stayed full for the whole `OverloadPolicy::max_wait`;
+    /// the event was not stored. The caller should try again after
+    /// `retry_after`.
+    #[error("overloaded: work queue is full")]
+    Overloaded { retry_after: Duration },
+
+    /// The event could not be enqueued and was not stored.
+    #[error("could not enqueue event: {0}")]
+    Queue(QueueError),
+
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+/// What ingest does when the work queue is full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverloadPolicy {
+    /// How long to wait for room before turning the event away; zero fails
+    /// fast.
+    pub max_wait: Duration,
+    /// How long a turned-away caller is told to back off.
+    pub retry_after: Duration,
+}
+
+impl Default for OverloadPolicy {
+    fn default() -> Self {
+        Self { max_wait: Duration::from_secs(1), retry_after: Duration::from_secs(1) }
+    }
+}
 
 #[derive(Clone)]
-pub struct IngestService {
-    pub store: MemoryStore,
-    pub tx: mpsc::Sender<String>,
+pub struct IngestService<S = MemoryStore> {
+    pub store: S,
+    pub queue: Arc<dyn WorkQueue>,
     pub telemetry: Telemetry,
+    /// Handlers to cancel when an operator takes their event away.
+    pub in_flight: InFlight,
+    /// Payload field used as the partition key of events that arrive
+    /// without one.
+    pub partition_field: Option<String>,
+    /// Priority of events that arrive without one.
+    pub priorities: Priorities,
+    pub overload: OverloadPolicy,
+    closed: Arc<AtomicBool>,
+    admitting: Admitting,
+}
+
+/// Ids being inserted and offered to the queue right now. Each maps to a
+/// channel whose sender is dropped once the outcome is settled.
+type Admitting = Arc<Mutex<HashMap<String, watch::Receiver<()>>>>;
+
+/// An id's place in `Admitting`, given up on drop.
+struct Admission {
+    admitting: Admitting,
+    id: String,
+    _done: watch::Sender<()>,
 }
 
-impl IngestService {
-    pub fn new(store: MemoryStore, tx: mpsc::Sender<String>, telemetry: Telemetry) -> Self {
-        Self { store, tx, telemetry }
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.admitting.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl<S: EventStore + Clone> IngestService<S> {
+    pub fn new(store: S, queue: impl WorkQueue, telemetry: Telemetry) -> Self {
+        Self { store, queue: Arc::new(queue), telemetry, in_flight: InFlight::default(), partition_field: None, priorities: Priorities::default(), overload: OverloadPolicy::default(), closed: Arc::default(), admitting: Arc::default() }
+    }
+
+    /// Share the processor's `InFlight`, so operator actions cancel the
+    /// handler still working on the event.
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// Default the partition key of incoming events to the value of payload
+    /// `field` (see `Event::default_partition_key`).
+    pub fn with_partition_field(mut self, field: Option<String>) -> Self {
+        self.partition_field = field;
+        self
+    }
+
+    /// Assign events that arrive without a priority the one `priorities`
+    /// gives their type.
+    pub fn with_priorities(mut self, priorities: Priorities) -> Self {
+        self.priorities = priorities;
+        self
+    }
+
+    pub fn with_overload_policy(mut self, overload: OverloadPolicy) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Refuse further events with `IngestError::Closed`, e.g. while shutting
+    /// down. Affects every clone of this service.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
     }
 
-    /// Idempotent ingest: insert if absent, enqueue if newly inserted.
-    pub async fn ingest(&self, event: Event) -> (EventRecord, bool) {
-        let (rec, inserted) = self.store.insert_if_absent(event).await;
-        if inserted {
-            self.telemetry.events_ingested.inc();
-            let _ = self.tx.send(rec.event.event_id.clone()).await;
-            self.telemetry.queue_depth.inc();
-        } else {
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Idempotent ingest: insert if absent, enqueue if newly inserted. A
+    /// redelivery of an event that has since been evicted is counted as a
+    /// duplicate and reported as `StoreError::Evicted`.
+    pub async fn ingest(&self, event: Event) -> Result<(EventRecord, bool), IngestError> {
+        self.ingest_at(event, None).await
+    }
+
+    /// Like `ingest`, but an event with a future `process_after` is stored
+    /// `Scheduled` and only handed to the workers once it is due.
+    ///
+    /// A new event is offered to the queue under the `OverloadPolicy`. If the
+    /// queue turns it away the record is discarded again, so nothing is
+    /// stored that no worker will see, and the caller gets
+    /// `IngestError::Overloaded` (or `Queue`/`Closed`) and may retry with the
+    /// same id. Until that is settled, a duplicate of the event waits for the
+    /// outcome instead of being answered with a record that may yet be
+    /// discarded.
+    pub async fn ingest_at(&self, mut event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), IngestError> {
+        if self.is_closed() {
+            return Err(IngestError::Closed);
+        }
+        if let Some(field) = &self.partition_field {
+            event.default_partition_key(field);
+        }
+        event.priority = event.priority.or_else(|| Some(self.priorities.for_type(&event.event_type)));
+        let _admission = self.admit(&event.event_id).await;
+        let (rec, inserted) = match self.store.insert_if_absent_at(event, process_after).await {
+            Ok(v) => v,
+            Err(e @ StoreError::Evicted { .. }) => {
+                self.telemetry.events_deduped.inc();
+                return Err(e.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !inserted {
             self.telemetry.events_deduped.inc();
+            return Ok((rec, false));
+        }
+        if let Err(e) = self.queue.offer(Job::for_record(&rec), self.overload.max_wait).await {
+            self.roll_back(&rec, e).await?;
+        }
+        self.telemetry.events_ingested.inc();
+        Ok((rec, true))
+    }
+
+    /// Take `id`'s place in `admitting`, first waiting for any ingest of the
+    /// same id already there to settle.
+    async fn admit(&self, id: &str) -> Admission {
+        loop {
+            let mut pending = {
+                let mut admitting = self.admitting.lock().unwrap();
+                match admitting.get(id) {
+                    Some(pending) => pending.clone(),
+                    None => {
+                        let (done, pending) = watch::channel(());
+                        admitting.insert(id.to_string(), pending);
+                        return Admission { admitting: self.admitting.clone(), id: id.to_string(), _done: done };
+                    }
+                }
+            };
+            // only ever fails, once the sender is dropped
+            let _ = pending.changed().await;
+        }
+    }
+
+    /// Discard `rec`, which the queue refused with `e`, and report why. If
+    /// something got hold of the record in the meantime it is kept and
+    /// enqueued past the limit instead, so it is not stranded.
+    async fn roll_back(&self, rec: &EventRecord, e: QueueError) -> Result<(), IngestError> {
+        let id = &rec.event.event_id;
+        if let Err(discard_err) = self.store.discard(id, rec.version).await {
+            tracing::error!(event_id = %id, error = %e, %discard_err, "could not roll back unqueued event, enqueueing it anyway");
+            self.enqueue(rec).await;
+            return Ok(());
+        }
+        tracing::warn!(event_id = %id, error = %e, "event not accepted: could not enqueue it");
+        Err(match e {
+            QueueError::Full => {
+                self.telemetry.events_shed.inc();
+                IngestError::Overloaded { retry_after: self.overload.retry_after }
+            }
+            QueueError::Closed => IngestError::Closed,
+            e => IngestError::Queue(e),
+        })
+    }
+
+    /// Enqueue every record still waiting in `Received` or `Scheduled`, e.g.
+    /// work recovered by a durable store after a restart. Scheduled events and
+    /// retries still waiting out their delay are scheduled for their
+    /// `not_before`. Returns how many were enqueued.
+    pub async fn enqueue_pending(&self) -> Result<usize, StoreError> {
+        let mut ids = self.store.ids_by_status(EventStatus::Received).await?;
+        ids.extend(self.store.ids_by_status(EventStatus::Scheduled).await?);
+        let n = ids.len();
+        for id in ids {
+            match self.store.get(&id).await {
+                Ok(rec) => self.enqueue(&rec).await,
+                // evicted or purged since it was listed
+                Err(StoreError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(n)
+    }
+
+    /// Enqueue `rec` in its lane and under its partition key, scheduled for
+    /// its `not_before` if it has one. A failure is only logged: the record
+    /// stays `Received` (or `Scheduled`), so `enqueue_pending` still finds it.
+    async fn enqueue(&self, rec: &EventRecord) {
+        if let Err(e) = self.queue.enqueue_job(Job::for_record(rec)).await {
+            tracing::error!(event_id = %rec.event.event_id, error = %e, "failed to enqueue event");
+        }
+    }
+
+    /// Operator action: take an event away from the worker processing it and
+    /// put it back on the queue. `expected` is the record version the operator
+    /// last saw; the worker's handler is cancelled and its own outcome will be
+    /// rejected as stale.
+    pub async fn requeue(&self, id: &str, expected: Option<u64>) -> Result<EventRecord, StoreError> {
+        self.store.set_error_and_mark_received(id, EventError::new(ErrorKind::Retryable, "operator", "requeued by operator"), expected).await?;
+        self.in_flight.cancel(id);
+        let rec = self.store.get(id).await?;
+        self.enqueue(&rec).await;
+        Ok(rec)
+    }
+
+    /// Call off a `Scheduled` event before it comes due: the record becomes
+    /// `Cancelled` and is taken off the queue's schedule.
+    pub async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<EventRecord, StoreError> {
+        self.store.cancel(id, expected).await?;
+        if let Err(e) = self.queue.cancel_scheduled(id).await {
+            // harmless: the worker that dequeues it will fail to claim it
+            tracing::warn!(event_id = %id, error = %e, "failed to unschedule cancelled event");
         }
-        (rec, inserted)
+        self.store.get(id).await
+    }
+
+    /// Operator action: mark an in-flight event `Failed` without further
+    /// retries, which puts it in the dead-letter queue and cancels its handler.
+    pub async fn fail(&self, id: &str, expected: Option<u64>) -> Result<EventRecord, StoreError> {
+        self.store.set_failed(id, EventError::new(ErrorKind::Permanent, "operator", "failed by operator"), expected).await?;
+        self.in_flight.cancel(id);
+        self.telemetry.dlq_depth.inc();
+        self.store.get(id).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::store::MemoryStore;
+    use crate::queue::MemoryQueue;
+    use crate::store::{EventStore, MemoryStore};
+    use crate::store::conformance::{LEASE, OWNER};
     use crate::telemetry::Telemetry;
-    use crate::domain::event::{Event, EventPayload, EventType};
+    use crate::domain::event::{Event, EventPayload, EventType, Priority};
     use chrono::Utc;
     use serde_json::json;
 
@@ -42,57 +287,229 @@ mod tests {
     async fn ingest_inserts_and_enqueues() {
         let store = MemoryStore::new();
         let telemetry = Telemetry::new();
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(8);
-        let svc = IngestService::new(store.clone(), tx, telemetry.clone());
+        let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
 
         let ev = Event {
             event_id: "i1".to_string(),
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({"u":"1"})),
+            partition_key: None,
+            priority: None,
         };
 
-        let (rec, inserted) = svc.ingest(ev.clone()).await;
+        let (rec, inserted) = svc.ingest(ev.clone()).await.unwrap();
         assert!(inserted);
         assert_eq!(rec.event.event_id, "i1");
 
         // ensure the id was enqueued
-        let queued = rx.recv().await;
-        assert_eq!(queued.unwrap(), "i1".to_string());
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["i1".to_string()]);
 
         // telemetry assertions — queue depth should have increased by the enqueue
         assert!(telemetry.events_ingested.get() > 0);
         assert_eq!(telemetry.queue_depth.get() as i64, 1);
     }
 
+    #[tokio::test]
+    async fn priority_is_kept_or_assigned_by_type() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let priorities = Priorities::new(Priority::Low).with(EventType::UserLoginFailed, Priority::High);
+        let svc = IngestService::new(store.clone(), queue.clone(), Telemetry::new()).with_priorities(priorities);
+
+        let ev = |id: &str, event_type: EventType, priority| Event { event_id: id.to_string(), event_type, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority };
+        svc.ingest(ev("bulk", EventType::Other("analytics.page_view".into()), None)).await.unwrap();
+        svc.ingest(ev("urgent", EventType::Other("analytics.page_view".into()), Some(Priority::Normal))).await.unwrap();
+        svc.ingest(ev("login", EventType::UserLoginFailed, None)).await.unwrap();
+
+        assert_eq!(store.get("bulk").await.unwrap().event.priority, Some(Priority::Low));
+        assert_eq!(store.get("urgent").await.unwrap().event.priority, Some(Priority::Normal));
+        assert_eq!(store.get("login").await.unwrap().event.priority, Some(Priority::High));
+        // each waits in its lane
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["login".to_string(), "urgent".to_string(), "bulk".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn events_the_queue_refuses_are_rolled_back() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new().with_capacity(1);
+        let overload = OverloadPolicy { max_wait: Duration::from_millis(20), retry_after: Duration::from_secs(5) };
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone()).with_overload_policy(overload);
+        let ev = |id: &str| Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+
+        svc.ingest(ev("f1")).await.unwrap();
+        assert!(matches!(svc.ingest(ev("f2")).await, Err(IngestError::Overloaded { retry_after }) if retry_after == Duration::from_secs(5)));
+        assert!(matches!(store.get("f2").await, Err(StoreError::NotFound)));
+        assert_eq!((telemetry.events_ingested.get(), telemetry.events_shed.get()), (1, 1));
+
+        // once a worker takes f1 there is room, and f2 can be sent again
+        let next = tokio::spawn({
+            let svc = svc.clone();
+            async move { svc.with_overload_policy(OverloadPolicy { max_wait: Duration::from_secs(5), ..OverloadPolicy::default() }).ingest(ev("f2")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert!(next.await.unwrap().unwrap().1);
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["f2".to_string()]);
+
+        queue.close();
+        assert!(matches!(svc.ingest(ev("f3")).await, Err(IngestError::Closed)));
+        assert!(matches!(store.get("f3").await, Err(StoreError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn duplicates_wait_for_the_first_ingest_to_be_admitted_or_refused() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new().with_capacity(1);
+        let overload = OverloadPolicy { max_wait: Duration::from_millis(100), retry_after: Duration::from_secs(1) };
+        let svc = IngestService::new(store.clone(), queue.clone(), Telemetry::new()).with_overload_policy(overload);
+        let ev = |id: &str| Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+        svc.ingest(ev("full")).await.unwrap();
+
+        // refused: the duplicate is not told about the record the first one
+        // stored and then discarded
+        let first = tokio::spawn({
+            let svc = svc.clone();
+            async move { svc.ingest(ev("d1")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(matches!(svc.ingest(ev("d1")).await, Err(IngestError::Overloaded { .. })));
+        assert!(matches!(first.await.unwrap(), Err(IngestError::Overloaded { .. })));
+        assert!(matches!(store.get("d1").await, Err(StoreError::NotFound)));
+
+        // admitted: the duplicate sees the record once it is queued
+        let first = tokio::spawn({
+            let svc = svc.clone();
+            async move { svc.ingest(ev("d2")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let dup = tokio::spawn({
+            let svc = svc.clone();
+            async move { svc.ingest(ev("d2")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert!(first.await.unwrap().unwrap().1);
+        assert!(!dup.await.unwrap().unwrap().1);
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["d2".to_string()]);
+    }
+
     #[tokio::test]
     async fn ingest_is_idempotent() {
         let store = MemoryStore::new();
         let telemetry = Telemetry::new();
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(8);
-        let svc = IngestService::new(store.clone(), tx.clone(), telemetry.clone());
+        let queue = MemoryQueue::new();
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
 
         let ev = Event {
             event_id: "i2".to_string(),
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
         };
 
-        let (_rec1, ins1) = svc.ingest(ev.clone()).await;
+        let (_rec1, ins1) = svc.ingest(ev.clone()).await.unwrap();
         assert!(ins1);
-        let (_rec2, ins2) = svc.ingest(ev.clone()).await;
+        let (_rec2, ins2) = svc.ingest(ev.clone()).await.unwrap();
         assert!(!ins2);
 
         // only one message should be in queue (first insert)
-        let q1 = rx.recv().await;
-        assert_eq!(q1.unwrap(), "i2".to_string());
-        // second recv should be None or timeout; channel empty now
-        let q2 = tokio::time::timeout(std::time::Duration::from_millis(50), rx.recv()).await;
-        assert!(q2.is_err(), "expected no second message in queue");
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["i2".to_string()]);
 
         // telemetry assertions
         assert!(telemetry.events_ingested.get() > 0);
         assert!(telemetry.events_deduped.get() > 0);
     }
+
+    #[tokio::test]
+    async fn enqueue_pending_requeues_received_records() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+
+        // records that reached the store without being enqueued (e.g. recovered from disk)
+        for id in ["p1", "p2", "p3"] {
+            let ev = Event {
+                event_id: id.to_string(),
+                event_type: EventType::UserLoginFailed,
+                occurred_at: Utc::now(),
+                payload: EventPayload(json!({})),
+                partition_key: None,
+                priority: None,
+            };
+            store.insert_if_absent(ev).await.unwrap();
+        }
+        store.claim_for_processing("p2", OWNER, LEASE).await.unwrap();
+        // p3 is waiting out a retry delay
+        store.claim_for_processing("p3", OWNER, LEASE).await.unwrap();
+        store.retry_later("p3", "slow".into(), std::time::Duration::from_secs(60), None).await.unwrap();
+
+        assert_eq!(svc.enqueue_pending().await.unwrap(), 2);
+        assert_eq!(queue.peek(10).await.unwrap(), vec!["p1".to_string()]);
+        assert_eq!(queue.scheduled_entries(), vec![(store.get("p3").await.unwrap().not_before.unwrap(), "p3".to_string())]);
+        assert_eq!(telemetry.queue_depth.get() as i64, 2);
+    }
+
+    #[tokio::test]
+    async fn future_events_are_scheduled_and_can_be_cancelled() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new();
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+        let at = Utc::now() + chrono::Duration::minutes(5);
+
+        for id in ["f1", "f2"] {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            let (rec, inserted) = svc.ingest_at(ev, Some(at)).await.unwrap();
+            assert!(inserted);
+            assert_eq!(rec.status, EventStatus::Scheduled);
+        }
+        assert!(queue.peek(10).await.unwrap().is_empty());
+        assert_eq!(queue.scheduled_entries(), vec![(at, "f1".to_string()), (at, "f2".to_string())]);
+
+        let rec = svc.cancel("f1", Some(1)).await.unwrap();
+        assert_eq!(rec.status, EventStatus::Cancelled);
+        assert_eq!(queue.scheduled_entries(), vec![(at, "f2".to_string())]);
+        assert!(matches!(svc.cancel("f1", None).await, Err(StoreError::InvalidTransition { .. })));
+    }
+
+    #[tokio::test]
+    async fn operator_requeue_checks_version_and_enqueues() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+        let svc = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+
+        let ev = Event {
+            event_id: "o1".to_string(),
+            event_type: EventType::UserLoginFailed,
+            occurred_at: Utc::now(),
+            payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
+        };
+        svc.ingest(ev).await.unwrap();
+        assert_eq!(queue.dequeue(LEASE).await.unwrap().unwrap().id, "o1");
+        store.claim_for_processing("o1", OWNER, LEASE).await.unwrap();
+        let handling = svc.in_flight.start("o1");
+
+        let err = svc.requeue("o1", Some(1)).await.unwrap_err();
+        assert!(matches!(err, StoreError::VersionConflict { expected: 1, actual: 2 }));
+        assert!(!handling.token().is_cancelled());
+
+        let rec = svc.requeue("o1", Some(2)).await.unwrap();
+        assert_eq!(rec.status, EventStatus::Received);
+        assert_eq!(rec.version, 3);
+        let delivery = queue.dequeue(LEASE).await.unwrap().unwrap();
+        assert_eq!(delivery.id, "o1");
+        // the processor finds the handler's breaker from the group alone
+        assert_eq!(delivery.group.as_deref(), Some("user.login_failed"));
+        // the worker still handling it is told to stop
+        assert!(handling.token().is_cancelled());
+    }
 }
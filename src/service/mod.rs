@@ -1,6 +1,22 @@
+pub mod breaker;
+pub mod cancel;
+pub mod dlq;
 pub mod ingest;
 pub mod processor;
 pub mod idempotency;
+pub mod priority;
+pub mod reaper;
+pub mod registry;
+pub mod retention;
+pub mod retry;
 
-pub use ingest::IngestService;
-pub use processor::run_processor_pool;
\ No newline at end of file
+pub use breaker::{BreakerPermit, BreakerPolicy, BreakerState, BreakerStatus, Breakers};
+pub use cancel::{InFlight, InFlightGuard};
+pub use dlq::{refresh_dlq_depth, DeadLetterQueue};
+pub use ingest::{IngestError, IngestService, OverloadPolicy};
+pub use processor::{run_processor_pool, ProcessorHandle, ShutdownReport};
+pub use priority::Priorities;
+pub use reaper::{reap_expired_leases, release_orphaned_claims, run_lease_reaper};
+pub use registry::{Cancellable, EventHandler, Fallback, HandlerOptions, HandlerRegistry, Route, TypedEvent, TypedHandler};
+pub use retention::{enforce_retention, run_retention};
+pub use retry::{Backoff, RetryPolicies, RetryPolicy};
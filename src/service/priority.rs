@@ -0,0 +1,64 @@
+use crate::domain::event::{EventType, Priority};
+use std::collections::HashMap;
+
+/// The queue lane for events that arrive without a priority: a default plus
+/// overrides for particular event types.
+#[derive(Debug, Clone, Default)]
+pub struct Priorities {
+    default: Priority,
+    by_type: HashMap<EventType, Priority>,
+}
+
+impl Priorities {
+    pub fn new(default: Priority) -> Self {
+        Self { default, by_type: HashMap::new() }
+    }
+
+    /// Put events of type `event_type` in the `priority` lane.
+    pub fn with(mut self, event_type: EventType, priority: Priority) -> Self {
+        self.by_type.insert(event_type, priority);
+        self
+    }
+
+    pub fn for_type(&self, event_type: &EventType) -> Priority {
+        self.by_type.get(event_type).copied().unwrap_or(self.default)
+    }
+}
+
+impl std::str::FromStr for Priorities {
+    type Err = String;
+
+    /// Parse `type=priority` pairs separated by commas, e.g.
+    /// `user.login_failed=high,analytics.page_view=low`; a bare `*=priority`
+    /// sets the default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut priorities = Priorities::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, priority) = pair.split_once('=').ok_or_else(|| format!("expected type=priority, got {pair:?}"))?;
+            let priority: Priority = priority.trim().parse()?;
+            match name.trim() {
+                "*" => priorities.default = priority,
+                name => priorities = priorities.with(EventType::try_from(name.to_string()).unwrap_or(EventType::Other(name.to_string())), priority),
+            }
+        }
+        Ok(priorities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_overrides_and_default() {
+        let p: Priorities = "user.login_failed=high, analytics.page_view=low".parse().unwrap();
+        assert_eq!(p.for_type(&EventType::UserLoginFailed), Priority::High);
+        assert_eq!(p.for_type(&EventType::Other("analytics.page_view".into())), Priority::Low);
+        assert_eq!(p.for_type(&EventType::Other("misc".into())), Priority::Normal);
+
+        let p: Priorities = "*=low".parse().unwrap();
+        assert_eq!(p.for_type(&EventType::UserLoginFailed), Priority::Low);
+        assert!("user.login_failed".parse::<Priorities>().is_err());
+        assert!("user.login_failed=urgent".parse::<Priorities>().is_err());
+    }
+}
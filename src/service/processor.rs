@@ -1,118 +1,310 @@
-use crate::domain::event::Event;
-use crate::store::MemoryStore;
+use crate::domain::error::{ErrorKind, EventError, HandlerError};
+use crate::domain::event::EventType;
+use crate::domain::state::EventStatus;
+use crate::queue::{Delivery, WorkQueue};
+use crate::service::cancel::InFlight;
+use crate::service::registry::{HandlerRegistry, CANCELLED};
+use crate::service::retry::RetryPolicies;
+use crate::store::{EventStore, StoreError};
 use crate::Telemetry;
-use serde_json::Value;
-use std::future::Future;
+use chrono::Utc;
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 
-/// Run a pool of processor workers that consume event IDs from `rx`, claim
-/// events in the `store` and call the provided async `handler` to process
-/// events. If the handler returns Err, the event is requeued until
-/// `max_retries` attempts.
-pub fn run_processor_pool<H, Fut>(
-    store: MemoryStore,
-    rx: Arc<Mutex<mpsc::Receiver<String>>>,
-    tx: mpsc::Sender<String>,
+/// How long a worker's claim on an event stays valid. If the worker dies
+/// without finishing, the lease reaper returns the event to the queue once
+/// this elapses.
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(300);
+
+/// Run a pool of processor workers that take event IDs from `queue`, claim
+/// events in the `store` and process them with the handler `handlers` routes
+/// their type to. If the handler returns a retryable error, the event is
+/// retried after the delay its `RetryPolicy` picks (the handler's own, else
+/// its type's in `retry`) or the handler asked for, until the policy gives
+/// up; then, or straight away for a permanent error, it is left `Failed` in
+/// the dead-letter queue with the error attached. A retry is the delivery
+/// nacked back to the queue with the chosen delay; the record's `not_before`
+/// keeps it from being claimed any sooner, and an id delivered early is
+/// nacked again until then. An event whose handler's circuit breaker is open
+/// is nacked for as long as it stays open, without being claimed; the
+/// breaker is found through the delivery's group. An id enqueued without
+/// one (see `Job::for_record`) is only checked once claimed, against its
+/// record's type, so each pause costs it an attempt.
+///
+/// The returned handle stops the pool with `shutdown`, or waits for it to
+/// end on its own (when the queue is closed) with `join`.
+pub fn run_processor_pool<S>(
+    store: S,
+    queue: impl WorkQueue,
     workers: usize,
-    max_retries: u32,
+    retry: RetryPolicies,
     telemetry: Telemetry,
-    handler: H,
-)
+    handlers: HandlerRegistry,
+) -> ProcessorHandle
 where
-    H: Fn(Event) -> Fut + Send + Sync + 'static + Clone,
-    Fut: Future<Output = Result<Value, String>> + Send + 'static,
+    S: EventStore + Clone,
 {
-    let handler = Arc::new(handler);
-    for _ in 0..workers {
-        let rx = rx.clone();
+    let queue: Arc<dyn WorkQueue> = Arc::new(queue);
+    let in_flight = handlers.in_flight();
+    let handlers = Arc::new(handlers);
+    let retry = Arc::new(retry);
+    // lease owners look like `3f2a9c1e-w0`, unique per pool and worker
+    let pool_id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    let mut handles = Vec::with_capacity(workers);
+    for n in 0..workers {
+        let worker_id = format!("{pool_id}-w{n}");
+        let queue = queue.clone();
         let store_clone = store.clone();
-        let tx_clone = tx.clone();
-        let handler_clone = handler.clone();
+        let handlers = handlers.clone();
         let telemetry = telemetry.clone();
-        tokio::spawn(async move {
+        let retry = retry.clone();
+        handles.push(tokio::spawn(async move {
             loop {
-                let opt = {
-                    let mut lock = rx.lock().await;
-                    lock.recv().await
+                let delivery = match queue.dequeue(DEFAULT_LEASE).await {
+                    Ok(Some(delivery)) => delivery,
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::error!(%e, "dequeue failed");
+                        sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
                 };
-                let id = match opt {
-                    Some(id) => id,
-                    None => break,
+                let id = delivery.id.clone();
+                // ask the handler's breaker before claiming: while it is open the event
+                // goes back unclaimed, so waiting out the outage uses up none of its attempts.
+                // The type comes from the delivery's group, which `Job::for_record` sets, so
+                // this costs no store read; an id enqueued without one is checked once claimed
+                let event_type = delivery.group.clone().and_then(|group| EventType::try_from(group).ok());
+                let permit = match event_type.map(|t| handlers.admit(&t)).transpose() {
+                    Ok(permit) => permit.flatten(),
+                    Err(wait) => {
+                        tracing::debug!(event_id = %id, wait = ?wait, "handler circuit open, pausing event");
+                        settle(&*queue, &delivery, Some(wait)).await;
+                        continue;
+                    }
                 };
-                // we popped one item off the queue
-                telemetry.queue_depth.dec();
+                // `Some(delay)` hands the delivery back to be redelivered after `delay`
+                let mut redeliver = None;
                 // Try to claim
-                match store_clone.claim_for_processing(&id).await {
+                match store_clone.claim_for_processing(&id, &worker_id, DEFAULT_LEASE).await {
                     Ok(true) => {
                         // fetch event
                         if let Ok(rec) = store_clone.get(&id).await {
+                            let permit = match permit {
+                                None if delivery.group.is_none() => match handlers.admit(&rec.event.event_type) {
+                                    Ok(permit) => permit,
+                                    Err(wait) => {
+                                        // too late to leave it unclaimed: hand the claim back as shutdown does
+                                        tracing::debug!(event_id = %id, wait = ?wait, "handler circuit open, returning claimed event");
+                                        let err = EventError::new(ErrorKind::Retryable, "circuit_open", "handler circuit breaker is open");
+                                        if record_outcome(&telemetry, &id, store_clone.set_error_and_mark_received(&id, err, Some(rec.version)).await) {
+                                            redeliver = Some(wait);
+                                        }
+                                        settle(&*queue, &delivery, redeliver).await;
+                                        continue;
+                                    }
+                                },
+                                permit => permit,
+                            };
                             let ev = rec.event.clone();
                             let start = Instant::now();
-                            let res = (handler_clone)(ev).await;
+                            let res = handlers.handle(ev).await;
                             let elapsed = start.elapsed();
                             telemetry.processing_hist.observe(elapsed.as_secs_f64());
+                            if let Some(permit) = permit {
+                                match &res {
+                                    Ok(_) => permit.succeeded(),
+                                    // cancelled attempts and bad events say nothing about the handler's health
+                                    Err(HandlerError::Retryable { code, .. }) if code == CANCELLED => {}
+                                    Err(HandlerError::Permanent { .. }) => {}
+                                    Err(_) => permit.failed(),
+                                }
+                            }
                             match res {
                                 Ok(result) => {
-                                    let _ = store_clone.set_result(&id, result).await;
-                                    telemetry.events_processed.inc();
+                                    if record_outcome(&telemetry, &id, store_clone.set_result(&id, result, Some(rec.version)).await) {
+                                        telemetry.events_processed.inc();
+                                    }
+                                }
+                                Err(HandlerError::Retryable { code, .. }) if code == CANCELLED && queue.is_closed() => {
+                                    // cut short by shutdown: hand it back without using up a retry, so the
+                                    // next start picks it up
+                                    let err = EventError::new(ErrorKind::Retryable, "shutdown", "interrupted by shutdown");
+                                    if record_outcome(&telemetry, &id, store_clone.set_error_and_mark_received(&id, err, Some(rec.version)).await) {
+                                        tracing::info!(event_id = %id, "returned event interrupted by shutdown");
+                                        redeliver = Some(Duration::ZERO);
+                                    }
                                 }
                                 Err(err) => {
-                                    // record error and requeue if attempts < max_retries
-                                    telemetry.events_failed.inc();
+                                    // an operator taking the event away is not a handler failure
+                                    if !matches!(&err, HandlerError::Retryable { code, .. } if code == CANCELLED) {
+                                        telemetry.events_failed.inc();
+                                    }
+                                    if let HandlerError::Timeout { after } = &err {
+                                        telemetry.handler_timeouts.inc();
+                                        tracing::warn!(event_id = %id, timeout = ?after, "handler timed out");
+                                    }
+                                    // `rec` was read after the claim, so `attempts` counts the one that just failed
                                     let attempts = rec.attempts;
-                                    if attempts >= max_retries {
-                                        let _ = store_clone.set_failed(&id, err).await;
-                                    } else {
-                                        let _ = store_clone.set_error_and_mark_received(&id, err).await;
-                                        // backoff
-                                        let backoff_ms = 100u64.saturating_mul(2u64.saturating_pow((attempts.saturating_sub(1)) as u32));
-                                        let tx2 = tx_clone.clone();
-                                        let id2 = id.clone();
-                                        // When requeueing, increase queue depth
-                                        telemetry.queue_depth.inc();
-                                        tokio::spawn(async move {
-                                            sleep(Duration::from_millis(backoff_ms.max(50))).await;
-                                            let _ = tx2.send(id2).await;
-                                        });
+                                    let event_type = &rec.event.event_type;
+                                    let policy = handlers.retry_policy(event_type).unwrap_or_else(|| retry.for_type(event_type));
+                                    let elapsed = (Utc::now() - rec.attempts_started_at()).to_std().unwrap_or_default();
+                                    let previous = rec.retry_delay_ms.map(Duration::from_millis);
+                                    let next = match &err {
+                                        HandlerError::Retryable { .. } | HandlerError::Timeout { .. } => policy.next_delay(attempts, previous, elapsed),
+                                        HandlerError::RetryAfter { after, .. } => policy.delay_as_requested(attempts, *after, elapsed),
+                                        HandlerError::Permanent { .. } => None,
+                                    };
+                                    let err = EventError::from(err);
+                                    match next {
+                                        None => {
+                                            // permanent, or out of retries: the record stays in the dead-letter queue
+                                            let code = err.code.clone();
+                                            if record_outcome(&telemetry, &id, store_clone.set_failed(&id, err, Some(rec.version)).await) {
+                                                telemetry.dlq_depth.inc();
+                                                tracing::warn!(event_id = %id, attempts, code = %code, "event dead-lettered");
+                                            }
+                                        }
+                                        Some(delay) => {
+                                            if record_outcome(&telemetry, &id, store_clone.retry_later(&id, err, delay, Some(rec.version)).await) {
+                                                redeliver = Some(delay);
+                                            }
+                                        }
                                     }
                                 }
                             }
                         }
                     }
-                    Ok(false) => continue,
-                    Err(_) => continue,
+                    // someone else holds it, it is already finished, or it came early
+                    Ok(false) => {
+                        if let Ok(rec) = store_clone.get(&id).await {
+                            if let Some(at) = rec.not_before.filter(|_| matches!(rec.status, EventStatus::Received | EventStatus::Scheduled)) {
+                                redeliver = Some((at - Utc::now()).to_std().unwrap_or_default());
+                            }
+                        }
+                    }
+                    Err(e) => tracing::error!(event_id = %id, error = %e, "claim failed"),
                 }
+                settle(&*queue, &delivery, redeliver).await;
             }
-        });
+        }));
+    }
+    ProcessorHandle { workers: handles, queue, in_flight }
+}
+
+/// Controls a pool started by `run_processor_pool`.
+pub struct ProcessorHandle {
+    workers: Vec<JoinHandle<()>>,
+    queue: Arc<dyn WorkQueue>,
+    in_flight: InFlight,
+}
+
+/// What `ProcessorHandle::shutdown` had to cut short.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Handlers still running at the deadline. They were cancelled and their
+    /// events returned to `Received`.
+    pub interrupted: usize,
+    /// Ids left in the queue, retries waiting out their delay included.
+    /// Their records are `Received`, so a durable store re-enqueues them on
+    /// the next start.
+    pub queued: usize,
+}
+
+impl ProcessorHandle {
+    /// Wait for every worker to exit, which happens once the queue is
+    /// closed.
+    pub async fn join(mut self) {
+        self.join_workers().await;
+    }
+
+    /// Close the queue and give the handlers already running until
+    /// `deadline` to finish. Any still running then are cancelled and their
+    /// events returned to `Received`.
+    pub async fn shutdown(mut self, deadline: Duration) -> ShutdownReport {
+        self.queue.close();
+        let mut report = ShutdownReport::default();
+        if tokio::time::timeout(deadline, self.join_workers()).await.is_err() {
+            report.interrupted = self.in_flight.len();
+            self.in_flight.cancel_all();
+            self.join_workers().await;
+        }
+        report.queued = self.queue.len().await.unwrap_or_default();
+        report
+    }
+
+    async fn join_workers(&mut self) {
+        // pop only once finished: a JoinHandle must not be polled again after it completes
+        while let Some(worker) = self.workers.last_mut() {
+            let _ = worker.await;
+            self.workers.pop();
+        }
+    }
+}
+
+/// Ack `delivery`, or with `Some(delay)` nack it to be redelivered after
+/// `delay`.
+async fn settle(queue: &dyn WorkQueue, delivery: &Delivery, redeliver: Option<Duration>) {
+    let settled = match redeliver {
+        Some(delay) => queue.nack(delivery, delay).await,
+        None => queue.ack(delivery).await,
+    };
+    if let Err(e) = settled {
+        tracing::warn!(event_id = %delivery.id, error = %e, "could not settle queue delivery");
+    }
+}
+
+/// Log and count a failed store mutation. Returns whether it succeeded.
+///
+/// An `InvalidTransition` or `VersionConflict` means someone else changed
+/// the record after we claimed it (typically the reaper requeued it after our
+/// lease expired, or an operator intervened), so our outcome is dropped
+/// rather than overwriting theirs.
+fn record_outcome(telemetry: &Telemetry, id: &str, res: Result<(), StoreError>) -> bool {
+    match res {
+        Ok(()) => true,
+        Err(StoreError::InvalidTransition { from, to }) => {
+            telemetry.transitions_rejected.inc();
+            tracing::warn!(event_id = %id, from = %from.as_str(), to = %to.as_str(), "store rejected stale outcome");
+            false
+        }
+        Err(StoreError::VersionConflict { expected, actual }) => {
+            telemetry.transitions_rejected.inc();
+            tracing::warn!(event_id = %id, expected, actual, "record changed while processing, dropping outcome");
+            false
+        }
+        Err(e) => {
+            tracing::error!(event_id = %id, error = %e, "failed to record outcome");
+            false
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::store::MemoryStore;
+    use crate::store::{EventStore, MemoryStore};
     use crate::telemetry::Telemetry;
+    use crate::service::registry::{Fallback, HandlerOptions};
+    use crate::service::retry::{Backoff, RetryPolicy};
+    use crate::domain::error::ErrorKind;
     use crate::domain::event::{Event, EventPayload, EventType};
     use chrono::Utc;
     use serde_json::json;
-    use std::sync::Arc;
-    use tokio::sync::{mpsc, Mutex};
+    use crate::queue::MemoryQueue;
 
     #[tokio::test]
     async fn processor_retries_and_fails() {
         let store = MemoryStore::new();
-        let (tx, rx) = mpsc::channel::<String>(16);
+        let queue = MemoryQueue::new();
 
         // handler always fails
-        let handler = |_: Event| async move { Err("boom".to_string()) };
+        let handler = |_: Event| async move { Err(HandlerError::retryable("boom", "always fails")) };
 
-        // start processor pool
-        let shared_rx = Arc::new(Mutex::new(rx));
         let telemetry = Telemetry::new();
-        run_processor_pool(store.clone(), shared_rx, tx.clone(), 2, 3, telemetry.clone(), handler);
+        run_processor_pool(store.clone(), queue.clone(), 2, RetryPolicy { max_attempts: 3, ..Default::default() }.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
 
         // insert event via store directly
         let ev = Event {
@@ -120,11 +312,13 @@ mod tests {
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
         };
-        let (_rec, inserted) = store.insert_if_absent(ev.clone()).await;
+        let (_rec, inserted) = store.insert_if_absent(ev.clone()).await.unwrap();
         assert!(inserted);
         // enqueue
-        let _ = tx.send(ev.event_id.clone()).await;
+        queue.enqueue(ev.event_id.clone()).await.unwrap();
 
         // wait for processing to complete (deterministic via wait_for_status)
         let ok = store.wait_for_status(&ev.event_id, crate::domain::state::EventStatus::Failed, std::time::Duration::from_secs(5)).await;
@@ -137,4 +331,408 @@ mod tests {
         // telemetry assertions
         assert!(telemetry.events_failed.get() > 0);
     }
+
+    #[tokio::test]
+    async fn stale_outcome_is_rejected_and_counted() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+
+        // while the handler runs, its lease is reaped and another worker
+        // completes the event, so this worker's outcome arrives too late
+        let other = store.clone();
+        let handler = move |ev: Event| {
+            let other = other.clone();
+            async move {
+                other.release_expired_leases(None).await.unwrap();
+                assert!(other.claim_for_processing(&ev.event_id, "other", DEFAULT_LEASE).await.unwrap());
+                other.set_result(&ev.event_id, json!({"by": "other"}), None).await.unwrap();
+                Ok(json!({"by": "pool"}))
+            }
+        };
+
+        let telemetry = Telemetry::new();
+        run_processor_pool(store.clone(), queue.clone(), 1, RetryPolicy { max_attempts: 3, ..Default::default() }.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
+
+        let ev = Event {
+            event_id: "s2".to_string(),
+            event_type: EventType::UserLoginFailed,
+            occurred_at: Utc::now(),
+            payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
+        };
+        store.insert_if_absent(ev.clone()).await.unwrap();
+        queue.enqueue(ev.event_id.clone()).await.unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while telemetry.transitions_rejected.get() == 0 {
+            assert!(std::time::Instant::now() < deadline, "rejection was not counted");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let rec = store.get(&ev.event_id).await.unwrap();
+        assert_eq!(rec.result, Some(json!({"by": "other"})));
+        assert_eq!(telemetry.events_processed.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_follow_the_policy_for_the_event_type() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let handler = |_: Event| async move { Err(HandlerError::retryable("boom", "always fails")) };
+
+        // login failures get two attempts 30ms apart; everything else one
+        let retry = RetryPolicies::new(RetryPolicy { max_attempts: 1, ..Default::default() })
+            .with(EventType::UserLoginFailed, RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(30)), max_attempts: 2, ..Default::default() });
+        let telemetry = Telemetry::new();
+        run_processor_pool(store.clone(), queue.clone(), 1, retry, telemetry.clone(), HandlerRegistry::new().on("*", handler));
+
+        for (id, event_type) in [("p1", EventType::UserLoginFailed), ("p2", EventType::Other("misc".into()))] {
+            let ev = Event { event_id: id.to_string(), event_type, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            store.insert_if_absent(ev).await.unwrap();
+            queue.enqueue(id.to_string()).await.unwrap();
+        }
+        for id in ["p1", "p2"] {
+            assert!(store.wait_for_status(id, crate::domain::state::EventStatus::Failed, std::time::Duration::from_secs(5)).await);
+        }
+
+        let p1 = store.get("p1").await.unwrap();
+        assert_eq!(p1.attempts, 2);
+        assert_eq!(p1.retry_delay_ms, Some(30));
+        // the retry waited at least the chosen delay
+        let claims: Vec<_> = p1.history.iter().filter(|c| c.to == crate::domain::state::EventStatus::Processing).map(|c| c.at).collect();
+        assert!(claims[1] - claims[0] >= chrono::Duration::milliseconds(30));
+
+        let p2 = store.get("p2").await.unwrap();
+        assert_eq!(p2.attempts, 1);
+        assert_eq!(p2.retry_delay_ms, None);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_skip_retries_and_retry_after_is_honoured() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let handler = |ev: Event| async move {
+            match ev.event_id.as_str() {
+                "bad" => Err(HandlerError::permanent("invalid_payload", "missing user_id")),
+                _ => Err(HandlerError::retry_after(Duration::from_millis(80), "rate_limited", "slow down")),
+            }
+        };
+        let retry = RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(1)), max_delay: Duration::from_millis(1), max_attempts: 2, ..Default::default() };
+        let telemetry = Telemetry::new();
+        run_processor_pool(store.clone(), queue.clone(), 2, retry.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
+
+        for id in ["bad", "busy"] {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            store.insert_if_absent(ev).await.unwrap();
+            queue.enqueue(id.to_string()).await.unwrap();
+        }
+        for id in ["bad", "busy"] {
+            assert!(store.wait_for_status(id, crate::domain::state::EventStatus::Failed, std::time::Duration::from_secs(5)).await);
+        }
+
+        let bad = store.get("bad").await.unwrap();
+        assert_eq!(bad.attempts, 1);
+        let err = bad.last_error.unwrap();
+        assert_eq!((err.kind, err.code.as_str(), err.details.as_str()), (ErrorKind::Permanent, "invalid_payload", "missing user_id"));
+
+        // the requested delay wins over the policy's 1ms cap
+        let busy = store.get("busy").await.unwrap();
+        assert_eq!(busy.attempts, 2);
+        assert_eq!(busy.retry_delay_ms, Some(80));
+        let err = busy.last_error.unwrap();
+        assert_eq!((err.kind, err.retry_after_ms), (ErrorKind::RetryAfter, Some(80)));
+    }
+
+    #[tokio::test]
+    async fn a_scheduled_events_retry_budget_starts_when_it_comes_due() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let handler = |_: Event| async move { Err(HandlerError::retryable("boom", "always fails")) };
+        // due later than the whole retry budget lasts
+        let retry = RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(5)), max_elapsed: Some(Duration::from_millis(100)), max_attempts: 3, ..Default::default() };
+        let telemetry = Telemetry::new();
+        run_processor_pool(store.clone(), queue.clone(), 1, retry.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
+
+        let ev = Event { event_id: "later".to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+        let due = Utc::now() + chrono::Duration::milliseconds(200);
+        let (rec, _) = store.insert_if_absent_at(ev, Some(due)).await.unwrap();
+        queue.enqueue_job(crate::queue::Job::for_record(&rec)).await.unwrap();
+        assert!(store.wait_for_status("later", crate::domain::state::EventStatus::Failed, std::time::Duration::from_secs(5)).await);
+
+        let rec = store.get("later").await.unwrap();
+        assert_eq!(rec.attempts, 3);
+        assert!(rec.attempts_started_at() >= due);
+    }
+
+    #[tokio::test]
+    async fn registry_routes_by_type_and_applies_the_handlers_policy() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let failing = |_: Event| async move { Err(HandlerError::retryable("boom", "always fails")) };
+        let handlers = HandlerRegistry::new()
+            .on(EventType::UserLoginFailed, |_: Event| async move { Ok(json!({"handled": "login"})) })
+            .on_with("billing.*", failing, HandlerOptions { retry: Some(RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(1)), max_attempts: 4, ..Default::default() }), timeout: None })
+            .fallback(Fallback::Skip);
+        let telemetry = Telemetry::new();
+        run_processor_pool(store.clone(), queue.clone(), 1, RetryPolicy { max_attempts: 1, ..Default::default() }.into(), telemetry.clone(), handlers);
+
+        for (id, event_type) in [("r1", EventType::UserLoginFailed), ("r2", EventType::Other("billing.invoice".into())), ("r3", EventType::Other("misc".into()))] {
+            let ev = Event { event_id: id.to_string(), event_type, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            store.insert_if_absent(ev).await.unwrap();
+            queue.enqueue(id.to_string()).await.unwrap();
+        }
+        let timeout = std::time::Duration::from_secs(5);
+        assert!(store.wait_for_status("r1", crate::domain::state::EventStatus::Completed, timeout).await);
+        assert!(store.wait_for_status("r2", crate::domain::state::EventStatus::Failed, timeout).await);
+        assert!(store.wait_for_status("r3", crate::domain::state::EventStatus::Completed, timeout).await);
+
+        assert_eq!(store.get("r1").await.unwrap().result, Some(json!({"handled": "login"})));
+        // the registration's policy wins over the pool's single attempt
+        assert_eq!(store.get("r2").await.unwrap().attempts, 4);
+        assert!(store.get("r3").await.unwrap().result.unwrap()["skipped"].is_string());
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_then_interrupts_and_reports_what_is_left_queued() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let handler = |ev: Event| async move {
+            match ev.event_id.as_str() {
+                "quick" => {
+                    sleep(Duration::from_millis(50)).await;
+                    Ok(json!({}))
+                }
+                "stuck" => std::future::pending().await,
+                _ => Err(HandlerError::retryable("flaky", "try again later")),
+            }
+        };
+        let handlers = HandlerRegistry::new().on("*", handler);
+        let in_flight = handlers.in_flight();
+        let retry = RetryPolicy { backoff: Backoff::Constant(Duration::from_secs(60)), ..Default::default() };
+        let pool = run_processor_pool(store.clone(), queue.clone(), 3, retry.into(), Telemetry::new(), handlers);
+
+        for id in ["quick", "stuck", "flaky"] {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            store.insert_if_absent(ev).await.unwrap();
+            queue.enqueue(id.to_string()).await.unwrap();
+        }
+        // wait until the retry is scheduled and the other two are running
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while store.get("flaky").await.unwrap().retry_delay_ms.is_none() || in_flight.len() < 2 {
+            assert!(std::time::Instant::now() < deadline, "events were not picked up");
+            sleep(Duration::from_millis(5)).await;
+        }
+
+        let report = pool.shutdown(Duration::from_millis(300)).await;
+        assert_eq!(report, ShutdownReport { interrupted: 1, queued: 2 });
+        assert_eq!(store.get("quick").await.unwrap().status, crate::domain::state::EventStatus::Completed);
+        let stuck = store.get("stuck").await.unwrap();
+        assert_eq!(stuck.status, crate::domain::state::EventStatus::Received);
+        assert_eq!(stuck.last_error.unwrap().code, "shutdown");
+        assert_eq!(store.get("flaky").await.unwrap().status, crate::domain::state::EventStatus::Received);
+    }
+
+    #[tokio::test]
+    async fn attempts_cancelled_by_an_operator_are_not_counted_as_failures() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let telemetry = Telemetry::new();
+        let handlers = HandlerRegistry::new().on("*", |_: Event| std::future::pending());
+        let in_flight = handlers.in_flight();
+        run_processor_pool(store.clone(), queue.clone(), 1, RetryPolicies::default(), telemetry.clone(), handlers);
+        let ingest = crate::service::IngestService::new(store.clone(), queue.clone(), telemetry.clone()).with_in_flight(in_flight.clone());
+        let ev = Event { event_id: "op1".to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+        ingest.ingest(ev).await.unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while in_flight.is_empty() {
+            assert!(std::time::Instant::now() < deadline, "event was not picked up");
+            sleep(Duration::from_millis(5)).await;
+        }
+        ingest.fail("op1", None).await.unwrap();
+        // the cancelled handler's outcome loses to the operator's
+        while telemetry.transitions_rejected.get() == 0 {
+            assert!(std::time::Instant::now() < deadline, "cancelled attempt was not settled");
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(telemetry.events_failed.get(), 0);
+        assert_eq!(store.get("op1").await.unwrap().last_error.unwrap().code, "operator");
+    }
+
+    #[tokio::test]
+    async fn events_of_one_partition_run_in_order_and_a_failure_holds_only_its_own() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler = {
+            let log = log.clone();
+            move |ev: Event| {
+                let log = log.clone();
+                async move {
+                    log.lock().unwrap().push(format!("start {}", ev.event_id));
+                    sleep(Duration::from_millis(20)).await;
+                    log.lock().unwrap().push(format!("end {}", ev.event_id));
+                    match ev.event_id.as_str() {
+                        "u2-1" => Err(HandlerError::retryable("boom", "fails twice")),
+                        _ => Ok(json!({})),
+                    }
+                }
+            }
+        };
+        let retry = RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(50)), max_attempts: 2, ..Default::default() };
+        run_processor_pool(store.clone(), queue.clone(), 4, retry.into(), Telemetry::new(), HandlerRegistry::new().on("*", handler));
+
+        // the key comes from the payload's user_id, as configured in main
+        let ingest = crate::service::IngestService::new(store.clone(), queue.clone(), Telemetry::new()).with_partition_field(Some("user_id".to_string()));
+        for (id, user) in [("u1-1", json!("u1")), ("u2-1", json!("u2")), ("u1-2", json!("u1")), ("u2-2", json!("u2")), ("u1-3", json!("u1")), ("u3-1", json!(3))] {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::UserLoginFailed, occurred_at: Utc::now(), payload: EventPayload(json!({"user_id": user})), partition_key: None, priority: None };
+            ingest.ingest(ev).await.unwrap();
+        }
+        let timeout = std::time::Duration::from_secs(5);
+        for id in ["u1-1", "u1-2", "u1-3", "u2-2", "u3-1"] {
+            assert!(store.wait_for_status(id, crate::domain::state::EventStatus::Completed, timeout).await);
+        }
+        assert!(store.wait_for_status("u2-1", crate::domain::state::EventStatus::Failed, timeout).await);
+        assert_eq!(store.get("u3-1").await.unwrap().event.partition_key.as_deref(), Some("3"));
+
+        let log = log.lock().unwrap().clone();
+        let of = |key: &str| log.iter().filter(|l| l.contains(&format!(" {key}-"))).cloned().collect::<Vec<_>>();
+        assert_eq!(of("u1"), ["start u1-1", "end u1-1", "start u1-2", "end u1-2", "start u1-3", "end u1-3"]);
+        // u2-2 waited for both attempts of u2-1, while u3 went ahead
+        assert_eq!(of("u2"), ["start u2-1", "end u2-1", "start u2-1", "end u2-1", "start u2-2", "end u2-2"]);
+        let at = |line: &str| log.iter().position(|l| l == line).unwrap();
+        assert!(at("end u3-1") < at("start u2-2"));
+    }
+
+    #[tokio::test]
+    async fn a_bulkhead_keeps_slow_events_from_taking_every_worker() {
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new().with_concurrency_limit("slow.*", 1);
+        let running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let most = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let slow = {
+            let (running, most) = (running.clone(), most.clone());
+            move |_: Event| {
+                let (running, most) = (running.clone(), most.clone());
+                async move {
+                    let now = running.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                    most.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+                    sleep(Duration::from_millis(100)).await;
+                    running.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    Ok(json!({}))
+                }
+            }
+        };
+        let handlers = HandlerRegistry::new().on("slow.*", slow).on("*", |_: Event| async move { Ok(json!({})) });
+        run_processor_pool(store.clone(), queue.clone(), 3, RetryPolicy::default().into(), Telemetry::new(), handlers);
+
+        let ingest = crate::service::IngestService::new(store.clone(), queue.clone(), Telemetry::new());
+        for (id, event_type) in [("s1", "slow.api"), ("s2", "slow.api"), ("s3", "slow.export"), ("f1", "misc"), ("f2", "misc")] {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::Other(event_type.into()), occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            ingest.ingest(ev).await.unwrap();
+        }
+        // the fast events are not stuck behind the slow ones
+        let timeout = std::time::Duration::from_secs(5);
+        for id in ["f1", "f2"] {
+            assert!(store.wait_for_status(id, crate::domain::state::EventStatus::Completed, timeout).await);
+        }
+        assert_ne!(store.get("s3").await.unwrap().status, crate::domain::state::EventStatus::Completed);
+        for id in ["s1", "s2", "s3"] {
+            assert!(store.wait_for_status(id, crate::domain::state::EventStatus::Completed, timeout).await);
+        }
+        assert_eq!(most.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn an_open_breaker_pauses_events_without_using_up_their_attempts() {
+        use crate::domain::state::EventStatus;
+        use crate::service::{BreakerPolicy, BreakerState, Breakers};
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let telemetry = Telemetry::new();
+        let down = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let handler = {
+            let down = down.clone();
+            move |_: Event| {
+                let down = down.load(std::sync::atomic::Ordering::SeqCst);
+                async move { if down { Err(HandlerError::retryable("unavailable", "dependency is down")) } else { Ok(json!({})) } }
+            }
+        };
+        let policy = BreakerPolicy { window: 2, min_calls: 2, failure_rate: 1.0, open_for: Duration::from_millis(300), probes: 1 };
+        let breakers = Breakers::new(policy).with_metrics(telemetry.breaker_state.clone(), telemetry.breaker_opened.clone(), telemetry.breaker_rejections.clone());
+        let handlers = HandlerRegistry::new().on("billing.*", handler).with_breakers(breakers.clone());
+        let retry = RetryPolicy { backoff: Backoff::Constant(Duration::from_millis(5)), max_attempts: 3, ..Default::default() };
+        run_processor_pool(store.clone(), queue.clone(), 1, retry.into(), telemetry.clone(), handlers);
+
+        let ingest = crate::service::IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+        let ids = ["b1", "b2", "b3", "b4", "b5"];
+        for id in ids {
+            let ev = Event { event_id: id.to_string(), event_type: EventType::Other("billing.charge".into()), occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+            ingest.ingest(ev).await.unwrap();
+        }
+        // two failures open the breaker; everything else waits it out unclaimed
+        sleep(Duration::from_millis(150)).await;
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Open));
+        assert_eq!(telemetry.breaker_state.with_label_values(&["billing.*"]).get(), 2.0);
+        assert_eq!(telemetry.breaker_opened.with_label_values(&["billing.*"]).get(), 1);
+        assert!(telemetry.breaker_rejections.with_label_values(&["billing.*"]).get() > 0);
+        for id in ids {
+            let rec = store.get(id).await.unwrap();
+            assert_eq!(rec.status, EventStatus::Received);
+            assert_eq!(rec.attempts, if ["b1", "b2"].contains(&id) { 1 } else { 0 }, "{id}");
+        }
+
+        // once the dependency is back a probe closes it and the rest go through
+        down.store(false, std::sync::atomic::Ordering::SeqCst);
+        for id in ids {
+            assert!(store.wait_for_status(id, EventStatus::Completed, std::time::Duration::from_secs(5)).await, "{id}");
+        }
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Closed));
+        assert_eq!(telemetry.breaker_state.with_label_values(&["billing.*"]).get(), 0.0);
+        for id in ids {
+            assert_eq!(store.get(id).await.unwrap().attempts, if ["b1", "b2"].contains(&id) { 2 } else { 1 }, "{id}");
+        }
+        assert_eq!(telemetry.dlq_depth.get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn an_id_enqueued_without_a_group_is_held_back_once_claimed() {
+        use crate::domain::state::EventStatus;
+        use crate::service::{BreakerPolicy, BreakerState, Breakers};
+        let store = MemoryStore::new();
+        let queue = MemoryQueue::new();
+        let telemetry = Telemetry::new();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handler = {
+            let calls = calls.clone();
+            move |_: Event| {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move { Ok(json!({})) }
+            }
+        };
+        let policy = BreakerPolicy { window: 2, min_calls: 2, failure_rate: 1.0, open_for: Duration::from_millis(200), probes: 1 };
+        let breakers = Breakers::new(policy).with_metrics(telemetry.breaker_state.clone(), telemetry.breaker_opened.clone(), telemetry.breaker_rejections.clone());
+        let handlers = HandlerRegistry::new().on("billing.*", handler).with_breakers(breakers.clone());
+        for _ in 0..2 {
+            breakers.try_acquire("billing.*").unwrap().failed();
+        }
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Open));
+        run_processor_pool(store.clone(), queue.clone(), 1, RetryPolicy::default().into(), telemetry.clone(), handlers);
+
+        // promoted from the schedule with no group, as `enqueue_at` leaves it
+        let ev = Event { event_id: "bare".to_string(), event_type: EventType::Other("billing.charge".into()), occurred_at: Utc::now(), payload: EventPayload(json!({})), partition_key: None, priority: None };
+        store.insert_if_absent(ev).await.unwrap();
+        queue.enqueue_at("bare".to_string(), Utc::now() + chrono::Duration::milliseconds(20)).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+        let rec = store.get("bare").await.unwrap();
+        assert_eq!(rec.status, EventStatus::Received);
+        assert_eq!(rec.last_error.unwrap().code, "circuit_open");
+        assert!(telemetry.breaker_rejections.with_label_values(&["billing.*"]).get() > 0);
+
+        // a probe once the breaker half-opens runs it
+        assert!(store.wait_for_status("bare", EventStatus::Completed, std::time::Duration::from_secs(5)).await);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(breakers.state("billing.*"), Some(BreakerState::Closed));
+    }
 }
@@ -0,0 +1,94 @@
+use crate::queue::{Job, WorkQueue};
+use crate::store::{EventStore, StoreError};
+use crate::Telemetry;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Release claims whose lease lapsed at or before `cutoff` (all claims if
+/// `None`) and push the events back onto the work queue. Returns how many
+/// events were recovered.
+pub async fn reap_expired_leases<S: EventStore>(
+    store: &S,
+    queue: &dyn WorkQueue,
+    telemetry: &Telemetry,
+    cutoff: Option<DateTime<Utc>>,
+) -> Result<usize, StoreError> {
+    let ids = store.release_expired_leases(cutoff).await?;
+    let n = ids.len();
+    for id in ids {
+        tracing::warn!(event_id = %id, "lease expired, re-enqueueing");
+        telemetry.leases_expired.inc();
+        // keep the event in its partition and lane; if the lookup fails it
+        // is still worth retrying, just without them
+        let job = store.get(&id).await.map(|rec| Job::for_record(&rec)).unwrap_or_else(|_| Job::new(id.clone()));
+        if let Err(e) = queue.enqueue_job(job).await {
+            tracing::error!(event_id = %id, error = %e, "failed to re-enqueue event");
+        }
+    }
+    Ok(n)
+}
+
+/// Release every claim left over from a previous run, without enqueueing.
+/// Must run before the workers start: at that point no lease can be live, and
+/// the released events are picked up by `IngestService::enqueue_pending`.
+pub async fn release_orphaned_claims<S: EventStore>(store: &S, telemetry: &Telemetry) -> Result<usize, StoreError> {
+    let ids = store.release_expired_leases(None).await?;
+    for id in &ids {
+        tracing::warn!(event_id = %id, "releasing claim left over from previous run");
+    }
+    telemetry.leases_expired.inc_by(ids.len() as u64);
+    Ok(ids.len())
+}
+
+/// Periodically reap expired leases, in the store and in the queue, until
+/// the queue is closed.
+pub fn run_lease_reaper<S>(store: S, queue: impl WorkQueue, telemetry: Telemetry, every: Duration)
+where
+    S: EventStore + Clone,
+{
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if queue.is_closed() {
+                break;
+            }
+            if let Err(e) = reap_expired_leases(&store, &queue, &telemetry, Some(Utc::now())).await {
+                tracing::error!(%e, "lease reaper failed");
+            }
+            if let Err(e) = queue.release_expired().await {
+                tracing::error!(%e, "releasing expired queue leases failed");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::queue::MemoryQueue;
+    use crate::store::MemoryStore;
+    use crate::domain::state::EventStatus;
+
+    #[tokio::test]
+    async fn reaper_requeues_abandoned_claims() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        let queue = MemoryQueue::new();
+
+        store.insert_if_absent(event("r1")).await.unwrap();
+        store.insert_if_absent(event("r2")).await.unwrap();
+        // r1's worker "died" right after claiming with a tiny lease
+        store.claim_for_processing("r1", "dead-worker", Duration::from_millis(1)).await.unwrap();
+        store.claim_for_processing("r2", OWNER, LEASE).await.unwrap();
+
+        run_lease_reaper(store.clone(), queue.clone(), telemetry.clone(), Duration::from_millis(10));
+        let delivery = tokio::time::timeout(Duration::from_secs(2), queue.dequeue(LEASE)).await.unwrap().unwrap().unwrap();
+        assert_eq!(delivery.id, "r1");
+        assert_eq!(store.get("r1").await.unwrap().status, EventStatus::Received);
+        assert_eq!(store.get("r2").await.unwrap().status, EventStatus::Processing);
+        assert_eq!(telemetry.leases_expired.get(), 1);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::domain::error::HandlerError;
+use crate::domain::event::{Event, EventType};
+use crate::service::breaker::{BreakerPermit, Breakers};
+use crate::service::cancel::InFlight;
+use crate::service::retry::RetryPolicy;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Code of the retryable error an attempt ends with when its cancellation
+/// token fires.
+pub const CANCELLED: &str = "cancelled";
+
+/// Processes one event. Implemented for any `Fn(Event) -> impl Future` of the
+/// right output, so plain async closures can be registered directly; wrap one
+/// in `Cancellable` to also get the cancellation token.
+#[async_trait]
+pub trait EventHandler: Send + Sync + 'static {
+    /// `cancel` fires when the attempt times out, the service shuts down or
+    /// an operator takes the event away. The registry stops waiting for the
+    /// handler at that point, so it should wind down any work it spawned.
+    async fn handle(&self, event: Event, cancel: CancellationToken) -> Result<Value, HandlerError>;
+}
+
+#[async_trait]
+impl<F, Fut> EventHandler for F
+where
+    F: Fn(Event) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, HandlerError>> + Send + 'static,
+{
+    async fn handle(&self, event: Event, _cancel: CancellationToken) -> Result<Value, HandlerError> {
+        (self)(event).await
+    }
+}
+
+/// Adapts a `Fn(Event, CancellationToken)` closure to `EventHandler`.
+pub struct Cancellable<F>(pub F);
+
+#[async_trait]
+impl<F, Fut> EventHandler for Cancellable<F>
+where
+    F: Fn(Event, CancellationToken) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, HandlerError>> + Send + 'static,
+{
+    async fn handle(&self, event: Event, cancel: CancellationToken) -> Result<Value, HandlerError> {
+        (self.0)(event, cancel).await
+    }
+}
+
+/// An event whose payload has been decoded into `T`.
+#[derive(Debug, Clone)]
+pub struct TypedEvent<T> {
+    pub event_id: String,
+    pub event_type: EventType,
+    pub occurred_at: DateTime<Utc>,
+    pub payload: T,
+    /// See `EventHandler::handle`.
+    pub cancel: CancellationToken,
+}
+
+/// Adapts a handler taking `TypedEvent<T>` to `EventHandler`: the payload is
+/// decoded before the handler runs, and a payload that does not decode is a
+/// permanent `invalid_payload` error, since retrying cannot fix it.
+pub struct TypedHandler<T, F> {
+    handler: F,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T, F> TypedHandler<T, F> {
+    pub fn new(handler: F) -> Self {
+        Self { handler, _payload: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<T, F, Fut> EventHandler for TypedHandler<T, F>
+where
+    T: DeserializeOwned + Send + 'static,
+    F: Fn(TypedEvent<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, HandlerError>> + Send + 'static,
+{
+    async fn handle(&self, event: Event, cancel: CancellationToken) -> Result<Value, HandlerError> {
+        let payload = event.payload.decode::<T>().map_err(|e| {
+            let name = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+            HandlerError::permanent("invalid_payload", format!("payload is not a valid {name}: {e}"))
+        })?;
+        (self.handler)(TypedEvent { event_id: event.event_id, event_type: event.event_type, occurred_at: event.occurred_at, payload, cancel }).await
+    }
+}
+
+/// Which event types a registration applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Exact(EventType),
+    /// A pattern over the type's name where `*` matches any run of
+    /// characters (dots included), e.g. `billing.*`.
+    Glob(String),
+}
+
+impl From<EventType> for Route {
+    fn from(event_type: EventType) -> Self {
+        Route::Exact(event_type)
+    }
+}
+
+/// A type name, or a glob if it contains `*`.
+impl From<&str> for Route {
+    fn from(s: &str) -> Self {
+        if s.contains('*') {
+            Route::Glob(s.to_string())
+        } else {
+            Route::Exact(EventType::try_from(s.to_string()).unwrap_or_else(|_| EventType::Other(s.to_string())))
+        }
+    }
+}
+
+/// Per-registration settings; unset fields fall back to the pool's.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerOptions {
+    /// Overrides the pool's `RetryPolicies` for events this handler gets.
+    pub retry: Option<RetryPolicy>,
+    /// Give up on a call after this long, cancel it and retry it as a
+    /// `HandlerError::Timeout`. Overrides the registry's `timeout`.
+    pub timeout: Option<Duration>,
+}
+
+/// What happens to an event no registration matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fallback {
+    /// Treat it as a retryable `no_handler` error, so it is retried under
+    /// the pool's policy in case a handler is deployed in the meantime.
+    #[default]
+    Fail,
+    /// Complete it without processing; the result says it was skipped.
+    Skip,
+    /// Dead-letter it straight away with a permanent `no_handler` error.
+    DeadLetter,
+}
+
+struct Registration {
+    /// The route as registered: the type name or the glob. Names the
+    /// handler's circuit breaker.
+    name: String,
+    handler: Arc<dyn EventHandler>,
+    options: HandlerOptions,
+}
+
+/// Routes each event to the handler registered for its `EventType`. Exact
+/// registrations win over globs; among globs the first one registered that
+/// matches wins.
+#[derive(Default)]
+pub struct HandlerRegistry {
+    exact: HashMap<EventType, Registration>,
+    globs: Vec<(String, Registration)>,
+    fallback: Fallback,
+    timeout: Option<Duration>,
+    in_flight: InFlight,
+    breakers: Breakers,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle events matching `route` with `handler`.
+    pub fn on(self, route: impl Into<Route>, handler: impl EventHandler) -> Self {
+        self.on_with(route, handler, HandlerOptions::default())
+    }
+
+    /// Like `on`, with its own retry policy and timeout. Registering the same
+    /// route twice replaces the earlier handler.
+    pub fn on_with(mut self, route: impl Into<Route>, handler: impl EventHandler, options: HandlerOptions) -> Self {
+        let route = route.into();
+        let name = match &route {
+            Route::Exact(event_type) => String::from(event_type.clone()),
+            Route::Glob(pattern) => pattern.clone(),
+        };
+        self.breakers.register(&name);
+        let reg = Registration { name, handler: Arc::new(handler), options };
+        match route {
+            Route::Exact(event_type) => {
+                self.exact.insert(event_type, reg);
+            }
+            Route::Glob(pattern) => match self.globs.iter_mut().find(|(p, _)| *p == pattern) {
+                Some(existing) => existing.1 = reg,
+                None => self.globs.push((pattern, reg)),
+            },
+        }
+        self
+    }
+
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Timeout for handlers whose registration does not set one.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Track handler cancellation tokens in `in_flight`, so an operator
+    /// action or shutdown holding a clone can cancel them.
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// The `InFlight` this registry registers its handlers' tokens with.
+    pub fn in_flight(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+
+    /// Guard each handler with a circuit breaker from `breakers`. The
+    /// processor asks it before claiming an event, and holds the event back
+    /// while it is open.
+    pub fn with_breakers(mut self, breakers: Breakers) -> Self {
+        for reg in self.exact.values().chain(self.globs.iter().map(|(_, reg)| reg)) {
+            breakers.register(&reg.name);
+        }
+        self.breakers = breakers;
+        self
+    }
+
+    /// The `Breakers` guarding this registry's handlers.
+    pub fn breakers(&self) -> Breakers {
+        self.breakers.clone()
+    }
+
+    /// Let an event of `event_type` through its handler's circuit breaker.
+    /// `Err` carries how long to hold it back; an event no registration
+    /// matches has no breaker to pass.
+    pub fn admit(&self, event_type: &EventType) -> Result<Option<BreakerPermit>, Duration> {
+        match self.resolve(event_type) {
+            Some(reg) if self.breakers.is_enabled() => self.breakers.try_acquire(&reg.name).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn resolve(&self, event_type: &EventType) -> Option<&Registration> {
+        self.exact.get(event_type).or_else(|| {
+            let name = String::from(event_type.clone());
+            self.globs.iter().find(|(pattern, _)| glob_match(pattern, &name)).map(|(_, reg)| reg)
+        })
+    }
+
+    /// The retry policy registered for `event_type`'s handler, if it has one.
+    pub fn retry_policy(&self, event_type: &EventType) -> Option<&RetryPolicy> {
+        self.resolve(event_type).and_then(|reg| reg.options.retry.as_ref())
+    }
+
+    /// Run the handler for `event`, or the fallback if there is none. The
+    /// attempt ends early with `HandlerError::Timeout` when it overruns its
+    /// timeout, or with a retryable `cancelled` error when its token is
+    /// cancelled through `InFlight`.
+    pub async fn handle(&self, event: Event) -> Result<Value, HandlerError> {
+        let Some(reg) = self.resolve(&event.event_type) else {
+            let name = String::from(event.event_type);
+            return match self.fallback {
+                Fallback::Fail => Err(HandlerError::retryable("no_handler", format!("no handler registered for {name}"))),
+                Fallback::Skip => Ok(json!({"skipped": format!("no handler registered for {name}")})),
+                Fallback::DeadLetter => Err(HandlerError::permanent("no_handler", format!("no handler registered for {name}"))),
+            };
+        };
+        let guard = self.in_flight.start(&event.event_id);
+        let cancel = guard.token().clone();
+        let call = reg.handler.handle(event, cancel.clone());
+        let run = async {
+            match reg.options.timeout.or(self.timeout) {
+                Some(after) => tokio::time::timeout(after, call).await.unwrap_or(Err(HandlerError::Timeout { after })),
+                None => call.await,
+            }
+        };
+        let res = tokio::select! {
+            biased;
+            res = run => res,
+            _ = cancel.cancelled() => Err(HandlerError::retryable(CANCELLED, "handling was cancelled")),
+        };
+        if matches!(res, Err(HandlerError::Timeout { .. })) {
+            cancel.cancel();
+        }
+        res
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else { return false };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else { return rest.is_empty() };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::event::{EventPayload, UserLoginFailed};
+
+    fn event(event_type: &str) -> Event {
+        event_with(event_type, json!({}))
+    }
+
+    fn event_with(event_type: &str, payload: Value) -> Event {
+        Event {
+            event_id: "r1".to_string(),
+            event_type: EventType::try_from(event_type.to_string()).unwrap(),
+            occurred_at: Utc::now(),
+            payload: EventPayload(payload),
+            partition_key: None,
+            priority: None,
+        }
+    }
+
+    fn named(name: &'static str) -> impl EventHandler {
+        move |_: Event| async move { Ok(json!(name)) }
+    }
+
+    #[test]
+    fn globs_match_any_run_of_characters() {
+        assert!(glob_match("billing.*", "billing.invoice.paid"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.paid", "billing.invoice.paid"));
+        assert!(glob_match("b*g.*.paid", "billing.invoice.paid"));
+        assert!(!glob_match("billing.*", "billing"));
+        assert!(!glob_match("billing.*", "shipping.label"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(glob_match("user.login_failed", "user.login_failed"));
+    }
+
+    #[tokio::test]
+    async fn exact_routes_win_then_globs_in_order() {
+        let registry = HandlerRegistry::new()
+            .on("billing.*", named("billing"))
+            .on("*", named("any"))
+            .on(EventType::UserLoginFailed, named("login"))
+            .on("billing.refund", named("refund"));
+        assert_eq!(registry.handle(event("user.login_failed")).await.unwrap(), json!("login"));
+        assert_eq!(registry.handle(event("billing.refund")).await.unwrap(), json!("refund"));
+        assert_eq!(registry.handle(event("billing.invoice")).await.unwrap(), json!("billing"));
+        assert_eq!(registry.handle(event("shipping.label")).await.unwrap(), json!("any"));
+    }
+
+    #[tokio::test]
+    async fn fallback_decides_unmatched_events() {
+        let err = HandlerRegistry::new().handle(event("misc")).await.unwrap_err();
+        assert!(matches!(err, HandlerError::Retryable { code, .. } if code == "no_handler"));
+        let err = HandlerRegistry::new().fallback(Fallback::DeadLetter).handle(event("misc")).await.unwrap_err();
+        assert!(matches!(err, HandlerError::Permanent { code, .. } if code == "no_handler"));
+        let ok = HandlerRegistry::new().fallback(Fallback::Skip).handle(event("misc")).await.unwrap();
+        assert!(ok["skipped"].is_string());
+    }
+
+    #[tokio::test]
+    async fn registrations_carry_timeout_and_retry_policy() {
+        let slow = |_: Event| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(json!({}))
+        };
+        let policy = RetryPolicy { max_attempts: 9, ..Default::default() };
+        let registry = HandlerRegistry::new().on_with("slow.*", slow, HandlerOptions { retry: Some(policy.clone()), timeout: Some(Duration::from_millis(20)) });
+
+        let err = registry.handle(event("slow.job")).await.unwrap_err();
+        assert_eq!(err, HandlerError::Timeout { after: Duration::from_millis(20) });
+        assert_eq!(registry.retry_policy(&EventType::Other("slow.job".into())), Some(&policy));
+        assert_eq!(registry.retry_policy(&EventType::UserLoginFailed), None);
+    }
+
+    #[tokio::test]
+    async fn typed_handlers_decode_or_fail_permanently() {
+        let registry = HandlerRegistry::new().on(
+            EventType::UserLoginFailed,
+            TypedHandler::new(|ev: TypedEvent<UserLoginFailed>| async move { Ok(json!({"user": ev.payload.user_id, "ip": ev.payload.ip})) }),
+        );
+        let ok = registry.handle(event_with("user.login_failed", json!({"user_id": "u1", "ip": "10.0.0.1"}))).await.unwrap();
+        assert_eq!(ok, json!({"user": "u1", "ip": "10.0.0.1"}));
+
+        let err = registry.handle(event_with("user.login_failed", json!({"ip": "10.0.0.1"}))).await.unwrap_err();
+        match err {
+            HandlerError::Permanent { code, details } => {
+                assert_eq!(code, "invalid_payload");
+                assert_eq!(details, "payload is not a valid UserLoginFailed: missing field `user_id`");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_fire_on_timeout_and_on_cancel() {
+        let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+        let waits = Cancellable(move |_: Event, cancel: CancellationToken| {
+            let seen_tx = seen_tx.clone();
+            async move {
+                // hand the token out before blocking, so the test can watch it
+                seen_tx.send(cancel).unwrap();
+                std::future::pending::<()>().await;
+                Ok(json!({}))
+            }
+        });
+        let in_flight = InFlight::new();
+        let registry = Arc::new(HandlerRegistry::new().on("*", waits).timeout(Some(Duration::from_millis(20))).with_in_flight(in_flight.clone()));
+
+        // the registry default applies when the registration sets no timeout
+        let err = registry.handle(event("job")).await.unwrap_err();
+        assert_eq!(err, HandlerError::Timeout { after: Duration::from_millis(20) });
+        assert!(seen_rx.recv().await.unwrap().is_cancelled());
+        assert!(in_flight.is_empty());
+
+        // a handler that ignores its token is abandoned all the same
+        let stuck = |_: Event| std::future::pending::<Result<Value, HandlerError>>();
+        let registry = Arc::new(HandlerRegistry::new().on("*", stuck).with_in_flight(in_flight.clone()));
+        let running = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.handle(event("job")).await }
+        });
+        while !in_flight.cancel("r1") {
+            tokio::task::yield_now().await;
+        }
+        let res = running.await.unwrap();
+        assert!(matches!(res, Err(HandlerError::Retryable { code, .. }) if code == "cancelled"));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::service::refresh_dlq_depth;
+use crate::store::{EventStore, RetentionPolicy, StoreError};
+use crate::Telemetry;
+use chrono::Utc;
+use std::time::Duration;
+
+/// Apply `policy` to `store` once. Returns how many records were evicted.
+pub async fn enforce_retention<S: EventStore>(store: &S, policy: &RetentionPolicy, telemetry: &Telemetry) -> Result<usize, StoreError> {
+    let evicted = store.evict(policy, Utc::now()).await?;
+    if !evicted.is_empty() {
+        tracing::debug!(evicted = evicted.len(), "evicted finished events");
+        // some of them may have been dead letters
+        refresh_dlq_depth(store, telemetry).await?;
+    }
+    telemetry.events_evicted.inc_by(evicted.len() as u64);
+    Ok(evicted.len())
+}
+
+/// Periodically apply `policy` to `store`.
+pub fn run_retention<S>(store: S, policy: RetentionPolicy, telemetry: Telemetry, every: Duration)
+where
+    S: EventStore + Clone,
+{
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = enforce_retention(&store, &policy, &telemetry).await {
+                tracing::error!(%e, "retention pass failed");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn retention_task_evicts_and_counts() {
+        let store = MemoryStore::new();
+        let telemetry = Telemetry::new();
+        store.insert_if_absent(event("t1")).await.unwrap();
+        store.claim_for_processing("t1", OWNER, LEASE).await.unwrap();
+        store.set_result("t1", json!({}), None).await.unwrap();
+        store.insert_if_absent(event("t2")).await.unwrap();
+
+        let policy = RetentionPolicy { completed_ttl: Some(Duration::ZERO), ..Default::default() };
+        run_retention(store.clone(), policy, telemetry.clone(), Duration::from_millis(10));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while telemetry.events_evicted.get() == 0 {
+            assert!(tokio::time::Instant::now() < deadline, "nothing was evicted");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(store.get("t1").await, Err(StoreError::Evicted { .. })));
+        assert!(store.get("t2").await.is_ok());
+    }
+}
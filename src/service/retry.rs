@@ -0,0 +1,179 @@
+use crate::domain::event::EventType;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How the delay grows from one retry to the next.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backoff {
+    /// The same delay before every retry.
+    Constant(Duration),
+    /// `initial`, then `step` longer for every further retry.
+    Linear { initial: Duration, step: Duration },
+    /// `initial`, multiplied by `factor` for every further retry.
+    Exponential { initial: Duration, factor: f64 },
+    /// "Decorrelated jitter": a random delay between `base` and three times
+    /// the previous one, which spreads out retries of events that failed
+    /// together.
+    DecorrelatedJitter { base: Duration },
+}
+
+/// When and how often a failed event is retried before it is dead-lettered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    /// Upper bound on any single delay.
+    pub max_delay: Duration,
+    /// Give up once the next retry would start this long after the current
+    /// round of attempts began (see `EventRecord::attempts_started_at`).
+    pub max_elapsed: Option<Duration>,
+    /// Attempts in total, including the first; `1` never retries.
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::Exponential { initial: Duration::from_millis(100), factor: 2.0 },
+            max_delay: Duration::from_secs(60),
+            max_elapsed: None,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `attempts` attempts (counting the
+    /// one that just failed) have been made, or `None` to give up. `previous`
+    /// is the delay chosen before the last retry and `elapsed` the time since
+    /// the current round of attempts began (see
+    /// `EventRecord::attempts_started_at`); a scheduled event's wait until it
+    /// came due is not part of it.
+    pub fn next_delay(&self, attempts: u32, previous: Option<Duration>, elapsed: Duration) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        // retries made so far: 0 before the first one
+        let n = attempts.saturating_sub(1);
+        let delay = match &self.backoff {
+            Backoff::Constant(d) => *d,
+            Backoff::Linear { initial, step } => initial.saturating_add(step.saturating_mul(n)),
+            Backoff::Exponential { initial, factor } => {
+                let secs = initial.as_secs_f64() * factor.powi(n.min(i32::MAX as u32) as i32);
+                Duration::try_from_secs_f64(secs).unwrap_or(self.max_delay)
+            }
+            Backoff::DecorrelatedJitter { base } => {
+                let lo = base.as_millis() as u64;
+                let hi = previous.unwrap_or(*base).as_millis().saturating_mul(3).min(u64::MAX as u128) as u64;
+                Duration::from_millis(fastrand::u64(lo..=hi.max(lo)))
+            }
+        };
+        self.within_limits(delay.min(self.max_delay), elapsed)
+    }
+
+    /// Like `next_delay`, for a handler that asked to wait exactly `delay`
+    /// (`HandlerError::RetryAfter`). The attempt and elapsed-time limits
+    /// still apply; `max_delay` does not.
+    pub fn delay_as_requested(&self, attempts: u32, delay: Duration, elapsed: Duration) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        self.within_limits(delay, elapsed)
+    }
+
+    fn within_limits(&self, delay: Duration, elapsed: Duration) -> Option<Duration> {
+        match self.max_elapsed {
+            Some(max) if elapsed.saturating_add(delay) > max => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/// A default `RetryPolicy` plus overrides for particular event types.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    by_type: HashMap<EventType, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy) -> Self {
+        Self { default, by_type: HashMap::new() }
+    }
+
+    /// Use `policy` for events of type `event_type`.
+    pub fn with(mut self, event_type: EventType, policy: RetryPolicy) -> Self {
+        self.by_type.insert(event_type, policy);
+        self
+    }
+
+    pub fn for_type(&self, event_type: &EventType) -> &RetryPolicy {
+        self.by_type.get(event_type).unwrap_or(&self.default)
+    }
+}
+
+impl From<RetryPolicy> for RetryPolicies {
+    fn from(default: RetryPolicy) -> Self {
+        Self::new(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn delays(policy: &RetryPolicy) -> Vec<Option<Duration>> {
+        (1..=policy.max_attempts).map(|a| policy.next_delay(a, None, Duration::ZERO)).collect()
+    }
+
+    #[test]
+    fn strategies_grow_from_the_first_retry() {
+        let base = RetryPolicy { max_attempts: 4, max_delay: ms(250), ..Default::default() };
+        // the first retry waits `initial`, not twice that
+        assert_eq!(delays(&base), vec![Some(ms(100)), Some(ms(200)), Some(ms(250)), None]);
+
+        let constant = RetryPolicy { backoff: Backoff::Constant(ms(30)), ..base.clone() };
+        assert_eq!(delays(&constant), vec![Some(ms(30)), Some(ms(30)), Some(ms(30)), None]);
+
+        let linear = RetryPolicy { backoff: Backoff::Linear { initial: ms(10), step: ms(50) }, ..base.clone() };
+        assert_eq!(delays(&linear), vec![Some(ms(10)), Some(ms(60)), Some(ms(110)), None]);
+    }
+
+    #[test]
+    fn decorrelated_jitter_stays_in_bounds() {
+        let policy = RetryPolicy { backoff: Backoff::DecorrelatedJitter { base: ms(10) }, max_delay: ms(500), max_attempts: 100, ..Default::default() };
+        let mut previous = None;
+        for attempt in 1..50 {
+            let d = policy.next_delay(attempt, previous, Duration::ZERO).unwrap();
+            assert!(d >= ms(10) && d <= ms(500), "{d:?} out of bounds");
+            assert!(d <= previous.unwrap_or(ms(10)) * 3);
+            previous = Some(d);
+        }
+    }
+
+    #[test]
+    fn max_elapsed_gives_up_early() {
+        let policy = RetryPolicy { backoff: Backoff::Constant(ms(100)), max_elapsed: Some(ms(1000)), ..Default::default() };
+        assert_eq!(policy.next_delay(1, None, ms(900)), Some(ms(100)));
+        assert_eq!(policy.next_delay(1, None, ms(901)), None);
+    }
+
+    #[test]
+    fn requested_delays_skip_the_cap_but_not_the_limits() {
+        let policy = RetryPolicy { max_delay: ms(10), max_elapsed: Some(ms(1000)), max_attempts: 2, ..Default::default() };
+        assert_eq!(policy.delay_as_requested(1, ms(500), Duration::ZERO), Some(ms(500)));
+        assert_eq!(policy.delay_as_requested(1, ms(500), ms(600)), None);
+        assert_eq!(policy.delay_as_requested(2, ms(5), Duration::ZERO), None);
+    }
+
+    #[test]
+    fn overrides_apply_per_event_type() {
+        let special = RetryPolicy { max_attempts: 1, ..Default::default() };
+        let policies = RetryPolicies::default().with(EventType::Other("billing".into()), special.clone());
+        assert_eq!(policies.for_type(&EventType::Other("billing".into())), &special);
+        assert_eq!(policies.for_type(&EventType::UserLoginFailed), &RetryPolicy::default());
+    }
+}
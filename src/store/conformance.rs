@@ -0,0 +1,685 @@
+//! Behavioural tests shared by every `EventStore` backend.
+//!
+//! Each backend runs the whole suite from its own test module with
+//! `event_store_conformance!(factory)`, where `factory` is a future yielding
+//! `(store, guard)`; the guard is kept alive for the duration of the test
+//! (e.g. a temporary directory) and may be `()`.
+//!
+//! Backends outside this crate get the module and the macro with the
+//! `conformance` cargo feature; the expanded tests need `tokio` with its
+//! `macros` and `rt` features.
+
+use crate::domain::event::{Event, EventPayload, EventType, Priority};
+use chrono::TimeZone;
+use crate::domain::state::EventStatus;
+use crate::store::{EventQuery, EventStore, RetentionPolicy, StoreError};
+use chrono::Utc;
+use serde_json::json;
+use std::time::Duration;
+
+pub const OWNER: &str = "test-worker";
+pub const LEASE: Duration = Duration::from_secs(60);
+
+pub fn event(id: &str) -> Event {
+    Event {
+        event_id: id.to_string(),
+        event_type: EventType::UserLoginFailed,
+        occurred_at: Utc::now(),
+        payload: EventPayload(json!({"user_id": "u1"})),
+        partition_key: None,
+        priority: None,
+    }
+}
+
+pub async fn insert_and_get<S: EventStore>(store: S) {
+    let (rec, inserted) = store.insert_if_absent(event("c1")).await.unwrap();
+    assert!(inserted);
+    assert_eq!(rec.status, EventStatus::Received);
+    assert_eq!(rec.attempts, 0);
+
+    let got = store.get("c1").await.unwrap();
+    assert_eq!(got.event.event_id, "c1");
+    assert_eq!(got.event.event_type, EventType::UserLoginFailed);
+    assert_eq!(got.event.payload, EventPayload(json!({"user_id": "u1"})));
+    assert_eq!(got.status, EventStatus::Received);
+    assert_eq!((got.event.partition_key, got.event.priority), (None, None));
+
+    // ordering hints are kept with the event
+    store.insert_if_absent(Event { partition_key: Some("u1".to_string()), priority: Some(Priority::High), ..event("c1b") }).await.unwrap();
+    let got = store.get("c1b").await.unwrap();
+    assert_eq!((got.event.partition_key.as_deref(), got.event.priority), (Some("u1"), Some(Priority::High)));
+}
+
+pub async fn insert_is_idempotent<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c2")).await.unwrap();
+    store.claim_for_processing("c2", OWNER, LEASE).await.unwrap();
+
+    // a duplicate insert must return the stored record untouched
+    let mut dup = event("c2");
+    dup.payload = EventPayload(json!({"other": true}));
+    let (rec, inserted) = store.insert_if_absent(dup).await.unwrap();
+    assert!(!inserted);
+    assert_eq!(rec.status, EventStatus::Processing);
+    assert_eq!(rec.event.payload, EventPayload(json!({"user_id": "u1"})));
+}
+
+pub async fn get_missing_is_not_found<S: EventStore>(store: S) {
+    assert!(matches!(store.get("nope").await, Err(StoreError::NotFound)));
+}
+
+pub async fn ids_by_status_filters<S: EventStore>(store: S) {
+    store.insert_if_absent(event("s1")).await.unwrap();
+    store.insert_if_absent(event("s2")).await.unwrap();
+    store.claim_for_processing("s2", OWNER, LEASE).await.unwrap();
+
+    assert_eq!(store.ids_by_status(EventStatus::Received).await.unwrap(), vec!["s1".to_string()]);
+    assert_eq!(store.ids_by_status(EventStatus::Processing).await.unwrap(), vec!["s2".to_string()]);
+    assert!(store.ids_by_status(EventStatus::Failed).await.unwrap().is_empty());
+}
+
+pub async fn claim_is_exclusive<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c3")).await.unwrap();
+    assert!(store.claim_for_processing("c3", OWNER, LEASE).await.unwrap());
+    assert!(!store.claim_for_processing("c3", OWNER, LEASE).await.unwrap());
+
+    let got = store.get("c3").await.unwrap();
+    assert_eq!(got.status, EventStatus::Processing);
+    assert_eq!(got.attempts, 1);
+    assert_eq!(got.lease_owner.as_deref(), Some(OWNER));
+    let expires = got.lease_expires_at.expect("claim should take a lease");
+    assert!(expires > Utc::now() + chrono::Duration::seconds(30));
+}
+
+pub async fn claim_missing_is_not_found<S: EventStore>(store: S) {
+    assert!(matches!(store.claim_for_processing("nope", OWNER, LEASE).await, Err(StoreError::NotFound)));
+}
+
+pub async fn set_result_completes<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c4")).await.unwrap();
+    store.claim_for_processing("c4", OWNER, LEASE).await.unwrap();
+    store.set_result("c4", json!({"ok": true}), None).await.unwrap();
+
+    let got = store.get("c4").await.unwrap();
+    assert_eq!(got.status, EventStatus::Completed);
+    assert_eq!(got.result, Some(json!({"ok": true})));
+    assert!(got.lease_owner.is_none() && got.lease_expires_at.is_none());
+}
+
+pub async fn set_failed_marks_failed<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c5")).await.unwrap();
+    store.claim_for_processing("c5", OWNER, LEASE).await.unwrap();
+    store.set_failed("c5", "boom".into(), None).await.unwrap();
+
+    let got = store.get("c5").await.unwrap();
+    assert_eq!(got.status, EventStatus::Failed);
+    assert_eq!(got.last_error.as_ref().map(|e| e.details.as_str()), Some("boom"));
+}
+
+pub async fn retry_roundtrip<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c6")).await.unwrap();
+    store.claim_for_processing("c6", OWNER, LEASE).await.unwrap();
+    store.set_error_and_mark_received("c6", "transient".into(), None).await.unwrap();
+
+    let got = store.get("c6").await.unwrap();
+    assert_eq!(got.status, EventStatus::Received);
+    assert_eq!(got.last_error.as_ref().map(|e| e.details.as_str()), Some("transient"));
+
+    // the record can be claimed again and attempts keep counting
+    assert!(store.claim_for_processing("c6", OWNER, LEASE).await.unwrap());
+    assert_eq!(store.get("c6").await.unwrap().attempts, 2);
+}
+
+pub async fn retry_later_records_delay<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c7")).await.unwrap();
+    store.claim_for_processing("c7", OWNER, LEASE).await.unwrap();
+    assert!(matches!(store.retry_later("c7", "slow".into(), Duration::from_millis(250), Some(1)).await, Err(StoreError::VersionConflict { .. })));
+    store.retry_later("c7", "slow".into(), Duration::from_millis(250), Some(2)).await.unwrap();
+
+    let got = store.get("c7").await.unwrap();
+    assert_eq!(got.status, EventStatus::Received);
+    assert_eq!(got.last_error.as_ref().map(|e| e.details.as_str()), Some("slow"));
+    assert_eq!(got.retry_delay_ms, Some(250));
+    assert_eq!(got.history.last().unwrap().detail.as_deref(), Some("error: slow"));
+
+    // not claimable until the delay has passed
+    let not_before = got.not_before.expect("retry should be held off");
+    assert!(not_before >= got.updated_at + chrono::Duration::milliseconds(200));
+    assert!(!store.claim_for_processing("c7", OWNER, LEASE).await.unwrap());
+    tokio::time::sleep((not_before - Utc::now()).to_std().unwrap_or_default()).await;
+    assert!(store.claim_for_processing("c7", OWNER, LEASE).await.unwrap());
+    assert_eq!(store.get("c7").await.unwrap().not_before, None);
+
+    // a replay starts over without a previous delay
+    store.set_failed("c7", "gave up".into(), None).await.unwrap();
+    store.replay("c7", None).await.unwrap();
+    assert_eq!(store.get("c7").await.unwrap().retry_delay_ms, None);
+}
+
+pub async fn scheduled_records_wait_and_can_be_cancelled<S: EventStore>(store: S) {
+    let at = Utc::now() + chrono::Duration::milliseconds(100);
+    let (rec, inserted) = store.insert_if_absent_at(event("s1"), Some(at)).await.unwrap();
+    assert!(inserted);
+    assert_eq!((rec.status, rec.not_before), (EventStatus::Scheduled, Some(at)));
+    store.insert_if_absent_at(event("s2"), Some(at)).await.unwrap();
+    // a time already past is not scheduled at all
+    let (rec, _) = store.insert_if_absent_at(event("s3"), Some(Utc::now() - chrono::Duration::seconds(1))).await.unwrap();
+    assert_eq!((rec.status, rec.not_before), (EventStatus::Received, None));
+
+    let got = store.get("s1").await.unwrap();
+    assert_eq!((got.status, got.not_before), (EventStatus::Scheduled, Some(at)));
+    assert_eq!(got.history[0].to, EventStatus::Scheduled);
+    assert_eq!(store.ids_by_status(EventStatus::Scheduled).await.unwrap().len(), 2);
+    assert!(!store.claim_for_processing("s1", OWNER, LEASE).await.unwrap());
+
+    assert!(matches!(store.cancel("s2", Some(2)).await, Err(StoreError::VersionConflict { .. })));
+    store.cancel("s2", Some(1)).await.unwrap();
+    assert_eq!(store.get("s2").await.unwrap().status, EventStatus::Cancelled);
+    assert!(matches!(store.cancel("s2", None).await, Err(StoreError::InvalidTransition { from: EventStatus::Cancelled, .. })));
+    assert!(matches!(store.cancel("s3", None).await, Err(StoreError::InvalidTransition { from: EventStatus::Received, .. })));
+
+    tokio::time::sleep((at - Utc::now()).to_std().unwrap_or_default()).await;
+    assert!(store.claim_for_processing("s1", OWNER, LEASE).await.unwrap());
+    let got = store.get("s1").await.unwrap();
+    assert_eq!((got.status, got.not_before, got.attempts), (EventStatus::Processing, None, 1));
+    assert_eq!(got.history.last().unwrap().from, Some(EventStatus::Scheduled));
+    assert!(store.claim_for_processing("s2", OWNER, LEASE).await.is_ok_and(|claimed| !claimed));
+}
+
+pub async fn release_expired_leases_requeues<S: EventStore>(store: S) {
+    store.insert_if_absent(event("l1")).await.unwrap();
+    store.insert_if_absent(event("l2")).await.unwrap();
+    store.insert_if_absent(event("l3")).await.unwrap();
+    store.claim_for_processing("l1", "short", Duration::from_millis(1)).await.unwrap();
+    store.claim_for_processing("l2", OWNER, LEASE).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    // only the lapsed lease is released
+    let released = store.release_expired_leases(Some(Utc::now())).await.unwrap();
+    assert_eq!(released, vec!["l1".to_string()]);
+    let l1 = store.get("l1").await.unwrap();
+    assert_eq!(l1.status, EventStatus::Received);
+    assert!(l1.lease_owner.is_none() && l1.lease_expires_at.is_none());
+    assert!(l1.last_error.unwrap().details.contains("short"));
+    assert_eq!(store.get("l2").await.unwrap().status, EventStatus::Processing);
+    assert_eq!(store.get("l3").await.unwrap().status, EventStatus::Received);
+
+    // without a cutoff every claim is released
+    let released = store.release_expired_leases(None).await.unwrap();
+    assert_eq!(released, vec!["l2".to_string()]);
+    assert!(store.claim_for_processing("l2", OWNER, LEASE).await.unwrap());
+    assert_eq!(store.get("l2").await.unwrap().attempts, 2);
+}
+
+pub async fn illegal_transitions_are_rejected<S: EventStore>(store: S) {
+    store.insert_if_absent(event("x1")).await.unwrap();
+
+    // nothing can finish an event that was never claimed
+    let err = store.set_result("x1", json!({}), None).await.unwrap_err();
+    assert!(matches!(err, StoreError::InvalidTransition { from: EventStatus::Received, to: EventStatus::Completed }));
+    assert!(matches!(store.set_error_and_mark_received("x1", "e".into(), None).await, Err(StoreError::InvalidTransition { .. })));
+
+    // a late worker cannot flip a completed event
+    store.claim_for_processing("x1", OWNER, LEASE).await.unwrap();
+    store.set_result("x1", json!({"ok": true}), None).await.unwrap();
+    let err = store.set_failed("x1", "late".into(), None).await.unwrap_err();
+    assert!(matches!(err, StoreError::InvalidTransition { from: EventStatus::Completed, to: EventStatus::Failed }));
+    assert!(matches!(store.set_error_and_mark_received("x1", "late".into(), None).await, Err(StoreError::InvalidTransition { .. })));
+    assert!(!store.claim_for_processing("x1", OWNER, LEASE).await.unwrap());
+
+    let got = store.get("x1").await.unwrap();
+    assert_eq!(got.status, EventStatus::Completed);
+    assert_eq!(got.result, Some(json!({"ok": true})));
+    assert!(got.last_error.is_none());
+}
+
+pub async fn versions_increase_on_every_change<S: EventStore>(store: S) {
+    let (rec, _) = store.insert_if_absent(event("v1")).await.unwrap();
+    assert_eq!(rec.version, 1);
+    store.claim_for_processing("v1", OWNER, LEASE).await.unwrap();
+    assert_eq!(store.get("v1").await.unwrap().version, 2);
+    store.set_error_and_mark_received("v1", "transient".into(), Some(2)).await.unwrap();
+    assert_eq!(store.get("v1").await.unwrap().version, 3);
+    store.claim_for_processing("v1", OWNER, LEASE).await.unwrap();
+    store.release_expired_leases(None).await.unwrap();
+    assert_eq!(store.get("v1").await.unwrap().version, 5);
+
+    // rejected mutations leave the version alone
+    assert!(store.set_result("v1", json!({}), None).await.is_err());
+    assert_eq!(store.get("v1").await.unwrap().version, 5);
+}
+
+pub async fn stale_version_is_a_conflict<S: EventStore>(store: S) {
+    store.insert_if_absent(event("v2")).await.unwrap();
+    store.claim_for_processing("v2", OWNER, LEASE).await.unwrap();
+    let seen = store.get("v2").await.unwrap().version;
+
+    let err = store.set_result("v2", json!({}), Some(seen - 1)).await.unwrap_err();
+    assert!(matches!(err, StoreError::VersionConflict { expected, actual } if expected == seen - 1 && actual == seen));
+    assert!(matches!(store.set_failed("v2", "x".into(), Some(seen + 1)).await, Err(StoreError::VersionConflict { .. })));
+    let got = store.get("v2").await.unwrap();
+    assert_eq!(got.status, EventStatus::Processing);
+    assert_eq!(got.version, seen);
+
+    store.set_result("v2", json!({"ok": true}), Some(seen)).await.unwrap();
+    // the first writer wins; a second one holding the same version loses
+    let err = store.set_failed("v2", "late".into(), Some(seen)).await.unwrap_err();
+    assert!(matches!(err, StoreError::VersionConflict { .. }));
+}
+
+pub async fn history_records_every_change<S: EventStore>(store: S) {
+    store.insert_if_absent(event("h1")).await.unwrap();
+    store.claim_for_processing("h1", OWNER, LEASE).await.unwrap();
+    store.set_error_and_mark_received("h1", "transient".into(), None).await.unwrap();
+    store.claim_for_processing("h1", "other-worker", LEASE).await.unwrap();
+    store.release_expired_leases(None).await.unwrap();
+    store.claim_for_processing("h1", OWNER, LEASE).await.unwrap();
+    store.set_result("h1", json!({"ok": true}), None).await.unwrap();
+    // rejected mutations are not recorded
+    assert!(store.set_failed("h1", "late".into(), None).await.is_err());
+
+    let history = store.get("h1").await.unwrap().history;
+    let steps: Vec<_> = history.iter().map(|c| (c.from, c.to, c.attempt, c.worker.as_deref(), c.detail.as_deref())).collect();
+    use EventStatus::*;
+    assert_eq!(
+        steps,
+        vec![
+            (None, Received, 0, None, None),
+            (Some(Received), Processing, 1, Some(OWNER), None),
+            (Some(Processing), Received, 1, Some(OWNER), Some("error: transient")),
+            (Some(Received), Processing, 2, Some("other-worker"), None),
+            (Some(Processing), Received, 2, Some("other-worker"), Some("lease_expired: lease held by other-worker expired")),
+            (Some(Received), Processing, 3, Some(OWNER), None),
+            (Some(Processing), Completed, 3, Some(OWNER), Some(r#"{"ok":true}"#)),
+        ]
+    );
+    assert!(history.windows(2).all(|w| w[0].at <= w[1].at));
+}
+
+pub async fn history_truncates_long_details<S: EventStore>(store: S) {
+    store.insert_if_absent(event("h2")).await.unwrap();
+    store.claim_for_processing("h2", OWNER, LEASE).await.unwrap();
+    let err = "x".repeat(10_000);
+    store.set_failed("h2", err.clone().into(), None).await.unwrap();
+
+    let rec = store.get("h2").await.unwrap();
+    assert_eq!(rec.last_error.as_ref().map(|e| e.details.as_str()), Some(err.as_str()));
+    let detail = rec.history.last().unwrap().detail.clone().unwrap();
+    assert!(detail.len() < 1_000 && detail.starts_with("error: xxx"));
+}
+
+async fn finish<S: EventStore>(store: &S, id: &str, ok: bool) {
+    store.insert_if_absent(event(id)).await.unwrap();
+    store.claim_for_processing(id, OWNER, LEASE).await.unwrap();
+    if ok {
+        store.set_result(id, json!({}), None).await.unwrap();
+    } else {
+        store.set_failed(id, "boom".into(), None).await.unwrap();
+    }
+}
+
+pub async fn evict_applies_ttls_and_keeps_ids<S: EventStore>(store: S) {
+    finish(&store, "e1", true).await;
+    finish(&store, "e2", false).await;
+    store.insert_if_absent(event("e3")).await.unwrap();
+    let hour = Duration::from_secs(3600);
+    let policy = RetentionPolicy { completed_ttl: Some(Duration::ZERO), failed_ttl: Some(hour), idempotency_window: Some(hour * 2), ..Default::default() };
+    let now = Utc::now();
+
+    assert_eq!(store.evict(&policy, now).await.unwrap(), vec!["e1".to_string()]);
+    assert!(matches!(store.get("e1").await, Err(StoreError::Evicted { status: EventStatus::Completed })));
+    assert!(matches!(store.insert_if_absent(event("e1")).await, Err(StoreError::Evicted { .. })));
+    assert_eq!(store.get("e2").await.unwrap().status, EventStatus::Failed);
+
+    // an hour later the failed record goes too; pending work never does
+    let later = now + chrono::Duration::minutes(90);
+    assert_eq!(store.evict(&policy, later).await.unwrap(), vec!["e2".to_string()]);
+    assert!(matches!(store.get("e2").await, Err(StoreError::Evicted { status: EventStatus::Failed })));
+    assert_eq!(store.get("e3").await.unwrap().status, EventStatus::Received);
+
+    // once the idempotency window has passed the ids are forgotten
+    store.evict(&policy, now + chrono::Duration::hours(3)).await.unwrap();
+    assert!(matches!(store.get("e1").await, Err(StoreError::NotFound)));
+    let (_, inserted) = store.insert_if_absent(event("e1")).await.unwrap();
+    assert!(inserted);
+}
+
+pub async fn replay_resets_dead_letters<S: EventStore>(store: S) {
+    finish(&store, "r1", false).await;
+    finish(&store, "r2", true).await;
+    let failed = store.get("r1").await.unwrap();
+
+    assert!(matches!(store.replay("r1", Some(failed.version - 1)).await, Err(StoreError::VersionConflict { .. })));
+    assert!(matches!(store.replay("r2", None).await, Err(StoreError::InvalidTransition { from: EventStatus::Completed, to: EventStatus::Received })));
+    assert!(matches!(store.replay("nope", None).await, Err(StoreError::NotFound)));
+
+    store.replay("r1", Some(failed.version)).await.unwrap();
+    let rec = store.get("r1").await.unwrap();
+    assert_eq!(rec.status, EventStatus::Received);
+    assert_eq!(rec.attempts, 0);
+    assert_eq!(rec.last_error, None);
+    assert_eq!(rec.version, failed.version + 1);
+    // the failed attempt is still in the history
+    let last = rec.history.last().unwrap();
+    assert_eq!((last.from, last.to, last.attempt), (Some(EventStatus::Failed), EventStatus::Received, 0));
+    assert_eq!(last.detail.as_deref(), Some(crate::domain::event::REPLAYED));
+    assert_eq!(rec.history.len(), failed.history.len() + 1);
+
+    // and it can be claimed again
+    assert!(store.claim_for_processing("r1", OWNER, LEASE).await.unwrap());
+    assert_eq!(store.get("r1").await.unwrap().attempts, 1);
+}
+
+pub async fn purge_removes_only_dead_letters<S: EventStore>(store: S) {
+    finish(&store, "p1", false).await;
+    finish(&store, "p2", true).await;
+
+    assert!(matches!(store.purge("p2", None).await, Err(StoreError::NotDeadLettered { status: EventStatus::Completed })));
+    assert!(matches!(store.purge("p1", Some(1)).await, Err(StoreError::VersionConflict { .. })));
+    store.purge("p1", None).await.unwrap();
+
+    assert!(matches!(store.get("p1").await, Err(StoreError::Evicted { status: EventStatus::Failed })));
+    assert!(matches!(store.insert_if_absent(event("p1")).await, Err(StoreError::Evicted { .. })));
+    assert!(store.ids_by_status(EventStatus::Failed).await.unwrap().is_empty());
+    assert_eq!(store.get("p2").await.unwrap().status, EventStatus::Completed);
+}
+
+pub async fn discard_rolls_back_an_insert<S: EventStore>(store: S) {
+    let (rec, _) = store.insert_if_absent(event("d1")).await.unwrap();
+    assert!(matches!(store.discard("d1", rec.version + 1).await, Err(StoreError::VersionConflict { .. })));
+    store.discard("d1", rec.version).await.unwrap();
+
+    // gone without a trace, so the id can be ingested again
+    assert!(matches!(store.get("d1").await, Err(StoreError::NotFound)));
+    assert!(store.ids_by_status(EventStatus::Received).await.unwrap().is_empty());
+    assert!(store.insert_if_absent(event("d1")).await.unwrap().1);
+    assert_eq!(store.get("d1").await.unwrap().history.len(), 1);
+
+    // a record a worker has touched stays
+    assert!(store.claim_for_processing("d1", OWNER, LEASE).await.unwrap());
+    assert!(matches!(store.discard("d1", rec.version).await, Err(StoreError::VersionConflict { .. })));
+    assert!(matches!(store.discard("missing", 1).await, Err(StoreError::NotFound)));
+}
+
+pub async fn evict_caps_record_count<S: EventStore>(store: S) {
+    for id in ["k1", "k2", "k3"] {
+        finish(&store, id, true).await;
+    }
+    store.insert_if_absent(event("k4")).await.unwrap();
+    let policy = RetentionPolicy { max_records: Some(2), ..Default::default() };
+
+    assert_eq!(store.evict(&policy, Utc::now()).await.unwrap(), vec!["k1".to_string(), "k2".to_string()]);
+    assert!(matches!(store.get("k1").await, Err(StoreError::Evicted { .. })));
+    assert!(store.get("k3").await.is_ok());
+    assert!(store.get("k4").await.is_ok());
+    assert_eq!(list_all(&store, EventQuery { limit: 10, ..Default::default() }).await, vec!["k3", "k4"]);
+    let completed = EventQuery { status: Some(EventStatus::Completed), limit: 10, ..Default::default() };
+    assert_eq!(list_all(&store, completed).await, vec!["k3"]);
+    assert!(store.evict(&policy, Utc::now()).await.unwrap().is_empty());
+}
+
+/// Ids of every record matching `query`, following cursors page by page.
+async fn list_all<S: EventStore>(store: &S, mut query: EventQuery) -> Vec<String> {
+    let mut ids = Vec::new();
+    loop {
+        let page = store.list(&query).await.unwrap();
+        assert!(page.records.len() <= query.limit.max(1));
+        ids.extend(page.records.into_iter().map(|r| r.event.event_id));
+        match page.next_cursor {
+            Some(c) => query.after = Some(c),
+            None => return ids,
+        }
+    }
+}
+
+pub async fn list_filters_and_pages<S: EventStore>(store: S) {
+    for (i, id) in ["l1", "l2", "l3", "l4", "l5"].into_iter().enumerate() {
+        let mut ev = event(id);
+        ev.occurred_at = Utc.with_ymd_and_hms(2024, 1, 1 + i as u32, 0, 0, 0).unwrap();
+        if id == "l5" {
+            ev.event_type = EventType::Other("billing.charge".into());
+        }
+        store.insert_if_absent(ev).await.unwrap();
+    }
+    store.claim_for_processing("l2", OWNER, LEASE).await.unwrap();
+    store.claim_for_processing("l4", OWNER, LEASE).await.unwrap();
+    store.set_error_and_mark_received("l4", "transient".into(), None).await.unwrap();
+    store.claim_for_processing("l4", OWNER, LEASE).await.unwrap();
+    let all = |limit| EventQuery { limit, ..Default::default() };
+
+    let first = store.list(&all(2)).await.unwrap();
+    assert_eq!(first.records.iter().map(|r| r.event.event_id.as_str()).collect::<Vec<_>>(), vec!["l1", "l2"]);
+    assert!(first.next_cursor.is_some());
+    assert_eq!(list_all(&store, all(2)).await, vec!["l1", "l2", "l3", "l4", "l5"]);
+    assert!(store.list(&all(5)).await.unwrap().next_cursor.is_none());
+
+    let processing = EventQuery { status: Some(EventStatus::Processing), limit: 1, ..Default::default() };
+    assert_eq!(list_all(&store, processing).await, vec!["l2", "l4"]);
+    let billing = EventQuery { event_type: Some(EventType::Other("billing.charge".into())), limit: 10, ..Default::default() };
+    assert_eq!(list_all(&store, billing).await, vec!["l5"]);
+    let retried = EventQuery { min_attempts: Some(2), limit: 10, ..Default::default() };
+    assert_eq!(list_all(&store, retried).await, vec!["l4"]);
+    let occurred = EventQuery {
+        occurred_from: Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap()),
+        occurred_to: Some(Utc.with_ymd_and_hms(2024, 1, 4, 0, 0, 0).unwrap()),
+        limit: 10,
+        ..Default::default()
+    };
+    assert_eq!(list_all(&store, occurred).await, vec!["l2", "l3"]);
+
+    let l3 = store.get("l3").await.unwrap().created_at;
+    let later = EventQuery { created_from: Some(l3), limit: 2, ..Default::default() };
+    assert_eq!(list_all(&store, later).await, vec!["l3", "l4", "l5"]);
+    let earlier = EventQuery { created_to: Some(l3), status: Some(EventStatus::Received), limit: 2, ..Default::default() };
+    assert_eq!(list_all(&store, earlier).await, vec!["l1"]);
+}
+
+pub async fn list_pages_are_stable_under_changes<S: EventStore>(store: S) {
+    for id in ["p1", "p2", "p3", "p4"] {
+        store.insert_if_absent(event(id)).await.unwrap();
+    }
+    let query = EventQuery { limit: 2, ..Default::default() };
+    let first = store.list(&query).await.unwrap();
+
+    // records already returned change and new ones arrive between pages
+    store.claim_for_processing("p1", OWNER, LEASE).await.unwrap();
+    store.insert_if_absent(event("p5")).await.unwrap();
+    let second = store.list(&EventQuery { after: first.next_cursor, ..query }).await.unwrap();
+    assert_eq!(second.records.iter().map(|r| r.event.event_id.as_str()).collect::<Vec<_>>(), vec!["p3", "p4"]);
+}
+
+pub async fn mutations_on_missing_are_not_found<S: EventStore>(store: S) {
+    assert!(matches!(store.set_result("nope", json!({}), None).await, Err(StoreError::NotFound)));
+    assert!(matches!(store.set_failed("nope", "x".into(), None).await, Err(StoreError::NotFound)));
+    assert!(matches!(store.set_error_and_mark_received("nope", "x".into(), None).await, Err(StoreError::NotFound)));
+}
+
+pub async fn wait_for_status_observes_change<S: EventStore + Clone>(store: S) {
+    store.insert_if_absent(event("c7")).await.unwrap();
+    let writer = store.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        writer.claim_for_processing("c7", OWNER, LEASE).await.unwrap();
+        writer.set_result("c7", json!({}), None).await.unwrap();
+    });
+    assert!(store.wait_for_status("c7", EventStatus::Completed, Duration::from_secs(5)).await);
+}
+
+pub async fn wait_for_status_times_out<S: EventStore>(store: S) {
+    store.insert_if_absent(event("c8")).await.unwrap();
+    assert!(!store.wait_for_status("c8", EventStatus::Completed, Duration::from_millis(50)).await);
+}
+
+/// Expand the conformance suite into `#[tokio::test]` functions for a backend.
+#[macro_export]
+macro_rules! event_store_conformance {
+    ($factory:expr) => {
+        mod conformance {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[tokio::test]
+            async fn insert_and_get() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::insert_and_get(store).await;
+            }
+
+            #[tokio::test]
+            async fn insert_is_idempotent() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::insert_is_idempotent(store).await;
+            }
+
+            #[tokio::test]
+            async fn get_missing_is_not_found() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::get_missing_is_not_found(store).await;
+            }
+
+            #[tokio::test]
+            async fn ids_by_status_filters() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::ids_by_status_filters(store).await;
+            }
+
+            #[tokio::test]
+            async fn claim_is_exclusive() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::claim_is_exclusive(store).await;
+            }
+
+            #[tokio::test]
+            async fn claim_missing_is_not_found() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::claim_missing_is_not_found(store).await;
+            }
+
+            #[tokio::test]
+            async fn set_result_completes() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::set_result_completes(store).await;
+            }
+
+            #[tokio::test]
+            async fn set_failed_marks_failed() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::set_failed_marks_failed(store).await;
+            }
+
+            #[tokio::test]
+            async fn retry_roundtrip() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::retry_roundtrip(store).await;
+            }
+
+            #[tokio::test]
+            async fn release_expired_leases_requeues() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::release_expired_leases_requeues(store).await;
+            }
+
+            #[tokio::test]
+            async fn illegal_transitions_are_rejected() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::illegal_transitions_are_rejected(store).await;
+            }
+
+            #[tokio::test]
+            async fn versions_increase_on_every_change() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::versions_increase_on_every_change(store).await;
+            }
+
+            #[tokio::test]
+            async fn stale_version_is_a_conflict() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::stale_version_is_a_conflict(store).await;
+            }
+
+            #[tokio::test]
+            async fn history_records_every_change() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::history_records_every_change(store).await;
+            }
+
+            #[tokio::test]
+            async fn history_truncates_long_details() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::history_truncates_long_details(store).await;
+            }
+
+            #[tokio::test]
+            async fn evict_applies_ttls_and_keeps_ids() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::evict_applies_ttls_and_keeps_ids(store).await;
+            }
+
+            #[tokio::test]
+            async fn retry_later_records_delay() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::retry_later_records_delay(store).await;
+            }
+
+            #[tokio::test]
+            async fn scheduled_records_wait_and_can_be_cancelled() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::scheduled_records_wait_and_can_be_cancelled(store).await;
+            }
+
+            #[tokio::test]
+            async fn replay_resets_dead_letters() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::replay_resets_dead_letters(store).await;
+            }
+
+            #[tokio::test]
+            async fn purge_removes_only_dead_letters() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::purge_removes_only_dead_letters(store).await;
+            }
+
+            #[tokio::test]
+            async fn discard_rolls_back_an_insert() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::discard_rolls_back_an_insert(store).await;
+            }
+
+            #[tokio::test]
+            async fn evict_caps_record_count() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::evict_caps_record_count(store).await;
+            }
+
+            #[tokio::test]
+            async fn list_filters_and_pages() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::list_filters_and_pages(store).await;
+            }
+
+            #[tokio::test]
+            async fn list_pages_are_stable_under_changes() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::list_pages_are_stable_under_changes(store).await;
+            }
+
+            #[tokio::test]
+            async fn mutations_on_missing_are_not_found() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::mutations_on_missing_are_not_found(store).await;
+            }
+
+            #[tokio::test]
+            async fn wait_for_status_observes_change() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::wait_for_status_observes_change(store).await;
+            }
+
+            #[tokio::test]
+            async fn wait_for_status_times_out() {
+                let (store, _guard) = $factory.await;
+                $crate::store::conformance::wait_for_status_times_out(store).await;
+            }
+        }
+    };
+}
+
+pub use crate::event_store_conformance;
@@ -0,0 +1,55 @@
+use crate::domain::error::DomainError;
+use crate::domain::state::EventStatus;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("not found")]
+    NotFound,
+
+    /// The mutation is not allowed from the record's current status, e.g. a
+    /// late worker trying to fail an event that already completed.
+    #[error("invalid transition from {from:?} to {to:?}")]
+    InvalidTransition { from: EventStatus, to: EventStatus },
+
+    /// The record was removed by the retention policy; only its id (and the
+    /// status it finished in) is still known, within the idempotency window.
+    #[error("event was {status:?} and has since been evicted")]
+    Evicted { status: EventStatus },
+
+    /// A compare-and-set update named a `version` the record has already
+    /// moved past, i.e. someone else changed it in the meantime.
+    #[error("version conflict: expected {expected}, found {actual}")]
+    VersionConflict { expected: u64, actual: u64 },
+
+    /// A dead-letter operation named a record that is not dead-lettered
+    /// (not `Failed`).
+    #[error("event is {status:?}, not in the dead-letter queue")]
+    NotDeadLettered { status: EventStatus },
+
+    /// The underlying storage failed (I/O, serialization, database errors).
+    #[error("storage backend error: {0}")]
+    Backend(String),
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+impl From<DomainError> for StoreError {
+    fn from(e: DomainError) -> Self {
+        match e {
+            DomainError::NotFound => StoreError::NotFound,
+            DomainError::InvalidTransition { from, to } => StoreError::InvalidTransition { from, to },
+            DomainError::VersionConflict { expected, actual } => StoreError::VersionConflict { expected, actual },
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::domain::error::EventError;
+use crate::domain::event::{Event, EventRecord};
+use crate::domain::state::EventStatus;
+use crate::store::{EventPage, EventQuery, RetentionPolicy, StoreError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Storage abstraction used by the ingest service, the processor pool and the
+/// HTTP layer. `MemoryStore` is the reference implementation; every backend is
+/// expected to pass the shared conformance suite in `store::conformance`.
+#[async_trait]
+pub trait EventStore: Send + Sync + 'static {
+    /// Insert if absent. Returns true if inserted, false if already existed.
+    /// An event evicted within the idempotency window is rejected with
+    /// `StoreError::Evicted` rather than inserted again.
+    async fn insert_if_absent(&self, event: Event) -> Result<(EventRecord, bool), StoreError> {
+        self.insert_if_absent_at(event, None).await
+    }
+
+    /// Like `insert_if_absent`, holding the new record `Scheduled` until
+    /// `process_after` if that is in the future (see
+    /// `EventRecord::scheduled`). An existing record is left as it is.
+    async fn insert_if_absent_at(&self, event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), StoreError>;
+
+    async fn get(&self, id: &str) -> Result<EventRecord, StoreError>;
+
+    /// Ids of all records currently in `status`, e.g. to re-enqueue `Received`
+    /// work after a restart.
+    async fn ids_by_status(&self, status: EventStatus) -> Result<Vec<String>, StoreError>;
+
+    /// Records matching `query`, one page at a time, in `(created_at,
+    /// event_id)` order. Backends keep indexes so that filtering by status
+    /// does not scan every record.
+    async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError>;
+
+    /// Claim for processing: move Received (or Scheduled) -> Processing,
+    /// increment attempts and take a lease for `owner` lasting `lease`,
+    /// atomically. A record whose `not_before` has not passed yet is not
+    /// claimed.
+    async fn claim_for_processing(&self, id: &str, owner: &str, lease: Duration) -> Result<bool, StoreError>;
+
+    /// Mark `Completed` with `result`.
+    ///
+    /// This and the other outcome mutations take the record `version` the
+    /// caller last saw; if the record has changed since, they fail with
+    /// `StoreError::VersionConflict` and leave it untouched. `None` skips the
+    /// check.
+    async fn set_result(&self, id: &str, result: Value, expected: Option<u64>) -> Result<(), StoreError>;
+
+    async fn set_failed(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Record an error and move back to `Received` for retry.
+    async fn set_error_and_mark_received(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Like `set_error_and_mark_received`, also recording on the record the
+    /// backoff `delay` chosen before the next attempt.
+    async fn retry_later(&self, id: &str, err: EventError, delay: Duration, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Move a `Scheduled` record to `Cancelled` before it is claimed.
+    /// Anything else is an `InvalidTransition`.
+    async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Take a dead-lettered (`Failed`) record back to `Received` with its
+    /// attempts reset, so it can be enqueued again. Anything else is an
+    /// `InvalidTransition`.
+    async fn replay(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Delete a dead-lettered record, keeping its id as a tombstone like an
+    /// eviction does. Fails with `NotDeadLettered` unless it is `Failed`.
+    async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError>;
+
+    /// Undo an insert whose event could not be enqueued: delete the record
+    /// and its history without keeping a tombstone, so the id can be
+    /// ingested again. Fails with `VersionConflict` unless the record is
+    /// still at version `expected`, i.e. untouched since it was inserted.
+    async fn discard(&self, id: &str, expected: u64) -> Result<(), StoreError>;
+
+    /// Move `Processing` records whose lease lapsed at or before `cutoff`
+    /// (every one of them if `None`) back to `Received`. Returns their ids.
+    async fn release_expired_leases(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<String>, StoreError>;
+
+    /// Apply `policy` as of `now`: remove the records it selects (remembering
+    /// their ids as tombstones) and forget tombstones older than the
+    /// idempotency window. Returns the ids of the evicted records.
+    async fn evict(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<Vec<String>, StoreError>;
+
+    /// Wait until the named event reaches `desired` status or the timeout elapses.
+    ///
+    /// The default implementation polls `get`; backends that can signal status
+    /// changes (like `MemoryStore`) should override it.
+    async fn wait_for_status(&self, id: &str, desired: EventStatus, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Ok(rec) = self.get(id).await {
+                if rec.status == desired {
+                    return true;
+                }
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+/// Lets `Arc<dyn EventStore>` (or any shared store) be used wherever a store is
+/// expected, so the backend can be chosen at runtime.
+#[async_trait]
+impl<T: EventStore + ?Sized> EventStore for Arc<T> {
+    async fn insert_if_absent(&self, event: Event) -> Result<(EventRecord, bool), StoreError> {
+        (**self).insert_if_absent(event).await
+    }
+
+    async fn insert_if_absent_at(&self, event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), StoreError> {
+        (**self).insert_if_absent_at(event, process_after).await
+    }
+
+    async fn get(&self, id: &str) -> Result<EventRecord, StoreError> {
+        (**self).get(id).await
+    }
+
+    async fn ids_by_status(&self, status: EventStatus) -> Result<Vec<String>, StoreError> {
+        (**self).ids_by_status(status).await
+    }
+
+    async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError> {
+        (**self).list(query).await
+    }
+
+    async fn claim_for_processing(&self, id: &str, owner: &str, lease: Duration) -> Result<bool, StoreError> {
+        (**self).claim_for_processing(id, owner, lease).await
+    }
+
+    async fn set_result(&self, id: &str, result: Value, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).set_result(id, result, expected).await
+    }
+
+    async fn set_failed(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).set_failed(id, err, expected).await
+    }
+
+    async fn set_error_and_mark_received(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).set_error_and_mark_received(id, err, expected).await
+    }
+
+    async fn retry_later(&self, id: &str, err: EventError, delay: Duration, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).retry_later(id, err, delay, expected).await
+    }
+
+    async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).cancel(id, expected).await
+    }
+
+    async fn replay(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).replay(id, expected).await
+    }
+
+    async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        (**self).purge(id, expected).await
+    }
+
+    async fn discard(&self, id: &str, expected: u64) -> Result<(), StoreError> {
+        (**self).discard(id, expected).await
+    }
+
+    async fn release_expired_leases(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<String>, StoreError> {
+        (**self).release_expired_leases(cutoff).await
+    }
+
+    async fn evict(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<Vec<String>, StoreError> {
+        (**self).evict(policy, now).await
+    }
+
+    async fn wait_for_status(&self, id: &str, desired: EventStatus, timeout: Duration) -> bool {
+        (**self).wait_for_status(id, desired, timeout).await
+    }
+}
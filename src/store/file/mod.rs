@@ -0,0 +1,497 @@
+//! Durable file-backed store.
+//!
+//! Every mutation is appended to a write-ahead log and fsynced before it is
+//! applied to the in-memory index (a `MemoryStore`), so readers never observe
+//! state that would be lost by a crash. Point-in-time snapshots are written
+//! periodically, after which older log segments are deleted. On open the index
+//! is rebuilt from the newest valid snapshot plus the log tail after it.
+
+pub mod snapshot;
+pub mod wal;
+
+use crate::domain::error::EventError;
+use crate::domain::event::{Event, EventRecord};
+use crate::domain::state::EventStatus;
+use crate::store::{EventPage, EventQuery, EventStore, MemoryStore, RetentionPolicy, StoreError, Tombstone};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use wal::{Wal, WalOp};
+
+#[derive(Debug, Clone)]
+pub struct FileStoreOptions {
+    /// How often to snapshot and compact the log; `None` disables the
+    /// background task (`FileStore::snapshot` can still be called directly).
+    pub snapshot_interval: Option<Duration>,
+    /// Snapshots kept on disk. Keeping more than one lets recovery fall back
+    /// to an older snapshot if the newest is damaged.
+    pub snapshots_to_keep: usize,
+}
+
+impl Default for FileStoreOptions {
+    fn default() -> Self {
+        Self { snapshot_interval: Some(Duration::from_secs(300)), snapshots_to_keep: 2 }
+    }
+}
+
+#[derive(Clone)]
+pub struct FileStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    options: FileStoreOptions,
+    index: MemoryStore,
+    // serializes writers so log order matches the order mutations are applied
+    wal: Mutex<Wal>,
+    // `next_seq` covered by the last snapshot; also serializes snapshotting
+    last_snapshot: Mutex<u64>,
+}
+
+impl FileStore {
+    /// Open the store in `dir` with default options, creating it if needed.
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::open_with(dir, FileStoreOptions::default()).await
+    }
+
+    /// Open the store in `dir`, recover its state and start the snapshot task.
+    pub async fn open_with(dir: impl AsRef<Path>, options: FileStoreOptions) -> Result<Self, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let index = MemoryStore::new();
+        let snap = snapshot::load_latest(&dir).await?;
+        let from_seq = snap.as_ref().map(|s| s.next_seq).unwrap_or(0);
+        let from_snapshot = match snap {
+            Some(snap) => {
+                let n = snap.records.len();
+                for rec in snap.records {
+                    index.put(rec).await;
+                }
+                for tomb in snap.tombstones {
+                    index.bury(tomb).await;
+                }
+                n
+            }
+            None => 0,
+        };
+        let (wal, entries) = Wal::open(&dir, from_seq).await?;
+        let replayed = entries.len();
+        for entry in entries {
+            match entry.op {
+                WalOp::Evict | WalOp::Purge => index.bury(Tombstone::of(&entry.record)).await,
+                WalOp::Discard => index.drop_record(&entry.record.event.event_id).await,
+                _ => index.put(entry.record).await,
+            }
+        }
+        tracing::info!(dir = %dir.display(), from_snapshot, replayed, "file store opened");
+
+        let inner = Arc::new(Inner { dir, options, index, wal: Mutex::new(wal), last_snapshot: Mutex::new(from_seq) });
+        if let Some(every) = inner.options.snapshot_interval {
+            spawn_snapshotter(Arc::downgrade(&inner), every);
+        }
+        Ok(Self { inner })
+    }
+
+    /// Write a snapshot of every record, then delete snapshots and log
+    /// segments that are no longer needed for recovery. Returns false if
+    /// nothing changed since the previous snapshot.
+    pub async fn snapshot(&self) -> Result<bool, StoreError> {
+        let inner = &self.inner;
+        let mut last = inner.last_snapshot.lock().await;
+        let (next_seq, records, tombstones) = {
+            let mut wal = inner.wal.lock().await;
+            let next_seq = wal.next_seq();
+            if next_seq == *last {
+                return Ok(false);
+            }
+            // holding the wal lock keeps the records consistent with next_seq
+            let records = inner.index.records().await;
+            let tombstones = inner.index.tombstones().await;
+            wal.rotate().await?;
+            (next_seq, records, tombstones)
+        };
+        let count = records.len();
+        snapshot::write(&inner.dir, next_seq, records, tombstones).await?;
+        *last = next_seq;
+
+        if let Some(oldest_kept) = snapshot::prune(&inner.dir, inner.options.snapshots_to_keep).await? {
+            let removed = inner.wal.lock().await.remove_segments_before(oldest_kept).await?;
+            tracing::debug!(next_seq, records = count, removed, "snapshot written");
+        }
+        Ok(true)
+    }
+
+    /// Apply `f` to a copy of the record, log the result if `f` reports a
+    /// change, then publish it to the index. Rejected mutations are not logged.
+    async fn mutate(&self, id: &str, op: WalOp, f: impl FnOnce(&mut EventRecord) -> Result<bool, StoreError>) -> Result<bool, StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        let mut rec = self.inner.index.get(id).await?;
+        if !f(&mut rec)? {
+            return Ok(false);
+        }
+        wal.append(op, &rec).await?;
+        self.inner.index.put(rec).await;
+        Ok(true)
+    }
+}
+
+/// Snapshot on an interval until the store is dropped.
+fn spawn_snapshotter(inner: std::sync::Weak<Inner>, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // the first tick fires immediately; recovery has just happened
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(inner) = inner.upgrade() else { break };
+            if let Err(e) = (FileStore { inner }).snapshot().await {
+                tracing::warn!(error = %e, "snapshot failed");
+            }
+        }
+    });
+}
+
+#[async_trait]
+impl EventStore for FileStore {
+    async fn insert_if_absent_at(&self, event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        match self.inner.index.get(&event.event_id).await {
+            Ok(existing) => return Ok((existing, false)),
+            Err(StoreError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let rec = match process_after {
+            Some(at) => EventRecord::scheduled(event, at),
+            None => EventRecord::new(event),
+        };
+        wal.append(WalOp::Insert, &rec).await?;
+        self.inner.index.put(rec.clone()).await;
+        Ok((rec, true))
+    }
+
+    async fn get(&self, id: &str) -> Result<EventRecord, StoreError> {
+        self.inner.index.get(id).await
+    }
+
+    async fn ids_by_status(&self, status: EventStatus) -> Result<Vec<String>, StoreError> {
+        self.inner.index.ids_by_status(status).await
+    }
+
+    async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError> {
+        self.inner.index.list(query).await
+    }
+
+    async fn claim_for_processing(&self, id: &str, owner: &str, lease: Duration) -> Result<bool, StoreError> {
+        self.mutate(id, WalOp::Claim, |rec| Ok(rec.claim(owner, lease))).await
+    }
+
+    async fn set_result(&self, id: &str, result: Value, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Result, |rec| {
+            rec.expect_version(expected)?;
+            rec.complete(result)?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn set_failed(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Failure, |rec| {
+            rec.expect_version(expected)?;
+            rec.fail(err)?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn set_error_and_mark_received(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Retry, |rec| {
+            rec.expect_version(expected)?;
+            rec.requeue(err)?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn retry_later(&self, id: &str, err: EventError, delay: Duration, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Retry, |rec| {
+            rec.expect_version(expected)?;
+            rec.retry_later(err, delay)?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Cancel, |rec| {
+            rec.expect_version(expected)?;
+            rec.cancel()?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn replay(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.mutate(id, WalOp::Replay, |rec| {
+            rec.expect_version(expected)?;
+            rec.replay()?;
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        let rec = self.inner.index.get(id).await?;
+        rec.expect_version(expected)?;
+        if rec.status != EventStatus::Failed {
+            return Err(StoreError::NotDeadLettered { status: rec.status });
+        }
+        wal.append(WalOp::Purge, &rec).await?;
+        self.inner.index.bury(Tombstone::of(&rec)).await;
+        Ok(())
+    }
+
+    async fn discard(&self, id: &str, expected: u64) -> Result<(), StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        let rec = self.inner.index.get(id).await?;
+        rec.expect_version(Some(expected))?;
+        wal.append(WalOp::Discard, &rec).await?;
+        self.inner.index.drop_record(id).await;
+        Ok(())
+    }
+
+    async fn release_expired_leases(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<String>, StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        let mut released = Vec::new();
+        for mut rec in self.inner.index.records_in(&[EventStatus::Processing]).await.into_iter().filter(|r| r.lease_expired(cutoff)) {
+            rec.release_lease()?;
+            wal.append(WalOp::LeaseExpired, &rec).await?;
+            released.push(rec.event.event_id.clone());
+            self.inner.index.put(rec).await;
+        }
+        Ok(released)
+    }
+
+    async fn evict(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<Vec<String>, StoreError> {
+        let mut wal = self.inner.wal.lock().await;
+        let finished: Vec<EventStatus> = EventStatus::ALL.into_iter().filter(|s| s.is_terminal()).collect();
+        let records = self.inner.index.records_in(&finished).await;
+        let total = self.inner.index.record_count().await;
+        let mut evicted = Vec::new();
+        for rec in policy.select_finished(&records, total, now) {
+            wal.append(WalOp::Evict, rec).await?;
+            self.inner.index.bury(Tombstone::of(rec)).await;
+            evicted.push(rec.event.event_id.clone());
+        }
+        // forgetting is not logged: replay brings expired tombstones back and
+        // the next pass drops them again
+        self.inner.index.forget(policy.key_cutoff(now)).await;
+        Ok(evicted)
+    }
+
+    async fn wait_for_status(&self, id: &str, desired: EventStatus, timeout: Duration) -> bool {
+        self.inner.index.wait_for_status(id, desired, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use serde_json::json;
+    use std::io::Write;
+
+    const FIRST_SEGMENT: &str = "wal-00000000000000000000.log";
+
+    fn no_background() -> FileStoreOptions {
+        FileStoreOptions { snapshot_interval: None, ..Default::default() }
+    }
+
+    fn files_with_prefix(dir: &Path, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.starts_with(prefix))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn state_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = FileStore::open(dir.path()).await.unwrap();
+            store.insert_if_absent(event("f1")).await.unwrap();
+            store.insert_if_absent(event("f2")).await.unwrap();
+            store.claim_for_processing("f1", OWNER, LEASE).await.unwrap();
+            store.set_result("f1", json!({"ok": true}), None).await.unwrap();
+            store.claim_for_processing("f2", OWNER, LEASE).await.unwrap();
+            store.set_error_and_mark_received("f2", "transient".into(), None).await.unwrap();
+        }
+
+        let store = FileStore::open(dir.path()).await.unwrap();
+        let f1 = store.get("f1").await.unwrap();
+        assert_eq!(f1.status, EventStatus::Completed);
+        assert_eq!(f1.result, Some(json!({"ok": true})));
+        let f2 = store.get("f2").await.unwrap();
+        assert_eq!(f2.status, EventStatus::Received);
+        assert_eq!(f2.attempts, 1);
+        assert_eq!(f2.last_error.as_ref().map(|e| e.details.as_str()), Some("transient"));
+
+        // idempotency holds across restarts
+        let (_rec, inserted) = store.insert_if_absent(event("f1")).await.unwrap();
+        assert!(!inserted);
+        assert_eq!(store.ids_by_status(EventStatus::Received).await.unwrap(), vec!["f2".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn torn_tail_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = FileStore::open(dir.path()).await.unwrap();
+            store.insert_if_absent(event("t1")).await.unwrap();
+        }
+        // simulate a crash in the middle of an append
+        let mut f = std::fs::OpenOptions::new().append(true).open(dir.path().join(FIRST_SEGMENT)).unwrap();
+        f.write_all(b"{\"seq\":1,\"op\":\"Cla").unwrap();
+        drop(f);
+
+        let store = FileStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.get("t1").await.unwrap().status, EventStatus::Received);
+        // the log stays appendable after truncation
+        store.claim_for_processing("t1", OWNER, LEASE).await.unwrap();
+        drop(store);
+        let store = FileStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.get("t1").await.unwrap().status, EventStatus::Processing);
+    }
+
+    #[tokio::test]
+    async fn corrupt_middle_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(FIRST_SEGMENT), b"garbage\n{}\n").unwrap();
+        assert!(matches!(FileStore::open(dir.path()).await, Err(StoreError::Backend(_))));
+    }
+
+    #[tokio::test]
+    async fn snapshot_compacts_log_and_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+            store.insert_if_absent(event("s1")).await.unwrap();
+            store.claim_for_processing("s1", OWNER, LEASE).await.unwrap();
+            assert!(store.snapshot().await.unwrap());
+            store.set_result("s1", json!({"n": 1}), None).await.unwrap();
+            store.insert_if_absent(event("s2")).await.unwrap();
+            assert!(store.snapshot().await.unwrap());
+            // nothing new since the last snapshot
+            assert!(!store.snapshot().await.unwrap());
+            store.insert_if_absent(event("s3")).await.unwrap();
+            assert!(store.snapshot().await.unwrap());
+            store.claim_for_processing("s3", OWNER, LEASE).await.unwrap();
+        }
+
+        // two snapshots retained; segments older than the oldest one are gone
+        assert_eq!(files_with_prefix(dir.path(), "snapshot-").len(), 2);
+        assert!(!dir.path().join(FIRST_SEGMENT).exists());
+
+        let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+        assert_eq!(store.get("s1").await.unwrap().status, EventStatus::Completed);
+        assert_eq!(store.get("s2").await.unwrap().status, EventStatus::Received);
+        // replayed from the tail written after the last snapshot
+        assert_eq!(store.get("s3").await.unwrap().status, EventStatus::Processing);
+    }
+
+    #[tokio::test]
+    async fn evictions_survive_reopen_and_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = RetentionPolicy { completed_ttl: Some(Duration::ZERO), ..Default::default() };
+        {
+            let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+            for id in ["v1", "v2"] {
+                store.insert_if_absent(event(id)).await.unwrap();
+                store.claim_for_processing(id, OWNER, LEASE).await.unwrap();
+                store.set_result(id, json!({}), None).await.unwrap();
+                if id == "v1" {
+                    // v1's tombstone ends up in the snapshot, v2's only in the log
+                    assert_eq!(store.evict(&policy, Utc::now()).await.unwrap(), vec!["v1".to_string()]);
+                    assert!(store.snapshot().await.unwrap());
+                }
+            }
+            assert_eq!(store.evict(&policy, Utc::now()).await.unwrap(), vec!["v2".to_string()]);
+        }
+
+        let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+        for id in ["v1", "v2"] {
+            assert!(matches!(store.get(id).await, Err(StoreError::Evicted { status: EventStatus::Completed })));
+            assert!(matches!(store.insert_if_absent(event(id)).await, Err(StoreError::Evicted { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn discards_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+            let (rec, _) = store.insert_if_absent(event("d1")).await.unwrap();
+            store.discard("d1", rec.version).await.unwrap();
+        }
+
+        let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+        assert!(matches!(store.get("d1").await, Err(StoreError::NotFound)));
+        assert!(store.insert_if_absent(event("d1")).await.unwrap().1);
+    }
+
+    #[tokio::test]
+    async fn corrupt_snapshot_falls_back_to_previous() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+            store.insert_if_absent(event("c1")).await.unwrap();
+            store.snapshot().await.unwrap();
+            store.insert_if_absent(event("c2")).await.unwrap();
+            store.snapshot().await.unwrap();
+            store.insert_if_absent(event("c3")).await.unwrap();
+        }
+
+        // tear the newest snapshot in half
+        let newest = files_with_prefix(dir.path(), "snapshot-").pop().unwrap();
+        let path = dir.path().join(newest);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let store = FileStore::open_with(dir.path(), no_background()).await.unwrap();
+        for id in ["c1", "c2", "c3"] {
+            assert!(store.get(id).await.is_ok(), "{id} should be recovered");
+        }
+    }
+
+    #[tokio::test]
+    async fn background_task_writes_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = FileStoreOptions { snapshot_interval: Some(Duration::from_millis(20)), ..Default::default() };
+        let store = FileStore::open_with(dir.path(), opts).await.unwrap();
+        store.insert_if_absent(event("b1")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(files_with_prefix(dir.path(), "snapshot-").len(), 1);
+    }
+
+    crate::store::conformance::event_store_conformance!(async {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::open(dir.path()).await.unwrap();
+        (store, dir)
+    });
+}
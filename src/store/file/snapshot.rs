@@ -0,0 +1,116 @@
+use crate::domain::event::EventRecord;
+use crate::store::file::wal::sync_dir;
+use crate::store::{StoreError, Tombstone};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+/// First line of a snapshot file. The second line is the body, whose CRC-32
+/// must match the header, holding as many records as the header says.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    /// The snapshot holds every log entry with `seq < next_seq`.
+    next_seq: u64,
+    records: usize,
+    crc32: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotBody {
+    records: Vec<EventRecord>,
+    tombstones: Vec<Tombstone>,
+}
+
+#[derive(Debug)]
+pub struct Snapshot {
+    pub next_seq: u64,
+    pub records: Vec<EventRecord>,
+    pub tombstones: Vec<Tombstone>,
+}
+
+fn snapshot_name(next_seq: u64) -> String {
+    format!("{SNAPSHOT_PREFIX}{next_seq:020}{SNAPSHOT_SUFFIX}")
+}
+
+/// Snapshots in `dir`, newest first.
+async fn list_snapshots(dir: &Path) -> Result<Vec<(u64, PathBuf)>, StoreError> {
+    let mut out = Vec::new();
+    let mut rd = tokio::fs::read_dir(dir).await?;
+    while let Some(ent) = rd.next_entry().await? {
+        let name = ent.file_name();
+        let Some(name) = name.to_str() else { continue };
+        let seq = name.strip_prefix(SNAPSHOT_PREFIX).and_then(|n| n.strip_suffix(SNAPSHOT_SUFFIX)).and_then(|n| n.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            out.push((seq, ent.path()));
+        }
+    }
+    out.sort_by_key(|(seq, _)| std::cmp::Reverse(*seq));
+    Ok(out)
+}
+
+/// Write a snapshot atomically: temp file, fsync, rename, fsync directory.
+pub async fn write(dir: &Path, next_seq: u64, records: Vec<EventRecord>, tombstones: Vec<Tombstone>) -> Result<PathBuf, StoreError> {
+    let count = records.len();
+    let body = serde_json::to_vec(&SnapshotBody { records, tombstones })?;
+    let header = SnapshotHeader { next_seq, records: count, crc32: crc32fast::hash(&body) };
+    let mut buf = serde_json::to_vec(&header)?;
+    buf.push(b'\n');
+    buf.extend_from_slice(&body);
+    buf.push(b'\n');
+
+    let path = dir.join(snapshot_name(next_seq));
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, &buf).await?;
+    tokio::fs::File::open(&tmp).await?.sync_all().await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    sync_dir(dir)?;
+    Ok(path)
+}
+
+async fn read(path: &Path) -> Result<Snapshot, StoreError> {
+    let bytes = tokio::fs::read(path).await?;
+    let split = bytes.iter().position(|b| *b == b'\n').ok_or_else(|| StoreError::Backend("snapshot has no header".into()))?;
+    let header: SnapshotHeader = serde_json::from_slice(&bytes[..split])?;
+    let body = bytes[split + 1..].strip_suffix(b"\n").ok_or_else(|| StoreError::Backend("snapshot is truncated".into()))?;
+    if crc32fast::hash(body) != header.crc32 {
+        return Err(StoreError::Backend("snapshot checksum mismatch".into()));
+    }
+    let body: SnapshotBody = serde_json::from_slice(body)?;
+    if body.records.len() != header.records {
+        return Err(StoreError::Backend("snapshot record count mismatch".into()));
+    }
+    Ok(Snapshot { next_seq: header.next_seq, records: body.records, tombstones: body.tombstones })
+}
+
+/// Load the newest snapshot that passes validation, skipping corrupt or torn
+/// ones. Returns `None` if there is no usable snapshot.
+pub async fn load_latest(dir: &Path) -> Result<Option<Snapshot>, StoreError> {
+    for (_, path) in list_snapshots(dir).await? {
+        match read(&path).await {
+            Ok(snap) => return Ok(Some(snap)),
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "skipping unusable snapshot"),
+        }
+    }
+    Ok(None)
+}
+
+/// Delete all but the `keep` newest snapshots (and stray temp files). Returns
+/// the `next_seq` of the oldest snapshot kept, i.e. how far the log may be
+/// truncated while still allowing recovery from any retained snapshot.
+pub async fn prune(dir: &Path, keep: usize) -> Result<Option<u64>, StoreError> {
+    let snapshots = list_snapshots(dir).await?;
+    let keep = keep.max(1).min(snapshots.len());
+    for (_, path) in &snapshots[keep..] {
+        tokio::fs::remove_file(path).await?;
+    }
+    let mut rd = tokio::fs::read_dir(dir).await?;
+    while let Some(ent) = rd.next_entry().await? {
+        let name = ent.file_name();
+        if name.to_str().is_some_and(|n| n.starts_with(SNAPSHOT_PREFIX) && n.ends_with(".tmp")) {
+            tokio::fs::remove_file(ent.path()).await?;
+        }
+    }
+    Ok(snapshots[..keep].last().map(|(seq, _)| *seq))
+}
@@ -0,0 +1,205 @@
+use crate::domain::event::EventRecord;
+use crate::store::StoreError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+const SEGMENT_PREFIX: &str = "wal-";
+const SEGMENT_SUFFIX: &str = ".log";
+
+/// Which mutation produced a log entry. Replay does not depend on it (every
+/// entry carries the full resulting record) but it keeps the log readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalOp {
+    Insert,
+    Claim,
+    Result,
+    Failure,
+    Retry,
+    LeaseExpired,
+    /// A scheduled record was cancelled before it came due.
+    Cancel,
+    /// The record was removed by the retention policy; replay turns it into
+    /// a tombstone.
+    Evict,
+    /// A dead-lettered record was sent back to `Received`.
+    Replay,
+    /// A dead-lettered record was deleted; replayed like `Evict`.
+    Purge,
+    /// An insert was rolled back; replay removes the record without a
+    /// tombstone.
+    Discard,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub seq: u64,
+    pub op: WalOp,
+    pub record: EventRecord,
+}
+
+/// Append-only JSON-lines log of record mutations, split into segments named
+/// after the first sequence number they hold. Every append is fsynced before
+/// it returns.
+pub struct Wal {
+    dir: PathBuf,
+    file: tokio::fs::File,
+    next_seq: u64,
+}
+
+impl Wal {
+    /// Open the log in `dir` and return every entry with `seq >= from_seq`.
+    ///
+    /// A partially written last line of the newest segment (a crash
+    /// mid-append) is truncated away; an unreadable line anywhere else, or a
+    /// gap between `from_seq` and the oldest segment, is reported as
+    /// corruption.
+    pub async fn open(dir: impl AsRef<Path>, from_seq: u64) -> Result<(Self, Vec<WalEntry>), StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        let segments = list_segments(&dir).await?;
+        if let Some((first, _)) = segments.first() {
+            if *first > from_seq {
+                return Err(StoreError::Backend(format!("wal in {} starts at seq {} but recovery needs {}", dir.display(), first, from_seq)));
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut next_seq = from_seq;
+        for (i, (_, path)) in segments.iter().enumerate() {
+            let newest = i + 1 == segments.len();
+            for entry in read_segment(path, newest).await? {
+                next_seq = next_seq.max(entry.seq + 1);
+                if entry.seq >= from_seq {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        let file = match segments.last() {
+            Some((_, path)) => tokio::fs::OpenOptions::new().append(true).open(path).await?,
+            None => create_segment(&dir, next_seq).await?,
+        };
+        Ok((Self { dir, file, next_seq }, entries))
+    }
+
+    /// Append one entry and fsync it.
+    pub async fn append(&mut self, op: WalOp, record: &EventRecord) -> Result<u64, StoreError> {
+        let seq = self.next_seq;
+        let entry = WalEntry { seq, op, record: record.clone() };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await?;
+        self.next_seq += 1;
+        Ok(seq)
+    }
+
+    /// Sequence number the next append will get.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Start a new segment; subsequent appends go there.
+    pub async fn rotate(&mut self) -> Result<(), StoreError> {
+        self.file = create_segment(&self.dir, self.next_seq).await?;
+        Ok(())
+    }
+
+    /// Delete segments whose entries all have `seq < before_seq`. The newest
+    /// segment is always kept.
+    pub async fn remove_segments_before(&self, before_seq: u64) -> Result<usize, StoreError> {
+        let segments = list_segments(&self.dir).await?;
+        let mut removed = 0;
+        for pair in segments.windows(2) {
+            let (_, path) = &pair[0];
+            let (next_first, _) = &pair[1];
+            if *next_first <= before_seq {
+                tokio::fs::remove_file(path).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn segment_name(first_seq: u64) -> String {
+    format!("{SEGMENT_PREFIX}{first_seq:020}{SEGMENT_SUFFIX}")
+}
+
+async fn create_segment(dir: &Path, first_seq: u64) -> Result<tokio::fs::File, StoreError> {
+    let path = dir.join(segment_name(first_seq));
+    let file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+/// Segments in `dir`, oldest first.
+async fn list_segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>, StoreError> {
+    let mut out = Vec::new();
+    let mut rd = tokio::fs::read_dir(dir).await?;
+    while let Some(ent) = rd.next_entry().await? {
+        let name = ent.file_name();
+        let Some(name) = name.to_str() else { continue };
+        let first = name.strip_prefix(SEGMENT_PREFIX).and_then(|n| n.strip_suffix(SEGMENT_SUFFIX)).and_then(|n| n.parse::<u64>().ok());
+        if let Some(first) = first {
+            out.push((first, ent.path()));
+        }
+    }
+    out.sort_by_key(|(first, _)| *first);
+    Ok(out)
+}
+
+/// Read every entry of one segment. With `allow_torn_tail`, an incomplete or
+/// unparsable last line is truncated from the file instead of failing.
+async fn read_segment(path: &Path, allow_torn_tail: bool) -> Result<Vec<WalEntry>, StoreError> {
+    let bytes = tokio::fs::read(path).await?;
+    let mut entries = Vec::new();
+    let mut good_len = 0usize;
+    let mut offset = 0usize;
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        offset += line.len();
+        let complete = line.ends_with(b"\n");
+        let body = line.strip_suffix(b"\n").unwrap_or(line);
+        if body.iter().all(|b| b.is_ascii_whitespace()) {
+            if complete {
+                good_len = offset;
+            }
+            continue;
+        }
+        let is_last = offset == bytes.len();
+        match serde_json::from_slice::<WalEntry>(body) {
+            Ok(entry) if complete => {
+                entries.push(entry);
+                good_len = offset;
+            }
+            // torn tail: a crash interrupted the last append
+            _ if is_last && allow_torn_tail => {
+                tracing::warn!(path = %path.display(), dropped = bytes.len() - good_len, "truncating torn wal tail");
+                break;
+            }
+            Ok(_) => {
+                return Err(StoreError::Backend(format!("truncated wal segment {}", path.display())));
+            }
+            Err(e) => {
+                return Err(StoreError::Backend(format!("corrupt wal {} at byte {}: {}", path.display(), offset - line.len(), e)));
+            }
+        }
+    }
+
+    if good_len < bytes.len() {
+        let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+        file.set_len(good_len as u64).await?;
+        file.sync_all().await?;
+    }
+    Ok(entries)
+}
+
+/// Make a newly created or renamed file's directory entry durable.
+pub(crate) fn sync_dir(dir: &Path) -> Result<(), StoreError> {
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
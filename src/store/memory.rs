@@ -1,109 +1,410 @@
+use crate::domain::error::EventError;
 use crate::domain::event::{Event, EventRecord};
 use crate::domain::state::EventStatus;
-use chrono::Utc;
+use crate::store::{EventPage, EventQuery, EventStore, RetentionPolicy, StoreError, Tombstone};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
 use std::sync::Arc;
-use thiserror::Error;
+use std::time::Duration;
 use tokio::sync::{RwLock, Notify};
 
-#[derive(Error, Debug)]
-pub enum StoreError {
-    #[error("not found")]
-    NotFound,
+/// Position of a record in list order.
+type Key = (DateTime<Utc>, String);
+
+fn key(rec: &EventRecord) -> Key {
+    (rec.created_at, rec.event.event_id.clone())
+}
+
+/// Records plus the ordered secondary indexes `list` and `ids_by_status`
+/// walk instead of scanning every record. All writes go through `upsert` and
+/// `remove` to keep them in step.
+#[derive(Default)]
+struct Table {
+    records: HashMap<String, EventRecord>,
+    by_created: BTreeSet<Key>,
+    by_status: HashMap<EventStatus, BTreeSet<Key>>,
+}
+
+impl Table {
+    fn get(&self, id: &str) -> Option<&EventRecord> {
+        self.records.get(id)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &EventRecord> {
+        self.records.values()
+    }
+
+    fn upsert(&mut self, rec: EventRecord) {
+        let k = key(&rec);
+        match self.records.get(&rec.event.event_id) {
+            Some(old) if old.status == rec.status => {}
+            Some(old) => {
+                if let Some(set) = self.by_status.get_mut(&old.status) {
+                    set.remove(&key(old));
+                }
+                self.by_status.entry(rec.status).or_default().insert(k);
+            }
+            None => {
+                self.by_created.insert(k.clone());
+                self.by_status.entry(rec.status).or_default().insert(k);
+            }
+        }
+        self.records.insert(rec.event.event_id.clone(), rec);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<EventRecord> {
+        let rec = self.records.remove(id)?;
+        let k = key(&rec);
+        self.by_created.remove(&k);
+        if let Some(set) = self.by_status.get_mut(&rec.status) {
+            set.remove(&k);
+        }
+        Some(rec)
+    }
+
+    /// Keys in list order from wherever `query` starts, using the status
+    /// index when the query filters on status.
+    fn scan<'a>(&'a self, query: &EventQuery) -> Box<dyn Iterator<Item = &'a Key> + 'a> {
+        let from = query.created_from.map(|t| (t, String::new()));
+        let after = query.after.as_ref().map(|c| (c.created_at, c.event_id.clone()));
+        // start from whichever of the two bounds is further along
+        let start = match (after, from) {
+            (Some(after), Some(from)) if from > after => Bound::Included(from),
+            (Some(after), _) => Bound::Excluded(after),
+            (None, Some(from)) => Bound::Included(from),
+            (None, None) => Bound::Unbounded,
+        };
+        match query.status {
+            Some(status) => match self.by_status.get(&status) {
+                Some(set) => Box::new(set.range((start, Bound::Unbounded))),
+                None => Box::new(std::iter::empty()),
+            },
+            None => Box::new(self.by_created.range((start, Bound::Unbounded))),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct MemoryStore {
-    inner: Arc<RwLock<HashMap<String, EventRecord>>>,
+    inner: Arc<RwLock<Table>>,
+    // ids of evicted records, kept for deduplication
+    tombstones: Arc<RwLock<HashMap<String, Tombstone>>>,
     // per-event notifiers for deterministic signaling
     notifiers: Arc<RwLock<HashMap<String, Arc<Notify>>>>,
 }
 
 impl MemoryStore {
     pub fn new() -> Self {
-        Self { inner: Arc::new(RwLock::new(HashMap::new())), notifiers: Arc::new(RwLock::new(HashMap::new())) }
+        Self {
+            inner: Arc::new(RwLock::new(Table::default())),
+            tombstones: Arc::new(RwLock::new(HashMap::new())),
+            notifiers: Arc::new(RwLock::new(HashMap::new())),
+        }
     }
 
-    /// Insert if absent. Returns true if inserted, false if already existed.
-    pub async fn insert_if_absent(&self, event: Event) -> (EventRecord, bool) {
-        let mut map = self.inner.write().await;
-        if let Some(existing) = map.get(&event.event_id) {
-            return (existing.clone(), false);
+    /// Insert or replace a record wholesale and wake its watchers. Used by
+    /// backends that keep a `MemoryStore` as their in-memory index.
+    pub(crate) async fn put(&self, rec: EventRecord) {
+        let id = rec.event.event_id.clone();
+        self.inner.write().await.upsert(rec);
+        let existing = self.notifiers.read().await.get(&id).cloned();
+        let n = match existing {
+            Some(n) => n,
+            None => self.notifiers.write().await.entry(id).or_insert_with(|| Arc::new(Notify::new())).clone(),
+        };
+        n.notify_waiters();
+    }
+
+    /// Clone every record, e.g. to write a snapshot.
+    pub(crate) async fn records(&self) -> Vec<EventRecord> {
+        self.inner.read().await.values().cloned().collect()
+    }
+
+    /// Clone the records in any of `statuses`, walking the status index
+    /// rather than every record.
+    pub(crate) async fn records_in(&self, statuses: &[EventStatus]) -> Vec<EventRecord> {
+        let map = self.inner.read().await;
+        statuses.iter().filter_map(|status| map.by_status.get(status)).flatten().filter_map(|(_, id)| map.get(id)).cloned().collect()
+    }
+
+    /// How many records the store holds.
+    pub(crate) async fn record_count(&self) -> usize {
+        self.inner.read().await.records.len()
+    }
+
+    /// Every tombstone, e.g. to write a snapshot.
+    pub(crate) async fn tombstones(&self) -> Vec<Tombstone> {
+        self.tombstones.read().await.values().cloned().collect()
+    }
+
+    /// Remove a record, remembering its id as a tombstone.
+    pub(crate) async fn bury(&self, tomb: Tombstone) {
+        self.bury_if(&tomb.event_id.clone(), |_| Ok(tomb)).await.ok();
+    }
+
+    /// Remove a record without a tombstone, as if it was never inserted.
+    pub(crate) async fn drop_record(&self, id: &str) {
+        self.remove_if(id, |_| Ok(())).await.ok();
+    }
+
+    /// Remove the named record, leaving no tombstone, unless `check` fails.
+    async fn remove_if(&self, id: &str, check: impl FnOnce(&EventRecord) -> Result<(), StoreError>) -> Result<(), StoreError> {
+        {
+            let mut map = self.inner.write().await;
+            check(map.get(id).ok_or(StoreError::NotFound)?)?;
+            map.remove(id);
         }
-        let rec = EventRecord::new(event);
-        map.insert(rec.event.event_id.clone(), rec.clone());
-        // create per-event notifier
-        let mut notifs = self.notifiers.write().await;
-        notifs.insert(rec.event.event_id.clone(), Arc::new(Notify::new()));
-        (rec, true)
+        if let Some(n) = self.notifiers.write().await.remove(id) {
+            n.notify_waiters();
+        }
+        Ok(())
     }
 
-    pub async fn get(&self, id: &str) -> Result<EventRecord, StoreError> {
+    /// Replace the named record (if any) with the tombstone `check` returns
+    /// for it, unless `check` fails.
+    async fn bury_if(&self, id: &str, check: impl FnOnce(Option<&EventRecord>) -> Result<Tombstone, StoreError>) -> Result<(), StoreError> {
+        {
+            // swap record for tombstone under one lock so a concurrent insert
+            // sees one or the other
+            let mut map = self.inner.write().await;
+            let tomb = check(map.get(id))?;
+            map.remove(id);
+            self.tombstones.write().await.insert(id.to_string(), tomb);
+        }
+        if let Some(n) = self.notifiers.write().await.remove(id) {
+            n.notify_waiters();
+        }
+        Ok(())
+    }
+
+    /// Tombstones for the finished records `policy` evicts at `now`, each with
+    /// the version it was selected at.
+    async fn evictable(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Vec<(Tombstone, u64)> {
         let map = self.inner.read().await;
-        map.get(id).cloned().ok_or(StoreError::NotFound)
+        let finished = EventStatus::ALL.into_iter().filter(|s| s.is_terminal()).filter_map(|s| map.by_status.get(&s)).flatten().filter_map(|(_, id)| map.get(id));
+        policy.select_finished(finished, map.records.len(), now).into_iter().map(|rec| (Tombstone::of(rec), rec.version)).collect()
     }
 
-    /// Claim for processing: move Received -> Processing and increment attempts atomically.
-    pub async fn claim_for_processing(&self, id: &str) -> Result<bool, StoreError> {
-        let mut map = self.inner.write().await;
-        let rec = map.get_mut(id).ok_or(StoreError::NotFound)?;
-        if rec.status == EventStatus::Received {
-            rec.status = EventStatus::Processing;
-            rec.attempts = rec.attempts.saturating_add(1);
-            rec.updated_at = Utc::now();
-            // notify per-event watchers that status changed
-            if let Some(n) = self.notifiers.read().await.get(id) {
-                n.notify_waiters();
+    /// Bury each victim still in the status and at the version it was
+    /// selected at; a replay may have moved it back to Received since.
+    /// Returns the ids actually buried.
+    async fn bury_unchanged(&self, victims: Vec<(Tombstone, u64)>) -> Vec<String> {
+        let mut ids = Vec::with_capacity(victims.len());
+        for (tomb, version) in victims {
+            let id = tomb.event_id.clone();
+            let buried = self
+                .bury_if(&id, |rec| {
+                    let rec = rec.ok_or(StoreError::NotFound)?;
+                    rec.expect_version(Some(version))?;
+                    match rec.status {
+                        status if status == tomb.status => Ok(tomb),
+                        status => Err(StoreError::InvalidTransition { from: status, to: tomb.status }),
+                    }
+                })
+                .await;
+            if buried.is_ok() {
+                ids.push(id);
             }
-            Ok(true)
-        } else {
-            Ok(false)
         }
+        ids
     }
 
-    pub async fn set_result(&self, id: &str, result: Value) -> Result<(), StoreError> {
+    /// Drop tombstones of events created at or before `cutoff`. Returns how
+    /// many were dropped.
+    pub(crate) async fn forget(&self, cutoff: Option<DateTime<Utc>>) -> usize {
+        let Some(cutoff) = cutoff else { return 0 };
+        let mut tombs = self.tombstones.write().await;
+        let before = tombs.len();
+        tombs.retain(|_, t| t.created_at > cutoff);
+        before - tombs.len()
+    }
+
+    /// Apply `f` to the named record under the write lock and wake watchers.
+    /// `f` works on a copy, so a rejected mutation leaves the record untouched.
+    async fn update<T>(&self, id: &str, f: impl FnOnce(&mut EventRecord) -> Result<T, StoreError>) -> Result<T, StoreError> {
         let mut map = self.inner.write().await;
-        let rec = map.get_mut(id).ok_or(StoreError::NotFound)?;
-        rec.result = Some(result);
-        rec.status = EventStatus::Completed;
-        rec.updated_at = Utc::now();
+        let mut next = map.get(id).cloned().ok_or(StoreError::NotFound)?;
+        let out = f(&mut next)?;
+        map.upsert(next);
+        // notify per-event watchers that status changed
         if let Some(n) = self.notifiers.read().await.get(id) {
             n.notify_waiters();
         }
-        Ok(())
+        Ok(out)
     }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    pub async fn set_failed(&self, id: &str, err: String) -> Result<(), StoreError> {
+#[async_trait]
+impl EventStore for MemoryStore {
+    async fn insert_if_absent_at(&self, event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), StoreError> {
         let mut map = self.inner.write().await;
-        let rec = map.get_mut(id).ok_or(StoreError::NotFound)?;
-        rec.last_error = Some(err);
-        rec.status = EventStatus::Failed;
-        rec.updated_at = Utc::now();
-        if let Some(n) = self.notifiers.read().await.get(id) {
-            n.notify_waiters();
+        if let Some(existing) = map.get(&event.event_id) {
+            return Ok((existing.clone(), false));
+        }
+        if let Some(tomb) = self.tombstones.read().await.get(&event.event_id) {
+            return Err(StoreError::Evicted { status: tomb.status });
+        }
+        let rec = match process_after {
+            Some(at) => EventRecord::scheduled(event, at),
+            None => EventRecord::new(event),
+        };
+        map.upsert(rec.clone());
+        // create per-event notifier
+        let mut notifs = self.notifiers.write().await;
+        notifs.insert(rec.event.event_id.clone(), Arc::new(Notify::new()));
+        Ok((rec, true))
+    }
+
+    async fn get(&self, id: &str) -> Result<EventRecord, StoreError> {
+        let map = self.inner.read().await;
+        if let Some(rec) = map.get(id) {
+            return Ok(rec.clone());
+        }
+        match self.tombstones.read().await.get(id) {
+            Some(tomb) => Err(StoreError::Evicted { status: tomb.status }),
+            None => Err(StoreError::NotFound),
         }
-        Ok(())
     }
 
-    /// Record an error and move back to `Received` for retry.
-    pub async fn set_error_and_mark_received(&self, id: &str, err: String) -> Result<(), StoreError> {
+    async fn ids_by_status(&self, status: EventStatus) -> Result<Vec<String>, StoreError> {
+        let map = self.inner.read().await;
+        Ok(map.by_status.get(&status).map(|set| set.iter().map(|(_, id)| id.clone()).collect()).unwrap_or_default())
+    }
+
+    async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError> {
+        let limit = query.limit.max(1);
+        let map = self.inner.read().await;
+        let mut out = Vec::new();
+        for (created_at, id) in map.scan(query) {
+            if query.created_to.is_some_and(|to| *created_at >= to) {
+                break;
+            }
+            match map.get(id) {
+                Some(rec) if query.matches(rec) => out.push(rec.clone()),
+                _ => continue,
+            }
+            if out.len() > limit {
+                break;
+            }
+        }
+        Ok(EventPage::from_overfetch(out, limit))
+    }
+
+    async fn claim_for_processing(&self, id: &str, owner: &str, lease: Duration) -> Result<bool, StoreError> {
+        self.update(id, |rec| Ok(rec.claim(owner, lease))).await
+    }
+
+    async fn set_result(&self, id: &str, result: Value, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.complete(result)?)
+        })
+        .await
+    }
+
+    async fn set_failed(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.fail(err)?)
+        })
+        .await
+    }
+
+    async fn set_error_and_mark_received(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.requeue(err)?)
+        })
+        .await
+    }
+
+    async fn retry_later(&self, id: &str, err: EventError, delay: Duration, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.retry_later(err, delay)?)
+        })
+        .await
+    }
+
+    async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.cancel()?)
+        })
+        .await
+    }
+
+    async fn replay(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.update(id, |rec| {
+            rec.expect_version(expected)?;
+            Ok(rec.replay()?)
+        })
+        .await
+    }
+
+    async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        self.bury_if(id, |rec| {
+            let rec = rec.ok_or(StoreError::NotFound)?;
+            rec.expect_version(expected)?;
+            match rec.status {
+                EventStatus::Failed => Ok(Tombstone::of(rec)),
+                status => Err(StoreError::NotDeadLettered { status }),
+            }
+        })
+        .await
+    }
+
+    async fn discard(&self, id: &str, expected: u64) -> Result<(), StoreError> {
+        self.remove_if(id, |rec| Ok(rec.expect_version(Some(expected))?)).await
+    }
+
+    async fn release_expired_leases(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<String>, StoreError> {
         let mut map = self.inner.write().await;
-        let rec = map.get_mut(id).ok_or(StoreError::NotFound)?;
-        rec.last_error = Some(err);
-        rec.status = EventStatus::Received;
-        rec.updated_at = Utc::now();
-        if let Some(n) = self.notifiers.read().await.get(id) {
-            n.notify_waiters();
+        let processing = map.by_status.get(&EventStatus::Processing).into_iter().flatten();
+        let expired: Vec<EventRecord> = processing.filter_map(|(_, id)| map.get(id)).filter(|r| r.lease_expired(cutoff)).cloned().collect();
+        let mut released = Vec::new();
+        for mut rec in expired {
+            rec.release_lease()?;
+            released.push(rec.event.event_id.clone());
+            map.upsert(rec);
         }
-        Ok(())
+        let notifs = self.notifiers.read().await;
+        for id in &released {
+            if let Some(n) = notifs.get(id) {
+                n.notify_waiters();
+            }
+        }
+        Ok(released)
+    }
+
+    async fn evict(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<Vec<String>, StoreError> {
+        let victims = self.evictable(policy, now).await;
+        let ids = self.bury_unchanged(victims).await;
+        self.forget(policy.key_cutoff(now)).await;
+        Ok(ids)
     }
 
-    /// Wait until the named event reaches `desired` status or the timeout elapses.
-    pub async fn wait_for_status(&self, id: &str, desired: EventStatus, timeout: std::time::Duration) -> bool {
+    async fn wait_for_status(&self, id: &str, desired: EventStatus, timeout: std::time::Duration) -> bool {
         use tokio::time::{timeout as ttimeout, Instant};
         let deadline = Instant::now() + timeout;
         loop {
+            // clone the notifier out so the map lock is not held while waiting
+            let notify = self.notifiers.read().await.get(id).cloned();
+            let notified = notify.as_ref().map(|n| n.notified());
+            tokio::pin!(notified);
+            // register interest before checking so a change in between is not missed
+            if let Some(n) = notified.as_mut().as_pin_mut() {
+                n.enable();
+            }
             if let Ok(rec) = self.get(id).await {
                 if rec.status == desired {
                     return true;
@@ -115,12 +416,14 @@ impl MemoryStore {
             }
             let remaining = deadline - now;
             // await the per-event notifier if present, otherwise small sleep
-            if let Some(n) = self.notifiers.read().await.get(id) {
-                let notified = n.notified();
-                let _ = ttimeout(remaining, notified).await;
-            } else {
-                // fallback to a short sleep
-                let _ = ttimeout(remaining, tokio::time::sleep(std::time::Duration::from_millis(50))).await;
+            match notified.as_pin_mut() {
+                Some(n) => {
+                    let _ = ttimeout(remaining, n).await;
+                }
+                None => {
+                    // fallback to a short sleep
+                    let _ = ttimeout(remaining, tokio::time::sleep(std::time::Duration::from_millis(50))).await;
+                }
             }
         }
     }
@@ -130,6 +433,7 @@ impl MemoryStore {
 mod tests {
     use super::*;
     use crate::domain::event::{Event, EventPayload, EventType};
+    use crate::store::conformance::{LEASE, OWNER};
     use chrono::Utc;
     use serde_json::json;
 
@@ -141,15 +445,17 @@ mod tests {
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({"user_id": "u1"})),
+            partition_key: None,
+            priority: None,
         };
-        let (_rec, inserted) = store.insert_if_absent(ev.clone()).await;
+        let (_rec, inserted) = store.insert_if_absent(ev.clone()).await.unwrap();
         assert!(inserted);
         let got = store.get(&ev.event_id).await.unwrap();
         assert_eq!(got.event.event_id, "e1");
         assert_eq!(got.status, EventStatus::Received);
 
         // idempotent insert
-        let (_rec2, inserted2) = store.insert_if_absent(ev).await;
+        let (_rec2, inserted2) = store.insert_if_absent(ev).await.unwrap();
         assert!(!inserted2);
     }
 
@@ -161,16 +467,18 @@ mod tests {
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
         };
-        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await;
-        let claimed = store.claim_for_processing(&ev.event_id).await.unwrap();
+        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await.unwrap();
+        let claimed = store.claim_for_processing(&ev.event_id, OWNER, LEASE).await.unwrap();
         assert!(claimed);
         // second claim should return false because it's Processing now
-        let claimed2 = store.claim_for_processing(&ev.event_id).await.unwrap();
+        let claimed2 = store.claim_for_processing(&ev.event_id, OWNER, LEASE).await.unwrap();
         assert!(!claimed2);
 
         // set result
-        store.set_result(&ev.event_id, json!({"ok": true})).await.unwrap();
+        store.set_result(&ev.event_id, json!({"ok": true}), None).await.unwrap();
         let got = store.get(&ev.event_id).await.unwrap();
         assert_eq!(got.status, EventStatus::Completed);
         assert_eq!(got.result.unwrap()["ok"], json!(true));
@@ -184,12 +492,16 @@ mod tests {
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
         };
-        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await;
-        store.set_failed(&ev.event_id, "boom".to_string()).await.unwrap();
+        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await.unwrap();
+        // only a claimed (Processing) event can fail
+        store.claim_for_processing(&ev.event_id, OWNER, LEASE).await.unwrap();
+        store.set_failed(&ev.event_id, "boom".into(), None).await.unwrap();
         let got = store.get(&ev.event_id).await.unwrap();
         assert_eq!(got.status, EventStatus::Failed);
-        assert_eq!(got.last_error.unwrap(), "boom");
+        assert_eq!(got.last_error.unwrap().details, "boom");
     }
 
     #[tokio::test]
@@ -200,12 +512,44 @@ mod tests {
             event_type: EventType::UserLoginFailed,
             occurred_at: Utc::now(),
             payload: EventPayload(json!({})),
+            partition_key: None,
+            priority: None,
         };
-        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await;
+        let (_rec, _ins) = store.insert_if_absent(ev.clone()).await.unwrap();
+        store.claim_for_processing(&ev.event_id, OWNER, LEASE).await.unwrap();
         // simulate an error and mark received for retry
-        store.set_error_and_mark_received(&ev.event_id, "transient".to_string()).await.unwrap();
+        store.set_error_and_mark_received(&ev.event_id, "transient".into(), None).await.unwrap();
         let got = store.get(&ev.event_id).await.unwrap();
         assert_eq!(got.status, EventStatus::Received);
-        assert_eq!(got.last_error.unwrap(), "transient");
+        assert_eq!(got.last_error.unwrap().details, "transient");
     }
+
+    #[tokio::test]
+    async fn a_replay_between_selecting_and_burying_keeps_its_record() {
+        let store = MemoryStore::new();
+        for id in ["v1", "v2"] {
+            let ev = Event {
+                event_id: id.to_string(),
+                event_type: EventType::UserLoginFailed,
+                occurred_at: Utc::now(),
+                payload: EventPayload(json!({})),
+                partition_key: None,
+                priority: None,
+            };
+            store.insert_if_absent(ev).await.unwrap();
+            store.claim_for_processing(id, OWNER, LEASE).await.unwrap();
+            store.set_failed(id, "boom".into(), None).await.unwrap();
+        }
+        let policy = RetentionPolicy { failed_ttl: Some(std::time::Duration::ZERO), ..Default::default() };
+        let victims = store.evictable(&policy, Utc::now()).await;
+        assert_eq!(victims.len(), 2);
+
+        // the replay lands after evict picked its victims but before it buries them
+        store.replay("v1", None).await.unwrap();
+        assert_eq!(store.bury_unchanged(victims).await, vec!["v2".to_string()]);
+        assert_eq!(store.get("v1").await.unwrap().status, EventStatus::Received);
+        assert!(matches!(store.get("v2").await, Err(StoreError::Evicted { status: EventStatus::Failed })));
+    }
+
+    crate::store::conformance::event_store_conformance!(async { (MemoryStore::new(), ()) });
 }
@@ -1,3 +1,20 @@
+mod error;
+pub mod event_store;
+pub mod file;
 pub mod memory;
+mod query;
+mod retention;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+
+pub use error::StoreError;
+pub use event_store::EventStore;
+pub use file::{FileStore, FileStoreOptions};
 pub use memory::MemoryStore;
+pub use query::{Cursor, EventPage, EventQuery};
+pub use retention::{RetentionPolicy, Tombstone};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
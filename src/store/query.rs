@@ -0,0 +1,126 @@
+use crate::domain::event::{EventRecord, EventType};
+use crate::domain::state::EventStatus;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+/// Filters and page position for `EventStore::list`. Results are ordered by
+/// `(created_at, event_id)`, which never changes for a record, so paging with
+/// `after` is stable while records are added or change status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventQuery {
+    pub status: Option<EventStatus>,
+    pub event_type: Option<EventType>,
+    /// Inclusive lower bound on `created_at`.
+    pub created_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `created_at`.
+    pub created_to: Option<DateTime<Utc>>,
+    /// Inclusive lower bound on the event's `occurred_at`.
+    pub occurred_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on the event's `occurred_at`.
+    pub occurred_to: Option<DateTime<Utc>>,
+    pub min_attempts: Option<u32>,
+    /// Continue after this position, taken from a previous page.
+    pub after: Option<Cursor>,
+    /// Page size; at least one record is always returned if any match.
+    pub limit: usize,
+}
+
+impl EventQuery {
+    /// Whether `rec` passes every filter (the cursor is not considered).
+    pub fn matches(&self, rec: &EventRecord) -> bool {
+        self.status.is_none_or(|s| rec.status == s)
+            && self.event_type.as_ref().is_none_or(|t| rec.event.event_type == *t)
+            && self.created_from.is_none_or(|t| rec.created_at >= t)
+            && self.created_to.is_none_or(|t| rec.created_at < t)
+            && self.occurred_from.is_none_or(|t| rec.event.occurred_at >= t)
+            && self.occurred_to.is_none_or(|t| rec.event.occurred_at < t)
+            && self.min_attempts.is_none_or(|n| rec.attempts >= n)
+    }
+}
+
+/// One page of `EventStore::list` results.
+#[derive(Debug, Clone)]
+pub struct EventPage {
+    pub records: Vec<EventRecord>,
+    /// Where the next page starts; `None` on the last page.
+    pub next_cursor: Option<Cursor>,
+}
+
+impl EventPage {
+    /// Build a page from up to `limit + 1` matches in list order; the extra
+    /// match only signals that another page exists.
+    pub fn from_overfetch(mut records: Vec<EventRecord>, limit: usize) -> Self {
+        let next_cursor = if records.len() > limit {
+            records.truncate(limit);
+            records.last().map(Cursor::of)
+        } else {
+            None
+        };
+        Self { records, next_cursor }
+    }
+}
+
+/// Position in the `(created_at, event_id)` order. Its string form is opaque
+/// to clients and safe to put in a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub event_id: String,
+}
+
+impl Cursor {
+    pub fn of(rec: &EventRecord) -> Self {
+        Self { created_at: rec.created_at, event_id: rec.event.event_id.clone() }
+    }
+
+    /// Hex of `<secs>.<nanos>:<event_id>`; exact to the nanosecond so the
+    /// record it points at compares equal.
+    pub fn encode(&self) -> String {
+        let raw = format!("{}.{:09}:{}", self.created_at.timestamp(), self.created_at.timestamp_subsec_nanos(), self.event_id);
+        raw.bytes().fold(String::with_capacity(raw.len() * 2), |mut out, b| {
+            let _ = write!(out, "{b:02x}");
+            out
+        })
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || "invalid cursor".to_string();
+        if !s.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| s.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let raw = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (ts, event_id) = raw.split_once(':').ok_or_else(invalid)?;
+        let (secs, nanos) = ts.split_once('.').ok_or_else(invalid)?;
+        let created_at = DateTime::from_timestamp(secs.parse().map_err(|_| invalid())?, nanos.parse().map_err(|_| invalid())?).ok_or_else(invalid)?;
+        Ok(Self { created_at, event_id: event_id.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrips_exactly() {
+        let c = Cursor { created_at: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(), event_id: "a:b/c d".to_string() };
+        let s = c.encode();
+        assert!(s.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(s.parse::<Cursor>().unwrap(), c);
+    }
+
+    #[test]
+    fn bad_cursors_are_rejected() {
+        for s in ["", "zz", "abc", "3132"] {
+            assert!(s.parse::<Cursor>().is_err(), "{s:?} parsed");
+        }
+    }
+}
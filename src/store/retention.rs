@@ -0,0 +1,162 @@
+use crate::domain::event::EventRecord;
+use crate::domain::state::EventStatus;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long finished events are kept and how many records a store may hold.
+///
+/// Only finished records (`Completed`, `Failed` or `Cancelled`) are ever
+/// evicted; work that is still pending, scheduled or in flight is never
+/// dropped, even over `max_records`. The default keeps everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Evict `Completed` records this long after they completed, and
+    /// `Cancelled` ones this long after they were cancelled.
+    pub completed_ttl: Option<Duration>,
+    /// Evict `Failed` records this long after they failed.
+    pub failed_ttl: Option<Duration>,
+    /// Once the store holds more records than this, evict the oldest
+    /// finished ones until it does not.
+    pub max_records: Option<usize>,
+    /// How long after ingestion the id of an evicted event is remembered, so
+    /// a redelivery is still recognised as a duplicate. `None` remembers ids
+    /// forever.
+    pub idempotency_window: Option<Duration>,
+}
+
+/// What is left of an evicted record: enough to reject redeliveries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub event_id: String,
+    /// Status the record had when it was evicted.
+    pub status: EventStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Tombstone {
+    pub fn of(rec: &EventRecord) -> Self {
+        Self { event_id: rec.event.event_id.clone(), status: rec.status, created_at: rec.created_at }
+    }
+}
+
+fn before(now: DateTime<Utc>, age: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(age).ok().and_then(|d| now.checked_sub_signed(d)).unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+impl RetentionPolicy {
+    /// Records in `status` last updated at or before the returned instant are
+    /// past their TTL. `None` if records in `status` do not expire.
+    pub fn cutoff(&self, status: EventStatus, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let ttl = match status {
+            EventStatus::Completed | EventStatus::Cancelled => self.completed_ttl,
+            EventStatus::Failed => self.failed_ttl,
+            EventStatus::Received | EventStatus::Processing | EventStatus::Scheduled => None,
+        };
+        ttl.map(|ttl| before(now, ttl))
+    }
+
+    /// Tombstones of events created at or before the returned instant have
+    /// outlived the idempotency window. `None` if they are kept forever.
+    pub fn key_cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.idempotency_window.map(|w| before(now, w))
+    }
+
+    /// Pick the records to evict at `now`: every finished record past its
+    /// TTL, then the oldest remaining finished records while the total is
+    /// above `max_records`.
+    pub fn select<'a>(&self, records: impl IntoIterator<Item = &'a EventRecord>, now: DateTime<Utc>) -> Vec<&'a EventRecord> {
+        let mut total = 0usize;
+        let finished: Vec<_> = records.into_iter().inspect(|_| total += 1).filter(|rec| rec.status.is_terminal()).collect();
+        self.select_finished(finished, total, now)
+    }
+
+    /// Like `select`, for a store that can hand over just its finished
+    /// records (`finished`) and its record count (`total`) without
+    /// scanning the rest.
+    pub fn select_finished<'a>(&self, finished: impl IntoIterator<Item = &'a EventRecord>, total: usize, now: DateTime<Utc>) -> Vec<&'a EventRecord> {
+        let mut expired = Vec::new();
+        let mut kept = Vec::new();
+        for rec in finished {
+            match self.cutoff(rec.status, now) {
+                Some(cutoff) if rec.updated_at <= cutoff => expired.push(rec),
+                _ => kept.push(rec),
+            }
+        }
+        if let Some(max) = self.max_records {
+            let excess = total.saturating_sub(expired.len()).saturating_sub(max);
+            if excess > 0 {
+                kept.sort_by(|a, b| (a.created_at, &a.event.event_id).cmp(&(b.created_at, &b.event.event_id)));
+                expired.extend(kept.into_iter().take(excess));
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::event;
+    use serde_json::json;
+
+    fn finished(id: &str, status: EventStatus, age_secs: i64, now: DateTime<Utc>) -> EventRecord {
+        let mut rec = EventRecord::new(event(id));
+        rec.claim("w", Duration::from_secs(60));
+        match status {
+            EventStatus::Completed => rec.complete(json!({})).unwrap(),
+            EventStatus::Failed => rec.fail("boom".into()).unwrap(),
+            _ => rec.requeue("retry".into()).unwrap(),
+        }
+        rec.created_at = now - chrono::Duration::seconds(age_secs);
+        rec.updated_at = rec.created_at;
+        rec
+    }
+
+    fn ids(recs: Vec<&EventRecord>) -> Vec<&str> {
+        recs.into_iter().map(|r| r.event.event_id.as_str()).collect()
+    }
+
+    #[test]
+    fn ttl_applies_per_status() {
+        let now = Utc::now();
+        let policy = RetentionPolicy { completed_ttl: Some(Duration::from_secs(60)), failed_ttl: Some(Duration::from_secs(600)), ..Default::default() };
+        let recs = [
+            finished("c-old", EventStatus::Completed, 120, now),
+            finished("c-new", EventStatus::Completed, 30, now),
+            finished("f-mid", EventStatus::Failed, 120, now),
+            finished("f-old", EventStatus::Failed, 900, now),
+            finished("r-old", EventStatus::Received, 9_000, now),
+        ];
+        let mut got = ids(policy.select(&recs, now));
+        got.sort();
+        assert_eq!(got, vec!["c-old", "f-old"]);
+    }
+
+    #[test]
+    fn cap_evicts_oldest_finished_first() {
+        let now = Utc::now();
+        let policy = RetentionPolicy { max_records: Some(2), ..Default::default() };
+        let recs = [
+            finished("pending", EventStatus::Received, 500, now),
+            finished("old", EventStatus::Completed, 400, now),
+            finished("mid", EventStatus::Failed, 300, now),
+            finished("new", EventStatus::Completed, 200, now),
+        ];
+        assert_eq!(ids(policy.select(&recs, now)), vec!["old", "mid"]);
+        // the same from the finished records and a count of all of them
+        assert_eq!(ids(policy.select_finished(&recs[1..], recs.len(), now)), vec!["old", "mid"]);
+
+        // pending work is never evicted, even if that leaves the store over the cap
+        let policy = RetentionPolicy { max_records: Some(0), ..Default::default() };
+        assert_eq!(policy.select(&recs, now).len(), 3);
+    }
+
+    #[test]
+    fn default_keeps_everything() {
+        let now = Utc::now();
+        let recs = [finished("a", EventStatus::Completed, 1_000_000, now)];
+        assert!(RetentionPolicy::default().select(&recs, now).is_empty());
+        assert_eq!(RetentionPolicy::default().key_cutoff(now), None);
+    }
+}
@@ -0,0 +1,689 @@
+//! Embedded SQLite backend.
+//!
+//! All state lives in one database file with one row per event, so operators
+//! can inspect it with the `sqlite3` shell. Every operation runs in its own
+//! transaction on a blocking thread; the schema is versioned through
+//! `PRAGMA user_version` and migrated on open.
+
+use crate::domain::error::EventError;
+use crate::domain::event::{lease_expired_error, summarize, REPLAYED, Event, EventPayload, EventRecord, EventType, Priority, StatusChange};
+use crate::domain::state::EventStatus;
+use crate::store::{EventPage, EventQuery, EventStore, RetentionPolicy, StoreError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql, TransactionBehavior};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Schema migrations, applied in order. Entry `i` upgrades the database from
+/// `user_version = i` to `i + 1`; never edit an entry once released.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE events (
+        event_id    TEXT PRIMARY KEY NOT NULL,
+        event_type  TEXT NOT NULL,
+        occurred_at TEXT NOT NULL,
+        payload     TEXT NOT NULL,
+        status      TEXT NOT NULL,
+        attempts    INTEGER NOT NULL DEFAULT 0,
+        last_error  TEXT,
+        result      TEXT,
+        created_at  TEXT NOT NULL,
+        updated_at  TEXT NOT NULL
+    );
+    CREATE INDEX events_status ON events(status);",
+    // 2: claim leases
+    "ALTER TABLE events ADD COLUMN lease_owner TEXT;
+    ALTER TABLE events ADD COLUMN lease_expires_at TEXT;",
+    // 3: record versions for compare-and-set updates
+    "ALTER TABLE events ADD COLUMN version INTEGER NOT NULL DEFAULT 1;",
+    // 4: status history, backfilled with the creation entry
+    "CREATE TABLE event_history (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        event_id    TEXT NOT NULL REFERENCES events(event_id),
+        from_status TEXT,
+        to_status   TEXT NOT NULL,
+        at          TEXT NOT NULL,
+        attempt     INTEGER NOT NULL,
+        worker      TEXT,
+        detail      TEXT
+    );
+    CREATE INDEX event_history_event ON event_history(event_id, id);
+    INSERT INTO event_history (event_id, from_status, to_status, at, attempt)
+        SELECT event_id, NULL, 'Received', created_at, 0 FROM events;",
+    // 5: retention
+    "CREATE TABLE tombstones (
+        event_id   TEXT PRIMARY KEY NOT NULL,
+        status     TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX tombstones_created ON tombstones(created_at);
+    CREATE INDEX events_status_updated ON events(status, updated_at);
+    CREATE INDEX events_created ON events(created_at);",
+    // 6: listing in (created_at, event_id) order, optionally by status
+    "DROP INDEX events_status;
+    CREATE INDEX events_status_created ON events(status, created_at, event_id);
+    CREATE INDEX events_created_id ON events(created_at, event_id);
+    DROP INDEX events_created;",
+    // 7: backoff chosen before the latest retry
+    "ALTER TABLE events ADD COLUMN retry_delay_ms INTEGER;",
+    // 8: earliest time a retry may be claimed
+    "ALTER TABLE events ADD COLUMN not_before TEXT;",
+    // 9: per-key ordering
+    "ALTER TABLE events ADD COLUMN partition_key TEXT;",
+    // 10: queue lane
+    "ALTER TABLE events ADD COLUMN priority TEXT;",
+];
+
+const SELECT_RECORD: &str =
+    "SELECT event_id, event_type, occurred_at, payload, status, attempts, last_error, result, created_at, updated_at, lease_owner, lease_expires_at, version, retry_delay_ms, not_before, partition_key, priority FROM events";
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+/// Errors are stored as JSON. A plain message written before errors were
+/// structured reads back through `EventError::from(String)`.
+impl ToSql for EventError {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let json = serde_json::to_string(self).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(ToSqlOutput::from(json))
+    }
+}
+
+impl FromSql for EventError {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let text = String::column_result(value)?;
+        Ok(serde_json::from_str(&text).unwrap_or_else(|_| text.into()))
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path` and apply pending migrations.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, StoreError> {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            let mut conn = Connection::open(&path)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "synchronous", "FULL")?;
+            conn.busy_timeout(Duration::from_secs(5))?;
+            migrate(&mut conn)?;
+            Ok(conn)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(e.to_string()))??;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Run a read-only query on a blocking thread.
+    async fn read<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, StoreError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|_| StoreError::Backend("sqlite connection poisoned".into()))?;
+            f(&conn)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(e.to_string()))?
+    }
+
+    /// Run `f` inside an immediate transaction on a blocking thread.
+    async fn tx<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Transaction<'_>) -> Result<T, StoreError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| StoreError::Backend("sqlite connection poisoned".into()))?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let out = f(&tx)?;
+            tx.commit()?;
+            Ok(out)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(e.to_string()))?
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let current: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if current > MIGRATIONS.len() {
+        return Err(StoreError::Backend(format!("database schema v{current} is newer than this build (v{})", MIGRATIONS.len())));
+    }
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        tracing::info!(version = i + 1, "applied sqlite migration");
+    }
+    Ok(())
+}
+
+fn row_to_record(row: &Row<'_>) -> rusqlite::Result<EventRecord> {
+    let event_type: String = row.get(1)?;
+    let status = parse_status(4, row.get(4)?)?;
+    let priority: Option<String> = row.get(16)?;
+    Ok(EventRecord {
+        event: Event {
+            event_id: row.get(0)?,
+            event_type: EventType::try_from(event_type.clone()).unwrap_or(EventType::Other(event_type)),
+            occurred_at: row.get(2)?,
+            payload: EventPayload(row.get(3)?),
+            partition_key: row.get(15)?,
+            priority: priority.map(|p| parse_priority(16, p)).transpose()?,
+        },
+        status,
+        attempts: row.get(5)?,
+        last_error: row.get(6)?,
+        result: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+        lease_owner: row.get(10)?,
+        lease_expires_at: row.get(11)?,
+        version: row.get(12)?,
+        history: Vec::new(),
+        retry_delay_ms: row.get(13)?,
+        not_before: row.get(14)?,
+    })
+}
+
+fn parse_status(idx: usize, s: String) -> rusqlite::Result<EventStatus> {
+    s.parse::<EventStatus>().map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, e.into()))
+}
+
+fn parse_priority(idx: usize, s: String) -> rusqlite::Result<Priority> {
+    s.parse::<Priority>().map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, e.into()))
+}
+
+fn load(conn: &Connection, id: &str) -> Result<Option<EventRecord>, StoreError> {
+    let Some(mut rec) = conn.query_row(&format!("{SELECT_RECORD} WHERE event_id = ?1"), params![id], row_to_record).optional()? else {
+        return Ok(None);
+    };
+    rec.history = load_history(conn, id)?;
+    Ok(Some(rec))
+}
+
+fn load_history(conn: &Connection, id: &str) -> Result<Vec<StatusChange>, StoreError> {
+    let mut stmt = conn.prepare_cached("SELECT from_status, to_status, at, attempt, worker, detail FROM event_history WHERE event_id = ?1 ORDER BY id")?;
+    let history = stmt
+        .query_map(params![id], |r| {
+            Ok(StatusChange {
+                from: r.get::<_, Option<String>>(0)?.map(|s| parse_status(0, s)).transpose()?,
+                to: parse_status(1, r.get(1)?)?,
+                at: r.get(2)?,
+                attempt: r.get(3)?,
+                worker: r.get(4)?,
+                detail: r.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(history)
+}
+
+fn load_tombstone(conn: &Connection, id: &str) -> Result<Option<EventStatus>, StoreError> {
+    Ok(conn.query_row("SELECT status FROM tombstones WHERE event_id = ?1", params![id], |r| parse_status(0, r.get(0)?)).optional()?)
+}
+
+/// Delete a record and its history, keeping its id as a tombstone.
+fn bury(tx: &rusqlite::Transaction<'_>, id: &str) -> Result<(), StoreError> {
+    tx.execute("INSERT OR REPLACE INTO tombstones (event_id, status, created_at) SELECT event_id, status, created_at FROM events WHERE event_id = ?1", params![id])?;
+    tx.execute("DELETE FROM event_history WHERE event_id = ?1", params![id])?;
+    tx.execute("DELETE FROM events WHERE event_id = ?1", params![id])?;
+    Ok(())
+}
+
+fn append_history(tx: &rusqlite::Transaction<'_>, id: &str, change: &StatusChange) -> Result<(), StoreError> {
+    tx.execute(
+        "INSERT INTO event_history (event_id, from_status, to_status, at, attempt, worker, detail) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![id, change.from.map(|s| s.as_str()), change.to.as_str(), change.at, change.attempt, change.worker, change.detail],
+    )?;
+    Ok(())
+}
+
+/// The columns a mutation needs to validate itself and write history.
+struct Current {
+    status: EventStatus,
+    version: u64,
+    attempts: u32,
+    lease_owner: Option<String>,
+}
+
+fn load_current(conn: &Connection, id: &str) -> Result<Option<Current>, StoreError> {
+    Ok(conn
+        .query_row("SELECT status, version, attempts, lease_owner FROM events WHERE event_id = ?1", params![id], |r| {
+            Ok(Current { status: parse_status(0, r.get(0)?)?, version: r.get(1)?, attempts: r.get(2)?, lease_owner: r.get(3)? })
+        })
+        .optional()?)
+}
+
+/// Move a record to `to` if `EventStatus::can_transition` allows it (and it
+/// is still at the `expected` version, if given), also setting `column` to
+/// `value` and appending `detail` to the history. A miss is reported as
+/// `NotFound`, `VersionConflict` or `InvalidTransition`, in that order.
+fn transition(tx: &rusqlite::Transaction<'_>, id: &str, to: EventStatus, column: &str, value: &dyn ToSql, expected: Option<u64>, detail: String) -> Result<(), StoreError> {
+    let cur = load_current(tx, id)?.ok_or(StoreError::NotFound)?;
+    if let Some(expected) = expected.filter(|e| *e != cur.version) {
+        return Err(StoreError::VersionConflict { expected, actual: cur.version });
+    }
+    if !cur.status.can_transition(to) {
+        return Err(StoreError::InvalidTransition { from: cur.status, to });
+    }
+    let now = Utc::now();
+    tx.execute(
+        &format!(
+            "UPDATE events SET status = ?2, {column} = ?3, lease_owner = NULL, lease_expires_at = NULL, not_before = NULL, updated_at = ?4, version = version + 1
+             WHERE event_id = ?1"
+        ),
+        params![id, to.as_str(), value, now],
+    )?;
+    let change = StatusChange { from: Some(cur.status), to, at: now, attempt: cur.attempts, worker: cur.lease_owner, detail: Some(summarize(detail)) };
+    append_history(tx, id, &change)
+}
+
+#[async_trait]
+impl EventStore for SqliteStore {
+    async fn insert_if_absent_at(&self, event: Event, process_after: Option<DateTime<Utc>>) -> Result<(EventRecord, bool), StoreError> {
+        self.tx(move |tx| {
+            if let Some(status) = load_tombstone(tx, &event.event_id)? {
+                return Err(StoreError::Evicted { status });
+            }
+            let rec = match process_after {
+                Some(at) => EventRecord::scheduled(event, at),
+                None => EventRecord::new(event),
+            };
+            // the primary key on event_id makes the insert a no-op for duplicates
+            let inserted = tx.execute(
+                "INSERT INTO events (event_id, event_type, occurred_at, payload, status, attempts, last_error, result, created_at, updated_at, version, not_before, partition_key, priority)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT(event_id) DO NOTHING",
+                params![
+                    rec.event.event_id,
+                    String::from(rec.event.event_type.clone()),
+                    rec.event.occurred_at,
+                    rec.event.payload.0,
+                    rec.status.as_str(),
+                    rec.attempts,
+                    rec.last_error,
+                    rec.result,
+                    rec.created_at,
+                    rec.updated_at,
+                    rec.version,
+                    rec.not_before,
+                    rec.event.partition_key,
+                    rec.event.priority.map(Priority::as_str),
+                ],
+            )?;
+            if inserted == 1 {
+                for change in &rec.history {
+                    append_history(tx, &rec.event.event_id, change)?;
+                }
+                Ok((rec, true))
+            } else {
+                let existing = load(tx, &rec.event.event_id)?.ok_or(StoreError::NotFound)?;
+                Ok((existing, false))
+            }
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<EventRecord, StoreError> {
+        let id = id.to_string();
+        self.read(move |conn| match load(conn, &id)? {
+            Some(rec) => Ok(rec),
+            None => match load_tombstone(conn, &id)? {
+                Some(status) => Err(StoreError::Evicted { status }),
+                None => Err(StoreError::NotFound),
+            },
+        })
+        .await
+    }
+
+    async fn ids_by_status(&self, status: EventStatus) -> Result<Vec<String>, StoreError> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare("SELECT event_id FROM events WHERE status = ?1 ORDER BY created_at")?;
+            let ids = stmt.query_map(params![status.as_str()], |r| r.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn list(&self, query: &EventQuery) -> Result<EventPage, StoreError> {
+        let query = query.clone();
+        let limit = query.limit.max(1);
+        self.read(move |conn| {
+            let mut sql = format!("{SELECT_RECORD} WHERE 1 = 1");
+            let mut args: Vec<Box<dyn ToSql>> = Vec::new();
+            // every `?` in a clause binds that clause's argument
+            let mut filter = |clause: &str, arg: Box<dyn ToSql>| {
+                args.push(arg);
+                sql.push_str(&clause.replace('?', &format!("?{}", args.len())));
+            };
+            if let Some(status) = query.status {
+                filter(" AND status = ?", Box::new(status.as_str()));
+            }
+            if let Some(t) = query.event_type.clone() {
+                filter(" AND event_type = ?", Box::new(String::from(t)));
+            }
+            if let Some(t) = query.created_from {
+                filter(" AND created_at >= ?", Box::new(t));
+            }
+            if let Some(t) = query.created_to {
+                filter(" AND created_at < ?", Box::new(t));
+            }
+            if let Some(t) = query.occurred_from {
+                filter(" AND occurred_at >= ?", Box::new(t));
+            }
+            if let Some(t) = query.occurred_to {
+                filter(" AND occurred_at < ?", Box::new(t));
+            }
+            if let Some(n) = query.min_attempts {
+                filter(" AND attempts >= ?", Box::new(n));
+            }
+            if let Some(c) = query.after.clone() {
+                filter(" AND (created_at > ? OR (created_at = ?", Box::new(c.created_at));
+                filter(" AND event_id > ?))", Box::new(c.event_id));
+            }
+            filter(" ORDER BY created_at, event_id LIMIT ?", Box::new(limit + 1));
+            let mut stmt = conn.prepare(&sql)?;
+            let mut records = stmt.query_map(rusqlite::params_from_iter(args.iter()), row_to_record)?.collect::<rusqlite::Result<Vec<_>>>()?;
+            for rec in &mut records {
+                rec.history = load_history(conn, &rec.event.event_id)?;
+            }
+            Ok(EventPage::from_overfetch(records, limit))
+        })
+        .await
+    }
+
+    async fn claim_for_processing(&self, id: &str, owner: &str, lease: Duration) -> Result<bool, StoreError> {
+        let id = id.to_string();
+        let owner = owner.to_string();
+        self.tx(move |tx| {
+            let cur = load_current(tx, &id)?.ok_or(StoreError::NotFound)?;
+            let now = Utc::now();
+            let expires = chrono::Duration::from_std(lease).ok().and_then(|d| now.checked_add_signed(d)).unwrap_or(now + chrono::Duration::days(36_500));
+            let attempt: Option<u32> = tx
+                .query_row(
+                    "UPDATE events SET status = ?2, attempts = attempts + 1, lease_owner = ?3, lease_expires_at = ?4, not_before = NULL, updated_at = ?5, version = version + 1
+                     WHERE event_id = ?1 AND status IN (?6, ?7) AND (not_before IS NULL OR not_before <= ?5)
+                     RETURNING attempts",
+                    params![id, EventStatus::Processing.as_str(), owner, expires, now, EventStatus::Received.as_str(), EventStatus::Scheduled.as_str()],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            let Some(attempt) = attempt else {
+                return Ok(false);
+            };
+            let change = StatusChange { from: Some(cur.status), to: EventStatus::Processing, at: now, attempt, worker: Some(owner), detail: None };
+            append_history(tx, &id, &change)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn set_result(&self, id: &str, result: Value, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| transition(tx, &id, EventStatus::Completed, "result", &result, expected, result.to_string())).await
+    }
+
+    async fn set_failed(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| transition(tx, &id, EventStatus::Failed, "last_error", &err, expected, err.to_string())).await
+    }
+
+    async fn set_error_and_mark_received(&self, id: &str, err: EventError, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| transition(tx, &id, EventStatus::Received, "last_error", &err, expected, err.to_string())).await
+    }
+
+    async fn retry_later(&self, id: &str, err: EventError, delay: Duration, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        let delay_ms: u64 = delay.as_millis().try_into().unwrap_or(u64::MAX);
+        self.tx(move |tx| {
+            transition(tx, &id, EventStatus::Received, "last_error", &err, expected, err.to_string())?;
+            let now = Utc::now();
+            let not_before = chrono::Duration::from_std(delay).ok().and_then(|d| now.checked_add_signed(d)).unwrap_or(now + chrono::Duration::days(36_500));
+            tx.execute("UPDATE events SET retry_delay_ms = ?2, not_before = ?3 WHERE event_id = ?1", params![id, delay_ms, not_before])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn cancel(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| transition(tx, &id, EventStatus::Cancelled, "last_error", &None::<EventError>, expected, "cancelled before it came due".to_string())).await
+    }
+
+    async fn replay(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| {
+            let cur = load_current(tx, &id)?.ok_or(StoreError::NotFound)?;
+            if let Some(expected) = expected.filter(|e| *e != cur.version) {
+                return Err(StoreError::VersionConflict { expected, actual: cur.version });
+            }
+            if cur.status != EventStatus::Failed {
+                return Err(StoreError::InvalidTransition { from: cur.status, to: EventStatus::Received });
+            }
+            let now = Utc::now();
+            tx.execute(
+                "UPDATE events SET status = ?2, attempts = 0, last_error = NULL, result = NULL, retry_delay_ms = NULL, updated_at = ?3, version = version + 1
+                 WHERE event_id = ?1",
+                params![id, EventStatus::Received.as_str(), now],
+            )?;
+            let change = StatusChange { from: Some(cur.status), to: EventStatus::Received, at: now, attempt: 0, worker: None, detail: Some(REPLAYED.to_string()) };
+            append_history(tx, &id, &change)
+        })
+        .await
+    }
+
+    async fn purge(&self, id: &str, expected: Option<u64>) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| {
+            let cur = load_current(tx, &id)?.ok_or(StoreError::NotFound)?;
+            if let Some(expected) = expected.filter(|e| *e != cur.version) {
+                return Err(StoreError::VersionConflict { expected, actual: cur.version });
+            }
+            if cur.status != EventStatus::Failed {
+                return Err(StoreError::NotDeadLettered { status: cur.status });
+            }
+            bury(tx, &id)
+        })
+        .await
+    }
+
+    async fn discard(&self, id: &str, expected: u64) -> Result<(), StoreError> {
+        let id = id.to_string();
+        self.tx(move |tx| {
+            let cur = load_current(tx, &id)?.ok_or(StoreError::NotFound)?;
+            if cur.version != expected {
+                return Err(StoreError::VersionConflict { expected, actual: cur.version });
+            }
+            tx.execute("DELETE FROM event_history WHERE event_id = ?1", params![id])?;
+            tx.execute("DELETE FROM events WHERE event_id = ?1", params![id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_expired_leases(&self, cutoff: Option<DateTime<Utc>>) -> Result<Vec<String>, StoreError> {
+        self.tx(move |tx| {
+            let mut stmt = tx.prepare(
+                "SELECT event_id, attempts, lease_owner FROM events
+                 WHERE status = ?1 AND (?2 IS NULL OR lease_expires_at IS NULL OR lease_expires_at <= ?2)",
+            )?;
+            let expired = stmt
+                .query_map(params![EventStatus::Processing.as_str(), cutoff], |r| Ok((r.get::<_, String>(0)?, r.get::<_, u32>(1)?, r.get::<_, Option<String>>(2)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let now = Utc::now();
+            let mut released = Vec::with_capacity(expired.len());
+            for (id, attempt, owner) in expired {
+                let err = lease_expired_error(owner.as_deref());
+                tx.execute(
+                    "UPDATE events SET status = ?2, last_error = ?3, lease_owner = NULL, lease_expires_at = NULL, updated_at = ?4, version = version + 1
+                     WHERE event_id = ?1",
+                    params![id, EventStatus::Received.as_str(), err, now],
+                )?;
+                let change = StatusChange { from: Some(EventStatus::Processing), to: EventStatus::Received, at: now, attempt, worker: owner, detail: Some(summarize(err.to_string())) };
+                append_history(tx, &id, &change)?;
+                released.push(id);
+            }
+            Ok(released)
+        })
+        .await
+    }
+
+    async fn evict(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> Result<Vec<String>, StoreError> {
+        let policy = policy.clone();
+        self.tx(move |tx| {
+            let mut evicted = Vec::new();
+            for status in [EventStatus::Completed, EventStatus::Failed, EventStatus::Cancelled] {
+                let Some(cutoff) = policy.cutoff(status, now) else { continue };
+                let mut stmt = tx.prepare("SELECT event_id FROM events WHERE status = ?1 AND updated_at <= ?2")?;
+                let ids = stmt.query_map(params![status.as_str(), cutoff], |r| r.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+                evicted.extend(ids);
+            }
+            for id in &evicted {
+                bury(tx, id)?;
+            }
+            if let Some(max) = policy.max_records {
+                let total: usize = tx.query_row("SELECT COUNT(*) FROM events", [], |r| r.get(0))?;
+                let excess = total.saturating_sub(max);
+                if excess > 0 {
+                    let mut stmt = tx.prepare("SELECT event_id FROM events WHERE status IN (?1, ?2, ?3) ORDER BY created_at, event_id LIMIT ?4")?;
+                    let ids = stmt
+                        .query_map(params![EventStatus::Completed.as_str(), EventStatus::Failed.as_str(), EventStatus::Cancelled.as_str(), excess], |r| r.get(0))?
+                        .collect::<rusqlite::Result<Vec<String>>>()?;
+                    for id in &ids {
+                        bury(tx, id)?;
+                    }
+                    evicted.extend(ids);
+                }
+            }
+            if let Some(cutoff) = policy.key_cutoff(now) {
+                tx.execute("DELETE FROM tombstones WHERE created_at <= ?1", params![cutoff])?;
+            }
+            Ok(evicted)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::{event, LEASE, OWNER};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn state_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        {
+            let store = SqliteStore::open(&path).await.unwrap();
+            store.insert_if_absent(event("q1")).await.unwrap();
+            store.claim_for_processing("q1", OWNER, LEASE).await.unwrap();
+            store.set_result("q1", json!({"ok": true}), None).await.unwrap();
+        }
+        let store = SqliteStore::open(&path).await.unwrap();
+        let rec = store.get("q1").await.unwrap();
+        assert_eq!(rec.status, EventStatus::Completed);
+        assert_eq!(rec.attempts, 1);
+        assert_eq!(rec.result, Some(json!({"ok": true})));
+        let (_rec, inserted) = store.insert_if_absent(event("q1")).await.unwrap();
+        assert!(!inserted);
+    }
+
+    #[tokio::test]
+    async fn scheduled_retry_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        let not_before = {
+            let store = SqliteStore::open(&path).await.unwrap();
+            store.insert_if_absent(event("q2")).await.unwrap();
+            store.claim_for_processing("q2", OWNER, LEASE).await.unwrap();
+            store.retry_later("q2", "slow".into(), Duration::from_secs(60), None).await.unwrap();
+            store.get("q2").await.unwrap().not_before.unwrap()
+        };
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert_eq!(store.get("q2").await.unwrap().not_before, Some(not_before));
+        assert!(!store.claim_for_processing("q2", OWNER, LEASE).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn open_rejects_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        }
+        assert!(matches!(SqliteStore::open(&path).await, Err(StoreError::Backend(_))));
+    }
+
+    #[tokio::test]
+    async fn history_migration_backfills_creation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        {
+            // a database from before history was tracked
+            let conn = Connection::open(&path).unwrap();
+            for sql in &MIGRATIONS[..3] {
+                conn.execute_batch(sql).unwrap();
+            }
+            conn.pragma_update(None, "user_version", 3).unwrap();
+            conn.execute(
+                "INSERT INTO events (event_id, event_type, occurred_at, payload, status, created_at, updated_at)
+                 VALUES ('old', 'user.login_failed', ?1, '{}', 'Received', ?1, ?1)",
+                params![Utc::now()],
+            )
+            .unwrap();
+        }
+        let store = SqliteStore::open(&path).await.unwrap();
+        store.claim_for_processing("old", OWNER, LEASE).await.unwrap();
+        let history = store.get("old").await.unwrap().history;
+        assert_eq!(history.iter().map(|c| (c.from, c.to)).collect::<Vec<_>>(), vec![(None, EventStatus::Received), (Some(EventStatus::Received), EventStatus::Processing)]);
+    }
+
+    #[tokio::test]
+    async fn plain_text_errors_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        drop(SqliteStore::open(&path).await.unwrap());
+        {
+            // a row written before errors were stored as JSON
+            let conn = Connection::open(&path).unwrap();
+            conn.execute(
+                "INSERT INTO events (event_id, event_type, occurred_at, payload, status, attempts, last_error, created_at, updated_at)
+                 VALUES ('old', 'user.login_failed', ?1, '{}', 'Failed', 5, 'connection reset', ?1, ?1)",
+                params![Utc::now()],
+            )
+            .unwrap();
+        }
+        let store = SqliteStore::open(&path).await.unwrap();
+        let err = store.get("old").await.unwrap().last_error.unwrap();
+        assert_eq!((err.code.as_str(), err.details.as_str()), ("error", "connection reset"));
+    }
+
+    crate::store::conformance::event_store_conformance!(async {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(dir.path().join("events.db")).await.unwrap();
+        (store, dir)
+    });
+}
@@ -1,12 +1,34 @@
-use prometheus::{Encoder, Gauge, Histogram, HistogramOpts, IntCounter, Opts, Registry, TextEncoder};
+use prometheus::{Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
 
 #[derive(Clone)]
 pub struct Telemetry {
     pub events_ingested: IntCounter,
     pub events_deduped: IntCounter,
+    /// Events turned away at ingest because the work queue was full.
+    pub events_shed: IntCounter,
     pub events_processed: IntCounter,
     pub events_failed: IntCounter,
+    pub leases_expired: IntCounter,
+    pub transitions_rejected: IntCounter,
+    pub events_evicted: IntCounter,
+    pub handler_timeouts: IntCounter,
     pub queue_depth: Gauge,
+    pub events_scheduled: Gauge,
+    /// Ready ids per queue lane, labelled `lane`.
+    pub lane_depth: GaugeVec,
+    /// Time ids spent ready in their queue lane before a worker took them.
+    pub lane_wait: HistogramVec,
+    /// Deliveries out per bulkhead, labelled `group`.
+    pub bulkhead_in_flight: GaugeVec,
+    /// Ids that stayed queued because their bulkhead was full.
+    pub bulkhead_limit_hits: IntCounterVec,
+    /// Circuit breaker state per handler, labelled `handler`: 0 closed, 1
+    /// half-open, 2 open.
+    pub breaker_state: GaugeVec,
+    pub breaker_opened: IntCounterVec,
+    /// Events held back because their handler's breaker was open.
+    pub breaker_rejections: IntCounterVec,
+    pub dlq_depth: Gauge,
     pub processing_hist: Histogram,
     pub registry: Registry,
 }
@@ -17,19 +39,47 @@ impl Telemetry {
 
         let events_ingested = IntCounter::with_opts(Opts::new("events_ingested_total", "Total ingested events")).unwrap();
         let events_deduped = IntCounter::with_opts(Opts::new("events_deduped_total", "Total deduped events")).unwrap();
+        let events_shed = IntCounter::with_opts(Opts::new("events_shed_total", "Total events turned away because the work queue was full")).unwrap();
         let events_processed = IntCounter::with_opts(Opts::new("events_processed_total", "Total processed events")).unwrap();
         let events_failed = IntCounter::with_opts(Opts::new("events_failed_total", "Total failed events")).unwrap();
+        let leases_expired = IntCounter::with_opts(Opts::new("leases_expired_total", "Total claims released after their lease expired")).unwrap();
+        let transitions_rejected = IntCounter::with_opts(Opts::new("store_transitions_rejected_total", "Total store mutations rejected as stale (illegal transition or version conflict)")).unwrap();
+        let events_evicted = IntCounter::with_opts(Opts::new("events_evicted_total", "Total finished events removed by the retention policy")).unwrap();
+        let handler_timeouts = IntCounter::with_opts(Opts::new("handler_timeouts_total", "Total handler attempts abandoned after exceeding their timeout")).unwrap();
         let queue_depth = Gauge::with_opts(Opts::new("queue_depth", "Queue depth")) .unwrap();
+        let events_scheduled = Gauge::with_opts(Opts::new("events_scheduled", "Events waiting for a retry delay or a later processing time")).unwrap();
+        let lane_depth = GaugeVec::new(Opts::new("queue_lane_depth", "Event ids ready in each queue lane"), &["lane"]).unwrap();
+        let lane_wait = HistogramVec::new(HistogramOpts::new("queue_lane_wait_seconds", "Time event ids waited in their queue lane before being dequeued"), &["lane"]).unwrap();
+        let bulkhead_in_flight = GaugeVec::new(Opts::new("bulkhead_in_flight", "Events being processed per concurrency group"), &["group"]).unwrap();
+        let bulkhead_limit_hits = IntCounterVec::new(Opts::new("bulkhead_limit_hits_total", "Total events held in the queue because their concurrency group was at its limit"), &["group"]).unwrap();
+        let breaker_state = GaugeVec::new(Opts::new("circuit_breaker_state", "Circuit breaker state per handler (0 closed, 1 half-open, 2 open)"), &["handler"]).unwrap();
+        let breaker_opened = IntCounterVec::new(Opts::new("circuit_breaker_opened_total", "Total times a handler's circuit breaker opened"), &["handler"]).unwrap();
+        let breaker_rejections = IntCounterVec::new(Opts::new("circuit_breaker_rejections_total", "Total events paused because their handler's circuit breaker was open"), &["handler"]).unwrap();
+        let dlq_depth = Gauge::with_opts(Opts::new("dlq_depth", "Events in the dead-letter queue")).unwrap();
         let processing_hist = Histogram::with_opts(HistogramOpts::new("event_processing_seconds", "Event processing duration")) .unwrap();
 
         registry.register(Box::new(events_ingested.clone())).ok();
         registry.register(Box::new(events_deduped.clone())).ok();
+        registry.register(Box::new(events_shed.clone())).ok();
         registry.register(Box::new(events_processed.clone())).ok();
         registry.register(Box::new(events_failed.clone())).ok();
+        registry.register(Box::new(leases_expired.clone())).ok();
+        registry.register(Box::new(transitions_rejected.clone())).ok();
+        registry.register(Box::new(events_evicted.clone())).ok();
+        registry.register(Box::new(handler_timeouts.clone())).ok();
         registry.register(Box::new(queue_depth.clone())).ok();
+        registry.register(Box::new(events_scheduled.clone())).ok();
+        registry.register(Box::new(lane_depth.clone())).ok();
+        registry.register(Box::new(lane_wait.clone())).ok();
+        registry.register(Box::new(bulkhead_in_flight.clone())).ok();
+        registry.register(Box::new(bulkhead_limit_hits.clone())).ok();
+        registry.register(Box::new(breaker_state.clone())).ok();
+        registry.register(Box::new(breaker_opened.clone())).ok();
+        registry.register(Box::new(breaker_rejections.clone())).ok();
+        registry.register(Box::new(dlq_depth.clone())).ok();
         registry.register(Box::new(processing_hist.clone())).ok();
 
-        Telemetry { events_ingested, events_deduped, events_processed, events_failed, queue_depth, processing_hist, registry }
+        Telemetry { events_ingested, events_deduped, events_shed, events_processed, events_failed, leases_expired, transitions_rejected, events_evicted, handler_timeouts, queue_depth, events_scheduled, lane_depth, lane_wait, bulkhead_in_flight, bulkhead_limit_hits, breaker_state, breaker_opened, breaker_rejections, dlq_depth, processing_hist, registry }
     }
 
     /// Gather metrics in Prometheus text format.
@@ -42,6 +92,12 @@ impl Telemetry {
     }
 }
 
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
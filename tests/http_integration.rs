@@ -1,30 +1,30 @@
 use serde_json::json;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
 
 use event_processing_service::domain::event::Event;
-use event_processing_service::service::IngestService;
-use event_processing_service::store::MemoryStore;
+use event_processing_service::service::{DeadLetterQueue, IngestService};
+use event_processing_service::queue::MemoryQueue;
+use event_processing_service::store::{EventStore, MemoryStore};
 use event_processing_service::telemetry::Telemetry;
 use event_processing_service::http::routes::build_router;
-use event_processing_service::service::run_processor_pool;
+use event_processing_service::service::{run_processor_pool, HandlerRegistry, RetryPolicy};
 
 #[tokio::test]
 async fn http_end_to_end() -> anyhow::Result<()> {
     // prepare components
     let store = MemoryStore::new();
     let telemetry = Telemetry::new();
-    let (tx, rx) = mpsc::channel::<String>(32);
-    let ingest = IngestService::new(store.clone(), tx.clone(), telemetry.clone());
+    let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+    let ingest = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
+    let dlq = DeadLetterQueue::new(store.clone(), queue.clone(), telemetry.clone());
 
     // start processor pool with a handler that succeeds
     let handler = |_ev: Event| async move { Ok(serde_json::json!({"ok": true})) };
-    let shared_rx = Arc::new(Mutex::new(rx));
-    run_processor_pool(store.clone(), shared_rx, tx.clone(), 2, 3, telemetry.clone(), handler);
+    run_processor_pool(store.clone(), queue.clone(), 2, RetryPolicy { max_attempts: 3, ..Default::default() }.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
 
     // give the processor a moment to pick up the message
     // build http app
-    let state = Arc::new(event_processing_service::http::handlers::HttpState { ingest, store: store.clone(), telemetry: telemetry.clone() });
+    let state = Arc::new(event_processing_service::http::handlers::HttpState { ingest, dlq, store: store.clone(), telemetry: telemetry.clone(), breakers: Default::default() });
     let _app = build_router(state.clone());
 
     // Instead of starting a full HTTP server (which can surface crate-version
@@ -38,7 +38,7 @@ async fn http_end_to_end() -> anyhow::Result<()> {
     use axum::body::to_bytes;
 
     let occurred_at = chrono::Utc::now();
-    let ev_in = EventIn { event_id: "httptest1".to_string(), event_type: "user.login_failed".to_string(), occurred_at, payload: json!({"user": "u1"}) };
+    let ev_in = EventIn { event_id: "httptest1".to_string(), event_type: "user.login_failed".to_string(), occurred_at, payload: json!({"user": "u1"}), process_after: None, delay_ms: None, partition_key: None, priority: None };
 
     // call POST handler
     let resp = post_events(AxState(state.clone()), AxJson(ev_in.clone())).await.into_response();
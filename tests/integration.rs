@@ -1,33 +1,31 @@
 use event_processing_service::service::IngestService;
-use event_processing_service::service::run_processor_pool;
-use event_processing_service::store::MemoryStore;
+use event_processing_service::service::{run_processor_pool, HandlerRegistry, RetryPolicy};
+use event_processing_service::queue::MemoryQueue;
+use event_processing_service::store::{EventStore, MemoryStore};
 use event_processing_service::telemetry::Telemetry;
+use event_processing_service::domain::error::HandlerError;
 use event_processing_service::domain::event::{Event, EventPayload, EventType};
 use chrono::Utc;
 use serde_json::json;
-use std::sync::Arc;
-use tokio::sync::mpsc;
-use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn integration_ingest_and_process() {
     let store = MemoryStore::new();
     let telemetry = Telemetry::new();
-    let (tx, rx) = mpsc::channel::<String>(32);
-    let ingest = IngestService::new(store.clone(), tx.clone(), telemetry.clone());
+    let queue = MemoryQueue::new().with_depth_gauge(telemetry.queue_depth.clone());
+    let ingest = IngestService::new(store.clone(), queue.clone(), telemetry.clone());
 
     // handler: succeed unless payload contains {"fail": true}
     let handler = |ev: Event| async move {
         if ev.payload.0.get("fail").and_then(|b| b.as_bool()).unwrap_or(false) {
-            Err("simulated".to_string())
+            Err(HandlerError::retryable("simulated", "simulated failure"))
         } else {
             Ok(json!({"ok": true}))
         }
     };
 
-    let shared_rx = Arc::new(Mutex::new(rx));
-    run_processor_pool(store.clone(), shared_rx, tx.clone(), 2, 3, telemetry.clone(), handler);
+    run_processor_pool(store.clone(), queue.clone(), 2, RetryPolicy { max_attempts: 3, ..Default::default() }.into(), telemetry.clone(), HandlerRegistry::new().on("*", handler));
 
     // ingest an event
     let ev = Event {
@@ -35,12 +33,14 @@ async fn integration_ingest_and_process() {
         event_type: EventType::UserLoginFailed,
         occurred_at: Utc::now(),
         payload: EventPayload(json!({ "user": "u1" })),
+        partition_key: None,
+        priority: None,
     };
-    let (_rec, inserted) = ingest.ingest(ev.clone()).await;
+    let (_rec, inserted) = ingest.ingest(ev.clone()).await.unwrap();
     assert!(inserted);
 
     // ingest duplicate
-    let (_rec2, inserted2) = ingest.ingest(ev.clone()).await;
+    let (_rec2, inserted2) = ingest.ingest(ev.clone()).await.unwrap();
     assert!(!inserted2);
 
     // wait for processing
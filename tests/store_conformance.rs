@@ -0,0 +1,7 @@
+//! Runs the shared `EventStore` suite the way a backend outside this crate
+//! would. Needs `--features conformance`.
+
+use event_processing_service::event_store_conformance;
+use event_processing_service::store::MemoryStore;
+
+event_store_conformance!(async { (MemoryStore::new(), ()) });